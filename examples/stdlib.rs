@@ -6,47 +6,28 @@ pub fn main() {
     let ntp_server = ntp_server_arg.unwrap_or_else(|| DEFAULT_NTP_SERVER.to_string());
     println!("Using NTP server: {}", ntp_server);
 
-    // Lookup the IP address of the NTP server using dns_lookup:
-    let ips = match dns_lookup::lookup_host(&ntp_server) {
-        Ok(ips) => ips,
+    // Look up the addresses of the NTP server:
+    let addrs = match barentp::dns::resolve(&ntp_server) {
+        Ok(addrs) => addrs,
         Err(err) => {
             eprintln!("Failed to lookup host");
             eprintln!("{}", err);
             std::process::exit(1);
         }
     };
-    if ips.is_empty() {
-        eprintln!("No IP addresses found for {}", ntp_server);
-        std::process::exit(1);
-    }
-    let ip = ips[0];
-    println!("Using NTP server IP address: {}", ip);
-
-    let addr = std::net::SocketAddr::new(ip, 123);
+    let addr = addrs[0];
     println!("Using NTP server socket address: {}", addr);
 
-    // Create a UDP socket, bind it to an available IP and port, then connect it to the NTP server:
-    let Ok(socket) = std::net::UdpSocket::bind("0.0.0.0:0") else {
-        eprintln!("Failed to create UDP socket");
-        std::process::exit(1);
+    // Create a UDP socket of the right address family with a timeout, then connect it to the NTP
+    // server:
+    let socket = match barentp::dns::connect(addr, std::time::Duration::from_secs(5)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Failed to connect UDP socket to {}", addr);
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
     };
-    if let Err(err) = socket.connect(addr) {
-        eprintln!("Failed to connect UTP socket to {}", addr);
-        eprintln!("{}", err);
-        std::process::exit(1);
-    }
-
-    if let Err(err) = socket.set_read_timeout(Some(std::time::Duration::from_secs(5))) {
-        eprintln!("Failed to set socket read timeout");
-        eprintln!("{}", err);
-        std::process::exit(1);
-    }
-
-    if let Err(err) = socket.set_write_timeout(Some(std::time::Duration::from_secs(5))) {
-        eprintln!("Failed to set socket write timeout");
-        eprintln!("{}", err);
-        std::process::exit(1);
-    }
 
     // Get a timestamp from the NTP server:
     println!("Fetching timestamp from NTP server...");
@@ -0,0 +1,233 @@
+//! Long-running association with a single server.
+//!
+//! An [`Association`] keeps the state needed to poll a server repeatedly: a
+//! [`ClockFilter`](crate::filter::ClockFilter) over its samples, the current poll interval and
+//! whether the server has asked to be left alone with a Kiss-o'-Death packet. It does not perform
+//! any I/O itself, it is driven by [`sntp_poll`](crate::sntp_poll) from a blocking loop or by
+//! [`sntp_poll`](crate::nonblocking::sntp_poll) from an async task:
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example(socket: std::net::UdpSocket) {
+//! use barentp::association::{Association, AssociationConfig};
+//! use barentp::{NtpClock, SystemClock};
+//!
+//! let clock = SystemClock;
+//! let mut association = Association::new(AssociationConfig::default(), clock.precision());
+//! while let Some(wait) = association.time_until_poll(clock.now()) {
+//!     std::thread::sleep(wait);
+//!     let _ = barentp::sntp_poll(&mut association, &socket, &clock);
+//!     if let Some(estimate) = association.estimate() {
+//!         println!("offset: {}", estimate.offset);
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::{
+    discipline::{PollController, POLL_GATE},
+    error::{Error, SntpProtocolError},
+    filter::{ClockFilter, FilterOutput},
+    protocol::{KissCode, NtpDuration, Timestamp},
+    sample::{Exchange, Reply, SntpSample},
+    select::Candidate,
+};
+
+/// Configuration for an [`Association`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssociationConfig {
+    /// Minimum poll exponent. Defaults to 6 (64 seconds).
+    pub min_poll: i8,
+    /// Maximum poll exponent. Defaults to 10 (1024 seconds).
+    pub max_poll: i8,
+    /// Ask the server to reply in interleaved mode, in which each reply carries the precise time
+    /// the server sent its previous reply instead of the time it started sending this one.
+    /// Servers that do not support interleaved mode reply normally. Defaults to false.
+    pub interleaved: bool,
+}
+
+impl Default for AssociationConfig {
+    fn default() -> Self {
+        AssociationConfig {
+            min_poll: 6,
+            max_poll: 10,
+            interleaved: false,
+        }
+    }
+}
+
+/// State of the client side of an association with a single server.
+#[derive(Debug, Clone)]
+pub struct Association {
+    filter: ClockFilter,
+    poll: PollController,
+    next_poll: Option<Timestamp>,
+    reach: u8,
+    last_sample: Option<SntpSample>,
+    last_offset: Option<NtpDuration>,
+    denied: Option<KissCode>,
+    interleaved: bool,
+    last_exchange: Option<Exchange>,
+    last_interleaved: bool,
+}
+
+impl Association {
+    /// Creates an association that is due to be polled immediately. `local_precision` is the
+    /// precision of the local clock, see [`NtpClock::precision`](crate::NtpClock::precision).
+    pub fn new(config: AssociationConfig, local_precision: i8) -> Self {
+        Association {
+            filter: ClockFilter::new(local_precision),
+            poll: PollController::new(config.min_poll, config.max_poll),
+            next_poll: None,
+            reach: 0,
+            last_sample: None,
+            last_offset: None,
+            denied: None,
+            interleaved: config.interleaved,
+            last_exchange: None,
+            last_interleaved: false,
+        }
+    }
+
+    /// Current poll exponent. The server is polled every `2^poll` seconds.
+    pub fn poll(&self) -> i8 {
+        self.poll.poll()
+    }
+
+    pub fn poll_interval(&self) -> NtpDuration {
+        NtpDuration::from_log2(self.poll.poll())
+    }
+
+    /// Returns true if the server should be polled at local time `now`.
+    pub fn is_poll_due(&self, now: Timestamp) -> bool {
+        self.denied.is_none()
+            && self
+                .next_poll
+                .map_or(true, |next| now - next >= NtpDuration::ZERO)
+    }
+
+    /// Time to wait from local time `now` until the next poll, or `None` if the server denied
+    /// access and must not be polled again.
+    pub fn time_until_poll(&self, now: Timestamp) -> Option<core::time::Duration> {
+        if self.denied.is_some() {
+            return None;
+        }
+
+        let wait = self.next_poll.map_or(NtpDuration::ZERO, |next| {
+            (next - now).max(NtpDuration::ZERO)
+        });
+        let nanos = wait.as_nanos() as u64;
+        Some(core::time::Duration::from_nanos(nanos))
+    }
+
+    /// Records the result of polling the server at local time `now` and schedules the next poll.
+    ///
+    /// Returns the new output of the clock filter if the result changed it.
+    pub fn handle_result<S, R>(
+        &mut self,
+        result: &Result<SntpSample, Error<S, R>>,
+        now: Timestamp,
+    ) -> Option<FilterOutput> {
+        self.reach <<= 1;
+
+        let output = match result {
+            Ok(sample) => {
+                self.reach |= 1;
+                self.last_sample = Some(*sample);
+                self.filter.add_sample(sample)
+            }
+            Err(Error::SntpProtocol(SntpProtocolError::KissOfDeath(code))) => {
+                if *code == KissCode::RATE {
+                    self.poll.back_off();
+                } else if code.is_access_denied() {
+                    self.denied = Some(*code);
+                }
+                None
+            }
+            Err(_) => {
+                // Back off from a server that has stopped responding entirely.
+                if self.reach == 0 && self.last_sample.is_some() {
+                    self.poll.back_off();
+                }
+                None
+            }
+        };
+
+        if let Some(output) = output {
+            if let Some(last_offset) = self.last_offset {
+                let change = (output.offset - last_offset).abs().to_seconds_f64();
+                self.poll
+                    .adjust(change < POLL_GATE * output.jitter.to_seconds_f64());
+            }
+            self.last_offset = Some(output.offset);
+        }
+
+        self.next_poll = Some(now + self.poll_interval());
+        output
+    }
+
+    /// Current best estimate of the server's offset from the clock filter.
+    pub fn estimate(&self) -> Option<FilterOutput> {
+        self.filter.output()
+    }
+
+    /// Root synchronization distance of [`Association::estimate`] at local time `now`, which
+    /// grows as the estimate gets older.
+    pub fn root_distance(&self, now: Timestamp) -> Option<NtpDuration> {
+        let estimate = self.filter.output()?;
+        let sample = self.last_sample.as_ref()?;
+        let dispersion = self.filter.dispersion_at(now)?;
+        Some(
+            (sample.root_delay + estimate.delay) / 2
+                + sample.root_dispersion
+                + dispersion
+                + estimate.jitter,
+        )
+    }
+
+    /// Returns the current estimate as a candidate for [`select`](crate::select::select), so that
+    /// the associations of several servers can be combined.
+    pub fn candidate(&self, now: Timestamp) -> Option<Candidate> {
+        Some(Candidate {
+            offset: self.filter.output()?.offset,
+            root_distance: self.root_distance(now)?,
+        })
+    }
+
+    /// The most recent sample received from the server.
+    pub fn last_sample(&self) -> Option<&SntpSample> {
+        self.last_sample.as_ref()
+    }
+
+    /// Reachability register. Each bit is set if the corresponding one of the last eight polls
+    /// succeeded, with the most recent poll in the lowest bit.
+    pub fn reachability(&self) -> u8 {
+        self.reach
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.reach != 0
+    }
+
+    /// Returns true if the server answered the last poll in interleaved mode.
+    pub fn is_interleaved(&self) -> bool {
+        self.last_interleaved
+    }
+
+    /// The exchange the next request should refer back to, if interleaved mode is enabled.
+    pub(crate) fn interleaved_exchange(&self) -> Option<Exchange> {
+        self.last_exchange.filter(|_| self.interleaved)
+    }
+
+    /// Remembers the exchange completed by a reply for the next interleaved request.
+    pub(crate) fn record_reply(&mut self, reply: Reply) -> SntpSample {
+        self.last_exchange = Some(reply.exchange);
+        self.last_interleaved = reply.interleaved;
+        reply.sample
+    }
+
+    /// The Kiss-o'-Death code the server used to deny access, if it did.
+    pub fn denied(&self) -> Option<KissCode> {
+        self.denied
+    }
+}
@@ -0,0 +1,114 @@
+//! Symmetric key authentication.
+//!
+//! NTP packets can be authenticated with a key shared between the sender and the receiver. The
+//! message authentication code (MAC) appended to the packet is the key identifier followed by a
+//! digest of the key and the packet, `H(key || packet)`, where `H` is MD5 or SHA-1.
+
+use crate::{
+    digest::{digest_eq, Md5, Sha1},
+    error::SntpProtocolError,
+    protocol::SntpMessage,
+};
+
+/// Digest algorithm of a [`SymmetricKey`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha1,
+}
+
+impl DigestAlgorithm {
+    /// Length of the digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            DigestAlgorithm::Md5 => Md5::OUTPUT_SIZE,
+            DigestAlgorithm::Sha1 => Sha1::OUTPUT_SIZE,
+        }
+    }
+}
+
+/// A key shared between the sender and receivers of authenticated packets.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymmetricKey<'a> {
+    pub id: u32,
+    pub algorithm: DigestAlgorithm,
+    pub secret: &'a [u8],
+}
+
+impl core::fmt::Debug for SymmetricKey<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SymmetricKey")
+            .field("id", &self.id)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> SymmetricKey<'a> {
+    pub const fn new(id: u32, algorithm: DigestAlgorithm, secret: &'a [u8]) -> Self {
+        SymmetricKey {
+            id,
+            algorithm,
+            secret,
+        }
+    }
+
+    /// Length of the MAC appended by [`SymmetricKey::sign`].
+    pub fn mac_len(&self) -> usize {
+        4 + self.algorithm.digest_len()
+    }
+
+    /// Appends a MAC to the packet in the first `len` bytes of `buffer` and returns the length of
+    /// the authenticated packet.
+    pub fn sign(&self, buffer: &mut [u8], len: usize) -> Result<usize, SntpProtocolError> {
+        let expected = len + self.mac_len();
+        if buffer.len() < expected {
+            return Err(SntpProtocolError::SntpBufferTooSmall {
+                size: buffer.len(),
+                expected,
+            });
+        }
+
+        let (digest, digest_len) = self.digest(&buffer[..len]);
+        buffer[len..len + 4].copy_from_slice(&self.id.to_be_bytes());
+        buffer[len + 4..expected].copy_from_slice(&digest[..digest_len]);
+        Ok(expected)
+    }
+
+    /// Checks that `packet` ends with a valid MAC from this key.
+    pub fn verify(&self, packet: &[u8]) -> Result<(), SntpProtocolError> {
+        let mut msg = SntpMessage::new_v4();
+        let extensions = msg.read_from_packet(packet)?;
+        let mac = extensions
+            .mac()
+            .ok_or(SntpProtocolError::AuthenticationFailed)?;
+        if mac.key_identifier != self.id || mac.digest.len() != self.algorithm.digest_len() {
+            return Err(SntpProtocolError::AuthenticationFailed);
+        }
+
+        let (digest, digest_len) = self.digest(&packet[..packet.len() - self.mac_len()]);
+        if !digest_eq(&digest[..digest_len], mac.digest) {
+            return Err(SntpProtocolError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+
+    fn digest(&self, data: &[u8]) -> ([u8; Sha1::OUTPUT_SIZE], usize) {
+        let mut output = [0; Sha1::OUTPUT_SIZE];
+        match self.algorithm {
+            DigestAlgorithm::Md5 => {
+                let mut md5 = Md5::new();
+                md5.update(self.secret);
+                md5.update(data);
+                output[..Md5::OUTPUT_SIZE].copy_from_slice(&md5.finalize());
+            }
+            DigestAlgorithm::Sha1 => {
+                let mut sha1 = Sha1::new();
+                sha1.update(self.secret);
+                sha1.update(data);
+                output = sha1.finalize();
+            }
+        }
+        (output, self.algorithm.digest_len())
+    }
+}
@@ -0,0 +1,247 @@
+//! Queries NTP servers and prints how far the local clock is from them, like `ntpdate -q` or
+//! `sntp`.
+
+use barentp::protocol::LeapIndicator;
+use barentp::{SntpSample, SystemClock};
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+
+const USAGE: &str = "\
+Usage: barentp [OPTIONS] [SERVER]...
+
+Queries each SERVER (default: pool.ntp.org) and prints the offset of the local clock from it.
+
+Options:
+  -t, --timeout <SECONDS>  How long to wait for each server to reply [default: 5]
+  -p, --port <PORT>        Port that the servers listen on [default: 123]
+  -j, --json               Print the results as JSON
+  -h, --help               Print this help
+  -V, --version            Print the version
+
+The exit status is 0 if every server replied, 1 if any of them did not and 2 if the arguments
+are invalid.
+";
+
+struct Options {
+    servers: Vec<String>,
+    timeout: Duration,
+    port: u16,
+    json: bool,
+}
+
+enum Command {
+    Query(Options),
+    Help,
+    Version,
+}
+
+/// The outcome of querying one server.
+struct Report {
+    server: String,
+    result: Result<(SocketAddr, SntpSample), String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Query(options)) => options,
+        Ok(Command::Help) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("barentp {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("barentp: {message}");
+            eprint!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    // Servers are queried concurrently so that unreachable ones do not add up their timeouts.
+    let reports: Vec<Report> = std::thread::scope(|scope| {
+        let handles: Vec<_> = options
+            .servers
+            .iter()
+            .map(|server| {
+                let options = &options;
+                scope.spawn(move || Report {
+                    server: server.clone(),
+                    result: query(server, options.port, options.timeout),
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("query thread panicked"))
+            .collect()
+    });
+
+    if options.json {
+        println!("{}", json_reports(&reports));
+    } else {
+        for report in &reports {
+            match &report.result {
+                Ok((addr, sample)) => println!("{}", human_report(&report.server, *addr, sample)),
+                Err(err) => eprintln!("barentp: {}: {err}", report.server),
+            }
+        }
+    }
+
+    if reports.iter().all(|report| report.result.is_ok()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options {
+        servers: Vec::new(),
+        timeout: Duration::from_secs(5),
+        port: 123,
+        json: false,
+    };
+
+    while let Some(arg) = args.next() {
+        // Options that take a value accept both `--timeout 1` and `--timeout=1`.
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| match inline_value {
+            Some(value) => Ok(value.to_string()),
+            None => args
+                .next()
+                .ok_or_else(|| format!("{name} requires a value")),
+        };
+
+        match name.as_str() {
+            "-t" | "--timeout" => {
+                let timeout = value(&name)?;
+                options.timeout = timeout
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or_else(|| format!("invalid timeout: {timeout}"))?;
+            }
+            "-p" | "--port" => {
+                let port = value(&name)?;
+                options.port = port.parse().map_err(|_| format!("invalid port: {port}"))?;
+            }
+            "-j" | "--json" => options.json = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            _ if name.starts_with('-') && name.len() > 1 => {
+                return Err(format!("unknown option: {name}"));
+            }
+            _ => options.servers.push(arg),
+        }
+    }
+
+    if options.servers.is_empty() {
+        options.servers.push(DEFAULT_NTP_SERVER.to_string());
+    }
+    Ok(Command::Query(options))
+}
+
+fn query(server: &str, port: u16, timeout: Duration) -> Result<(SocketAddr, SntpSample), String> {
+    let host = match server.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{server}]:{port}"),
+        Err(_) => format!("{server}:{port}"),
+    };
+    barentp::dns::sntp_query_host_happy_eyeballs(&host, &SystemClock, timeout)
+        .map(|reply| (reply.addr, reply.sample))
+        .map_err(|err| err.to_string())
+}
+
+fn human_report(server: &str, addr: SocketAddr, sample: &SntpSample) -> String {
+    format!(
+        "{server} ({addr}): offset {:+.6} s, delay {:.6} s, stratum {}, refid {}, leap {}, \
+         precision 2^{}",
+        sample.offset.to_seconds_f64(),
+        sample.delay.to_seconds_f64(),
+        sample.stratum,
+        refid(sample),
+        leap(sample.leap_indicator),
+        sample.precision,
+    )
+}
+
+fn json_reports(reports: &[Report]) -> String {
+    let mut json = String::from("[");
+    for (index, report) in reports.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let _ = write!(json, "\n  {{\"server\": {}", json_string(&report.server));
+        match &report.result {
+            Ok((addr, sample)) => {
+                let _ = write!(
+                    json,
+                    ", \"address\": {}, \"offset\": {}, \"delay\": {}, \"stratum\": {}, \
+                     \"refid\": {}, \"leap\": \"{}\", \"precision\": {}}}",
+                    json_string(&addr.to_string()),
+                    sample.offset.to_seconds_f64(),
+                    sample.delay.to_seconds_f64(),
+                    sample.stratum,
+                    json_string(&refid(sample)),
+                    leap(sample.leap_indicator),
+                    sample.precision,
+                );
+            }
+            Err(err) => {
+                let _ = write!(json, ", \"error\": {}}}", json_string(err));
+            }
+        }
+    }
+    json.push_str("\n]");
+    json
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// The reference identifier as `ntpq` shows it: the reference source of a primary server, or the
+/// address of the upstream server otherwise.
+fn refid(sample: &SntpSample) -> String {
+    let bytes = sample.reference_identifier.to_be_bytes();
+    if sample.stratum <= 1 {
+        bytes
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '?' })
+            .collect()
+    } else {
+        Ipv4Addr::from(bytes).to_string()
+    }
+}
+
+fn leap(leap_indicator: LeapIndicator) -> &'static str {
+    match leap_indicator {
+        LeapIndicator::NoWarning => "none",
+        LeapIndicator::LastMinuteHas61Seconds => "insert",
+        LeapIndicator::LastMinuteHas59Seconds => "delete",
+        LeapIndicator::AlarmCondition => "unsynchronized",
+    }
+}
@@ -1,6 +1,14 @@
 use crate::{
-    error::Error,
+    association::Association,
+    broadcast::{BroadcastClient, Broadcaster},
+    clock::NtpClock,
+    control::{ControlMessage, ControlReassembler, ControlResponse},
+    error::{Error, SntpProtocolError},
+    peer::SymmetricPeer,
     protocol::{SntpMessage, Timestamp},
+    sample::{ClientRequest, Exchange, Reply, SntpSample},
+    select::MultiQuery,
+    server::{ClientAddress, SntpServer},
 };
 
 pub trait NtpTransport {
@@ -9,18 +17,79 @@ pub trait NtpTransport {
 
     fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError>;
     fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError>;
+
+    /// Sends a packet like [`NtpTransport::send`] and returns the time it left if the transport
+    /// knows it more precisely than the caller, e.g. from a hardware timestamp.
+    ///
+    /// The timestamp must be from the same clock as the [`NtpClock`] the transport is used with.
+    /// By default no timestamp is returned and the time the request was written is used instead.
+    fn send_timestamped(&self, buffer: &[u8]) -> Result<Option<Timestamp>, Self::SendError> {
+        self.send(buffer).map(|()| None)
+    }
+
+    /// Receives a packet like [`NtpTransport::recv`], along with the time it arrived if the
+    /// transport knows it more precisely than the caller, e.g. from a kernel timestamp.
+    ///
+    /// The timestamp must be from the same clock as the [`NtpClock`] the transport is used with.
+    /// By default no timestamp is returned and the clock is read after `recv` returns instead.
+    fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        self.recv(buffer).map(|len| (len, None))
+    }
+}
+
+/// Transport that is not connected to a single server, such as a bound but unconnected UDP
+/// socket, so that one transport can be used to query many servers.
+pub trait NtpAddressedTransport {
+    /// Address of a server, e.g. `std::net::SocketAddr`.
+    type Address: PartialEq;
+    type SendError;
+    type RecvError;
+
+    fn send_to(&self, buffer: &[u8], address: &Self::Address) -> Result<(), Self::SendError>;
+    fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, Self::Address), Self::RecvError>;
+
+    /// Returns true if a packet received from `source` comes from `address`, e.g. the same IPv4
+    /// address written as an IPv4-mapped IPv6 address. By default the addresses must be equal.
+    fn is_same_address(source: &Self::Address, address: &Self::Address) -> bool {
+        source == address
+    }
+
+    /// Sends a packet like [`NtpAddressedTransport::send_to`] and returns the time it left if
+    /// the transport knows it, see [`NtpTransport::send_timestamped`].
+    fn send_to_timestamped(
+        &self,
+        buffer: &[u8],
+        address: &Self::Address,
+    ) -> Result<Option<Timestamp>, Self::SendError> {
+        self.send_to(buffer, address).map(|()| None)
+    }
+
+    /// Receives a packet like [`NtpAddressedTransport::recv_from`] along with the time it
+    /// arrived if the transport knows it, see [`NtpTransport::recv_timestamped`].
+    fn recv_from_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Self::Address, Option<Timestamp>), Self::RecvError> {
+        self.recv_from(buffer)
+            .map(|(len, address)| (len, address, None))
+    }
 }
 
 fn sntp_send_and_recv<T>(transport: &T) -> Result<SntpMessage, Error<T::SendError, T::RecvError>>
 where
     T: NtpTransport,
 {
-    let mut buf = [0; SntpMessage::BUFFER_SIZE];
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
     let mut msg = SntpMessage::new_v4();
     msg.write_to_buffer(&mut buf)?;
-    transport.send(&buf).map_err(Error::TransportSend)?;
-    transport.recv(&mut buf).map_err(Error::TransportRecv)?;
-    msg.read_from_buffer(&buf)?;
+    transport
+        .send(&buf[..SntpMessage::BUFFER_SIZE])
+        .map_err(Error::TransportSend)?;
+    let len = transport.recv(&mut buf).map_err(Error::TransportRecv)?;
+    msg.read_from_packet(&buf[..len.min(buf.len())])?;
     Ok(msg)
 }
 
@@ -34,6 +103,340 @@ where
     Ok(msg.transmit_timestamp)
 }
 
+/// Queries a server and computes the offset of its clock relative to `clock`, along with the
+/// round-trip delay and error estimates.
+///
+/// Replies that do not answer this request are discarded while waiting for the one that does, so
+/// the transport should have a receive timeout in case the reply is lost.
+pub fn sntp_query<T, C>(
+    transport: &T,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransport,
+    C: NtpClock + ?Sized,
+{
+    query(transport, clock, None).map(|reply| reply.sample)
+}
+
+/// Queries a server like [`sntp_query`], asking for an interleaved reply if `previous` is given.
+fn query<T, C>(
+    transport: &T,
+    clock: &C,
+    previous: Option<Exchange>,
+) -> Result<Reply, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let mut request = ClientRequest::new(clock, &mut buf, previous)?;
+    let t1 = transport
+        .send_timestamped(&buf[..SntpMessage::BUFFER_SIZE])
+        .map_err(Error::TransportSend)?;
+    request.sent(t1);
+
+    // Replies to other requests, e.g. late or duplicated replies to an earlier one, are
+    // discarded like any other bogus packet.
+    loop {
+        let (len, t4) = transport
+            .recv_timestamped(&mut buf)
+            .map_err(Error::TransportRecv)?;
+        let t4 = t4.unwrap_or_else(|| clock.now());
+        match request.process_reply(&buf[..len.min(buf.len())], t4) {
+            Err(SntpProtocolError::OriginateTimestampMismatch) => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Queries each of the servers in turn and combines their offsets using the clock selection
+/// algorithm from RFC 5905, discarding servers that disagree with the majority.
+pub fn sntp_query_servers<T, C, const N: usize>(
+    transports: &[T; N],
+    clock: &C,
+) -> MultiQuery<T::SendError, T::RecvError, N>
+where
+    T: NtpTransport,
+    C: NtpClock + ?Sized,
+{
+    MultiQuery::new(core::array::from_fn(|i| sntp_query(&transports[i], clock)))
+}
+
+/// Sends a control message request and waits for all fragments of the response, which is
+/// reassembled in `buffer`.
+///
+/// Packets that are not part of the response, such as late responses to earlier requests, are
+/// ignored. The transport should have a receive timeout in case a fragment is lost.
+pub fn sntp_control<'b, T>(
+    transport: &T,
+    request: &ControlMessage<'_>,
+    buffer: &'b mut [u8],
+) -> Result<ControlResponse<'b>, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransport,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = request.write_to_buffer(&mut buf)?;
+    transport.send(&buf[..len]).map_err(Error::TransportSend)?;
+
+    let mut reassembler = ControlReassembler::new(request, buffer);
+    loop {
+        let len = transport.recv(&mut buf).map_err(Error::TransportRecv)?;
+        let fragment = match ControlMessage::read_from_packet(&buf[..len.min(buf.len())]) {
+            Ok(fragment) => fragment,
+            Err(_) => continue,
+        };
+        match reassembler.add_fragment(&fragment) {
+            Ok(true) => break,
+            Ok(false) | Err(SntpProtocolError::StalePacket) => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(reassembler.finish().expect("response is complete"))
+}
+
+/// Polls the server of `association` and records the result in it.
+///
+/// This should be called whenever [`Association::is_poll_due`] returns true.
+pub fn sntp_poll<T, C>(
+    association: &mut Association,
+    transport: &T,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransport,
+    C: NtpClock + ?Sized,
+{
+    let result = query(transport, clock, association.interleaved_exchange())
+        .map(|reply| association.record_reply(reply));
+    association.handle_result(&result, clock.now());
+    result
+}
+
+/// Queries the server at `address` through an unconnected transport, like [`sntp_query`].
+///
+/// Packets received from any other address, e.g. late replies from other servers queried through
+/// the same transport, are discarded.
+pub fn sntp_query_to<T, C>(
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    query_to(transport, address, clock, None).map(|reply| reply.sample)
+}
+
+/// Queries a server like [`sntp_query_to`], asking for an interleaved reply if `previous` is
+/// given.
+fn query_to<T, C>(
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+    previous: Option<Exchange>,
+) -> Result<Reply, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let mut request = ClientRequest::new(clock, &mut buf, previous)?;
+    let t1 = transport
+        .send_to_timestamped(&buf[..SntpMessage::BUFFER_SIZE], address)
+        .map_err(Error::TransportSend)?;
+    request.sent(t1);
+
+    loop {
+        let (len, source, t4) = transport
+            .recv_from_timestamped(&mut buf)
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, address) {
+            continue;
+        }
+        let t4 = t4.unwrap_or_else(|| clock.now());
+        match request.process_reply(&buf[..len.min(buf.len())], t4) {
+            Err(SntpProtocolError::OriginateTimestampMismatch) => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Queries each of the servers in turn through one unconnected transport and combines their
+/// offsets, like [`sntp_query_servers`].
+pub fn sntp_query_servers_to<T, C, const N: usize>(
+    transport: &T,
+    addresses: &[T::Address; N],
+    clock: &C,
+) -> MultiQuery<T::SendError, T::RecvError, N>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    MultiQuery::new(core::array::from_fn(|i| {
+        sntp_query_to(transport, &addresses[i], clock)
+    }))
+}
+
+/// Polls the server of `association` at `address` through an unconnected transport and records
+/// the result in it, like [`sntp_poll`].
+pub fn sntp_poll_to<T, C>(
+    association: &mut Association,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let result = query_to(
+        transport,
+        address,
+        clock,
+        association.interleaved_exchange(),
+    )
+    .map(|reply| association.record_reply(reply));
+    association.handle_result(&result, clock.now());
+    result
+}
+
+type AddressedError<T> =
+    Error<<T as NtpAddressedTransport>::SendError, <T as NtpAddressedTransport>::RecvError>;
+
+/// Receives one request from `transport` and answers it with `server`.
+///
+/// Returns the address of the client that was answered, or `None` if the request was dropped.
+pub fn sntp_serve_one<T, C>(
+    server: &mut SntpServer<'_>,
+    transport: &T,
+    clock: &C,
+) -> Result<Option<T::Address>, AddressedError<T>>
+where
+    T: NtpAddressedTransport,
+    T::Address: ClientAddress,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let (len, client, received) = transport
+        .recv_from_timestamped(&mut buf)
+        .map_err(Error::TransportRecv)?;
+    let received = received.unwrap_or_else(|| clock.now());
+
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    let request = &buf[..len.min(buf.len())];
+    let Some(len) = server.handle_request(request, client.ip_addr(), received, clock, &mut reply)
+    else {
+        return Ok(None);
+    };
+    let sent_at = transport
+        .send_to_timestamped(&reply[..len], &client)
+        .map_err(Error::TransportSend)?;
+    server.sent(sent_at.unwrap_or_else(|| clock.now()));
+    Ok(Some(client))
+}
+
+/// Sends the next broadcast packet of `broadcaster` to `destination`, which is usually a subnet
+/// broadcast address or a multicast group.
+///
+/// This should be called whenever [`Broadcaster::time_until_next`] has passed.
+pub fn sntp_broadcast<T, C>(
+    broadcaster: &mut Broadcaster<'_>,
+    transport: &T,
+    destination: &T::Address,
+    clock: &C,
+) -> Result<(), AddressedError<T>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = broadcaster.build_packet(clock, &mut buf)?;
+    transport
+        .send_to(&buf[..len], destination)
+        .map_err(Error::TransportSend)
+}
+
+/// Waits for the next broadcast packet from `server` and passes it to `client`, see
+/// [`BroadcastClient::handle_packet`].
+///
+/// Packets received from any other address are discarded.
+pub fn sntp_recv_broadcast<T, C>(
+    client: &mut BroadcastClient<'_>,
+    transport: &T,
+    server: &T::Address,
+    clock: &C,
+) -> Result<Option<SntpSample>, AddressedError<T>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    loop {
+        let (len, source, received) = transport
+            .recv_from_timestamped(&mut buf)
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, server) {
+            continue;
+        }
+        let received = received.unwrap_or_else(|| clock.now());
+        let sample = client.handle_packet(&buf[..len.min(buf.len())], received)?;
+        return Ok(sample);
+    }
+}
+
+/// Sends the next packet of `peer` to the other side of the association at `address`.
+///
+/// This should be called whenever [`SymmetricPeer::time_until_poll`] has passed.
+pub fn sntp_peer_send<T, C>(
+    peer: &mut SymmetricPeer<'_>,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<(), AddressedError<T>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = peer.build_packet(clock, &mut buf)?;
+    let sent_at = transport
+        .send_to_timestamped(&buf[..len], address)
+        .map_err(Error::TransportSend)?;
+    peer.sent(sent_at);
+    Ok(())
+}
+
+/// Waits for the next packet from the other side of the association at `address`, ignoring
+/// packets from any other source.
+///
+/// Returns a sample if the packet completed an exchange, see [`SymmetricPeer::handle_packet`].
+pub fn sntp_peer_recv<T, C>(
+    peer: &mut SymmetricPeer<'_>,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<Option<SntpSample>, AddressedError<T>>
+where
+    T: NtpAddressedTransport,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    loop {
+        let (len, source, received) = transport
+            .recv_from_timestamped(&mut buf)
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, address) {
+            continue;
+        }
+        let received = received.unwrap_or_else(|| clock.now());
+        let sample = peer.handle_packet(&buf[..len.min(buf.len())], received)?;
+        return Ok(sample);
+    }
+}
+
 #[cfg(feature = "std")]
 impl NtpTransport for std::net::UdpSocket {
     type SendError = std::io::Error;
@@ -51,3 +454,29 @@ impl NtpTransport for std::net::UdpSocket {
         self.recv(buffer)
     }
 }
+
+#[cfg(feature = "std")]
+impl NtpAddressedTransport for std::net::UdpSocket {
+    type Address = std::net::SocketAddr;
+    type SendError = std::io::Error;
+    type RecvError = std::io::Error;
+
+    fn send_to(&self, buffer: &[u8], address: &Self::Address) -> Result<(), Self::SendError> {
+        self.send_to(buffer, *address).map(|_| ())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, Self::Address), Self::RecvError> {
+        self.recv_from(buffer)
+    }
+
+    fn is_same_address(source: &Self::Address, address: &Self::Address) -> bool {
+        is_same_socket_addr(source, address)
+    }
+}
+
+/// Compares socket addresses the way a dual-stack socket sees them, where replies from an IPv4
+/// server come from its IPv4-mapped IPv6 address.
+#[cfg(feature = "std")]
+pub(crate) fn is_same_socket_addr(a: &std::net::SocketAddr, b: &std::net::SocketAddr) -> bool {
+    a.ip().to_canonical() == b.ip().to_canonical() && a.port() == b.port()
+}
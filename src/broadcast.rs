@@ -0,0 +1,228 @@
+//! Broadcast and multicast mode.
+//!
+//! A broadcast server periodically sends its time to a subnet broadcast address or a multicast
+//! group, so that many clients can be synchronized without each of them polling the server. A
+//! broadcast packet only carries the time it was sent, so a [`BroadcastClient`] also needs the
+//! one-way delay from the server to compute an offset. It is measured with a normal client/server
+//! exchange, such as [`sntp_query`](crate::sntp_query), and passed to
+//! [`BroadcastClient::calibrate`]:
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example() -> std::io::Result<()> {
+//! use barentp::broadcast::BroadcastClient;
+//! use barentp::{NtpClock, SystemClock};
+//!
+//! let server = "192.168.1.1:123".parse().unwrap();
+//! let listener = std::net::UdpSocket::bind("0.0.0.0:123")?;
+//! let calibration = std::net::UdpSocket::bind("0.0.0.0:0")?;
+//! calibration.connect(server)?;
+//!
+//! let mut client = BroadcastClient::new(SystemClock.precision());
+//! if let Ok(sample) = barentp::sntp_query(&calibration, &SystemClock) {
+//!     client.calibrate(&sample);
+//! }
+//! loop {
+//!     if let Ok(Some(sample)) =
+//!         barentp::sntp_recv_broadcast(&mut client, &listener, &server, &SystemClock)
+//!     {
+//!         println!("offset: {}", sample.offset);
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! To listen on a multicast group instead, join the group on the socket, e.g. with
+//! `UdpSocket::join_multicast_v4` for the IPv4 NTP group `224.0.1.1`.
+//!
+//! The server side is a [`Broadcaster`], which builds a broadcast packet whenever
+//! [`Broadcaster::time_until_next`] has passed. [`sntp_broadcast`](crate::sntp_broadcast) sends
+//! it to a broadcast or multicast address, which for `std::net::UdpSocket` requires
+//! `set_broadcast(true)` or a multicast TTL. Anyone on the network can send broadcast packets, so
+//! clients should only trust them when they are authenticated with a
+//! [`SymmetricKey`](crate::auth::SymmetricKey) shared with the server.
+
+use crate::{
+    auth::SymmetricKey,
+    clock::NtpClock,
+    error::SntpProtocolError,
+    protocol::{Mode, NtpDuration, SntpMessage, Timestamp},
+    sample::{check_synchronized, SntpSample},
+    server::ServerConfig,
+};
+
+/// Client side of broadcast mode for a single server.
+#[derive(Debug, Clone)]
+pub struct BroadcastClient<'a> {
+    local_precision: i8,
+    key: Option<SymmetricKey<'a>>,
+    one_way_delay: Option<NtpDuration>,
+    last_transmit: Option<Timestamp>,
+}
+
+impl<'a> BroadcastClient<'a> {
+    /// Creates a client that is not calibrated yet. `local_precision` is the precision of the
+    /// local clock, see [`NtpClock::precision`](crate::NtpClock::precision).
+    pub fn new(local_precision: i8) -> Self {
+        BroadcastClient {
+            local_precision,
+            key: None,
+            one_way_delay: None,
+            last_transmit: None,
+        }
+    }
+
+    /// Only accepts packets that are authenticated with `key`.
+    pub fn with_key(mut self, key: SymmetricKey<'a>) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Estimates the one-way delay from the server from a client/server exchange with it.
+    ///
+    /// Calibration can be repeated with more samples, the one with the lowest round-trip delay is
+    /// used since it was the least affected by queuing in the network. Calibrating also accepts
+    /// packets older than the last one again, in case the server stepped its clock back.
+    pub fn calibrate(&mut self, sample: &SntpSample) {
+        self.last_transmit = None;
+        let one_way_delay = sample.delay / 2;
+        self.one_way_delay = Some(
+            self.one_way_delay
+                .map_or(one_way_delay, |delay| delay.min(one_way_delay)),
+        );
+    }
+
+    /// Sets the one-way delay directly, e.g. from configuration, or clears it with `None` so that
+    /// the client must be calibrated again. Like [`calibrate`](Self::calibrate), this accepts
+    /// packets older than the last one again.
+    pub fn set_one_way_delay(&mut self, one_way_delay: Option<NtpDuration>) {
+        self.last_transmit = None;
+        self.one_way_delay = one_way_delay;
+    }
+
+    pub fn one_way_delay(&self) -> Option<NtpDuration> {
+        self.one_way_delay
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.one_way_delay.is_some()
+    }
+
+    /// Validates a broadcast packet from the server that was received at local time `received`.
+    ///
+    /// Returns a sample if the client has been calibrated, or `None` if it has not. Packets that
+    /// are not newer than the last valid packet are rejected, so duplicated or replayed packets
+    /// are not counted twice, until the client is calibrated again.
+    pub fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        received: Timestamp,
+    ) -> Result<Option<SntpSample>, SntpProtocolError> {
+        let mut msg = SntpMessage::new_v4();
+        msg.read_from_packet(packet)?;
+        if let Some(key) = &self.key {
+            key.verify(packet)?;
+        }
+
+        if msg.mode != Mode::Broadcast {
+            return Err(SntpProtocolError::UnexpectedSntpMode(msg.mode));
+        }
+        check_synchronized(&msg)?;
+
+        if let Some(last) = self.last_transmit {
+            if msg.transmit_timestamp - last <= NtpDuration::ZERO {
+                return Err(SntpProtocolError::StalePacket);
+            }
+        }
+        self.last_transmit = Some(msg.transmit_timestamp);
+
+        Ok(self.one_way_delay.map(|one_way_delay| {
+            SntpSample::from_broadcast(&msg, received, one_way_delay, self.local_precision)
+        }))
+    }
+}
+
+/// Server side of broadcast mode, which periodically sends the time to many clients at once.
+#[derive(Debug, Clone)]
+pub struct Broadcaster<'a> {
+    config: ServerConfig,
+    poll: i8,
+    key: Option<SymmetricKey<'a>>,
+    next: Option<Timestamp>,
+}
+
+impl<'a> Broadcaster<'a> {
+    /// Creates a broadcaster that sends a packet every `2^poll` seconds, starting immediately.
+    pub fn new(config: ServerConfig, poll: i8) -> Self {
+        Broadcaster {
+            config,
+            poll,
+            key: None,
+            next: None,
+        }
+    }
+
+    /// Authenticates every packet with `key`.
+    pub fn with_key(mut self, key: SymmetricKey<'a>) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Replaces the configuration, e.g. after the server's own clock was synchronized.
+    pub fn set_config(&mut self, config: ServerConfig) {
+        self.config = config;
+    }
+
+    pub fn poll(&self) -> i8 {
+        self.poll
+    }
+
+    /// Time to wait from local time `now` until the next packet should be sent.
+    pub fn time_until_next(&self, now: Timestamp) -> core::time::Duration {
+        let wait = self.next.map_or(NtpDuration::ZERO, |next| {
+            (next - now).max(NtpDuration::ZERO)
+        });
+        core::time::Duration::from_nanos(wait.as_nanos() as u64)
+    }
+
+    /// Writes the next broadcast packet to `buffer`, stamped with the current time of `clock`,
+    /// and schedules the one after it. Returns the length of the packet.
+    pub fn build_packet<C>(
+        &mut self,
+        clock: &C,
+        buffer: &mut [u8],
+    ) -> Result<usize, SntpProtocolError>
+    where
+        C: NtpClock + ?Sized,
+    {
+        let config = &self.config;
+        let now = clock.now();
+        let msg = SntpMessage {
+            leap_indicator: config.leap_indicator,
+            version: crate::protocol::Version::V4,
+            mode: Mode::Broadcast,
+            stratum: config.stratum,
+            poll: self.poll as u8,
+            precision: clock.precision() as u8,
+            root_delay: config.root_delay.to_ntp_short(),
+            root_dispersion: config.root_dispersion.to_ntp_short(),
+            reference_identifier: u32::from_be_bytes(config.reference_identifier),
+            reference_timestamp: config.reference_timestamp.unwrap_or(now),
+            originate_timestamp: Timestamp::new(0, 0),
+            receive_timestamp: Timestamp::new(0, 0),
+            transmit_timestamp: now,
+        };
+        msg.write_to_buffer(buffer)?;
+
+        let len = match &self.key {
+            Some(key) => key.sign(buffer, SntpMessage::BUFFER_SIZE)?,
+            None => SntpMessage::BUFFER_SIZE,
+        };
+        self.next = Some(now + NtpDuration::from_log2(self.poll));
+        Ok(len)
+    }
+}
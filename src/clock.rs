@@ -0,0 +1,78 @@
+use crate::protocol::{NtpDuration, Timestamp};
+
+/// The local clock that requests and replies are timestamped with.
+///
+/// The offset reported for a server is relative to this clock.
+pub trait NtpClock {
+    /// Returns the current time.
+    fn now(&self) -> Timestamp;
+
+    /// Precision of the clock as a power of two in seconds, e.g. `-20` is roughly one
+    /// microsecond.
+    fn precision(&self) -> i8 {
+        -20
+    }
+}
+
+impl<C> NtpClock for &C
+where
+    C: NtpClock + ?Sized,
+{
+    fn now(&self) -> Timestamp {
+        (**self).now()
+    }
+
+    fn precision(&self) -> i8 {
+        (**self).precision()
+    }
+}
+
+/// [`NtpClock`] implementation that uses the standard library's `std::time::SystemTime`.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl NtpClock for SystemClock {
+    fn now(&self) -> Timestamp {
+        std::time::SystemTime::now().into()
+    }
+}
+
+/// A clock that can be adjusted, e.g. an operating system or hardware clock.
+///
+/// This is driven by [`ClockDiscipline`](crate::discipline::ClockDiscipline) to keep the clock
+/// synchronized.
+pub trait ClockControl {
+    type Error;
+
+    /// Immediately moves the clock forwards (positive) or backwards (negative) by `offset`.
+    fn step(&mut self, offset: NtpDuration) -> Result<(), Self::Error>;
+
+    /// Gradually moves the clock forwards or backwards by `offset` without stepping it, by
+    /// temporarily speeding it up or slowing it down.
+    fn slew(&mut self, offset: NtpDuration) -> Result<(), Self::Error>;
+
+    /// Sets the frequency correction of the clock in seconds per second. A positive value makes
+    /// the clock run faster, e.g. `1e-6` makes it gain an extra microsecond every second.
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Self::Error>;
+}
+
+impl<C> ClockControl for &mut C
+where
+    C: ClockControl + ?Sized,
+{
+    type Error = C::Error;
+
+    fn step(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        (**self).step(offset)
+    }
+
+    fn slew(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        (**self).slew(offset)
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Self::Error> {
+        (**self).set_frequency(frequency)
+    }
+}
@@ -0,0 +1,471 @@
+//! NTP control messages (mode 6), as used by `ntpq`.
+//!
+//! Control messages query the state of an NTP server rather than its time: the status of its
+//! associations with other servers ([`ControlOpcode::READSTAT`]) and the values of its system or
+//! peer variables ([`ControlOpcode::READVAR`]). A response larger than a single packet is split
+//! into fragments, which a [`ControlReassembler`] puts back together in a buffer provided by the
+//! caller. [`sntp_control`](crate::sntp_control) sends a request over a connected transport and
+//! waits for the complete response:
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example() -> std::io::Result<()> {
+//! use barentp::control::ControlMessage;
+//!
+//! let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+//! socket.connect("192.168.1.1:123")?;
+//! socket.set_read_timeout(Some(std::time::Duration::from_secs(2)))?;
+//!
+//! let mut buffer = [0; 4096];
+//! let request = ControlMessage::read_variables(1, 0, b"");
+//! if let Ok(response) = barentp::sntp_control(&socket, &request, &mut buffer) {
+//!     for variable in response.variables() {
+//!         println!("{} = {}", variable.name, variable.value);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Relevant documentation from RFC 9327:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |LI |  VN |Mode |R|E|M| OpCode  |       Sequence Number         |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |            Status             |       Association ID          |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |            Offset             |            Count              |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                                                               |
+//! .                                                               .
+//! .                  Data (up to 468 octets)                      .
+//! .                                                               .
+//! |                                                               |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+
+use crate::{
+    error::SntpProtocolError,
+    protocol::{LeapIndicator, Mode},
+};
+
+/// Operation requested by a control message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ControlOpcode(pub u8);
+
+impl ControlOpcode {
+    /// Read the status of the server, or of one of its associations.
+    pub const READSTAT: ControlOpcode = ControlOpcode(1);
+    /// Read system variables, or the variables of one of the server's associations.
+    pub const READVAR: ControlOpcode = ControlOpcode(2);
+}
+
+/// A single control message packet, which is a request or one fragment of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlMessage<'a> {
+    /// Set in responses.
+    pub response: bool,
+    /// Set in responses if the request failed, in which case the error code is the high byte of
+    /// the status.
+    pub error: bool,
+    /// Set in every fragment of a response except the last.
+    pub more: bool,
+    pub opcode: ControlOpcode,
+    /// Chosen by the client and copied into the response, to match responses to requests.
+    pub sequence: u16,
+    /// System status for association 0, otherwise the status of the association.
+    pub status: u16,
+    /// Association the request is about, or 0 for the server itself.
+    pub association_id: u16,
+    /// Offset of this fragment's data in the complete response.
+    pub offset: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> ControlMessage<'a> {
+    /// Size of the header that precedes the data.
+    pub const HEADER_SIZE: usize = 12;
+
+    /// Largest amount of data in a single packet.
+    pub const MAX_DATA_SIZE: usize = 468;
+
+    /// Version number written into requests. `ntpq` uses version 2 so that older servers answer
+    /// too.
+    const VERSION: u8 = 2;
+
+    /// Creates a request.
+    pub fn request(
+        opcode: ControlOpcode,
+        sequence: u16,
+        association_id: u16,
+        data: &'a [u8],
+    ) -> Self {
+        ControlMessage {
+            response: false,
+            error: false,
+            more: false,
+            opcode,
+            sequence,
+            status: 0,
+            association_id,
+            offset: 0,
+            data,
+        }
+    }
+
+    /// Creates a request for the status of the server and the list of its associations, or for
+    /// the status of one association.
+    pub fn read_status(sequence: u16, association_id: u16) -> Self {
+        Self::request(ControlOpcode::READSTAT, sequence, association_id, &[])
+    }
+
+    /// Creates a request for the comma separated list of variables in `names`, or for all
+    /// variables if it is empty, of the server or of one association.
+    pub fn read_variables(sequence: u16, association_id: u16, names: &'a [u8]) -> Self {
+        Self::request(ControlOpcode::READVAR, sequence, association_id, names)
+    }
+
+    /// Length of the packet written by [`ControlMessage::write_to_buffer`], which pads the data
+    /// to a multiple of 4 bytes.
+    pub fn packet_len(&self) -> usize {
+        Self::HEADER_SIZE + self.data.len().next_multiple_of(4)
+    }
+
+    /// Writes the packet to `buffer` and returns its length.
+    pub fn write_to_buffer(&self, buffer: &mut [u8]) -> Result<usize, SntpProtocolError> {
+        if self.data.len() > Self::MAX_DATA_SIZE {
+            return Err(SntpProtocolError::ControlDataTooLarge {
+                size: self.data.len(),
+                max: Self::MAX_DATA_SIZE,
+            });
+        }
+        let len = self.packet_len();
+        if buffer.len() < len {
+            return Err(SntpProtocolError::SntpBufferTooSmall {
+                size: buffer.len(),
+                expected: len,
+            });
+        }
+
+        buffer[0] = (Self::VERSION << 3) | Mode::Reserved6 as u8;
+        buffer[1] = (u8::from(self.response) << 7)
+            | (u8::from(self.error) << 6)
+            | (u8::from(self.more) << 5)
+            | (self.opcode.0 & 0x1f);
+        buffer[2..4].copy_from_slice(&self.sequence.to_be_bytes());
+        buffer[4..6].copy_from_slice(&self.status.to_be_bytes());
+        buffer[6..8].copy_from_slice(&self.association_id.to_be_bytes());
+        buffer[8..10].copy_from_slice(&self.offset.to_be_bytes());
+        buffer[10..12].copy_from_slice(&(self.data.len() as u16).to_be_bytes());
+        buffer[Self::HEADER_SIZE..Self::HEADER_SIZE + self.data.len()].copy_from_slice(self.data);
+        buffer[Self::HEADER_SIZE + self.data.len()..len].fill(0);
+        Ok(len)
+    }
+
+    /// Reads a packet as it was received from a transport. Any padding or MAC after the data is
+    /// ignored.
+    pub fn read_from_packet(packet: &'a [u8]) -> Result<Self, SntpProtocolError> {
+        if packet.len() < Self::HEADER_SIZE {
+            return Err(SntpProtocolError::TruncatedSntpPacket {
+                size: packet.len(),
+                expected: Self::HEADER_SIZE,
+            });
+        }
+
+        let mode = Mode::from_bits(packet[0]);
+        if mode != Mode::Reserved6 {
+            return Err(SntpProtocolError::UnexpectedSntpMode(mode));
+        }
+
+        let count = usize::from(u16::from_be_bytes([packet[10], packet[11]]));
+        if packet.len() < Self::HEADER_SIZE + count {
+            return Err(SntpProtocolError::TruncatedSntpPacket {
+                size: packet.len(),
+                expected: Self::HEADER_SIZE + count,
+            });
+        }
+
+        Ok(ControlMessage {
+            response: packet[1] & 0x80 != 0,
+            error: packet[1] & 0x40 != 0,
+            more: packet[1] & 0x20 != 0,
+            opcode: ControlOpcode(packet[1] & 0x1f),
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            status: u16::from_be_bytes([packet[4], packet[5]]),
+            association_id: u16::from_be_bytes([packet[6], packet[7]]),
+            offset: u16::from_be_bytes([packet[8], packet[9]]),
+            data: &packet[Self::HEADER_SIZE..Self::HEADER_SIZE + count],
+        })
+    }
+}
+
+/// Maximum number of fragments in a response. `ntpd` never sends more than 24.
+const MAX_FRAGMENTS: usize = 32;
+
+/// Puts the fragments of a response back together, in whatever order they arrive.
+#[derive(Debug)]
+pub struct ControlReassembler<'b> {
+    opcode: ControlOpcode,
+    sequence: u16,
+    buffer: &'b mut [u8],
+    /// Offset and length of each fragment received so far.
+    fragments: [(usize, usize); MAX_FRAGMENTS],
+    fragment_count: usize,
+    received: usize,
+    /// Length of the response, known once the last fragment has arrived.
+    total: Option<usize>,
+    status: u16,
+    association_id: u16,
+}
+
+impl<'b> ControlReassembler<'b> {
+    /// Creates a reassembler for the response to `request`, which is written to `buffer`.
+    pub fn new(request: &ControlMessage<'_>, buffer: &'b mut [u8]) -> Self {
+        ControlReassembler {
+            opcode: request.opcode,
+            sequence: request.sequence,
+            buffer,
+            fragments: [(0, 0); MAX_FRAGMENTS],
+            fragment_count: 0,
+            received: 0,
+            total: None,
+            status: 0,
+            association_id: 0,
+        }
+    }
+
+    /// Adds a fragment of the response and returns true once the response is complete.
+    ///
+    /// Fragments of responses to other requests are rejected with
+    /// [`SntpProtocolError::StalePacket`], and an error response with
+    /// [`SntpProtocolError::ControlError`]. Duplicate fragments are ignored.
+    pub fn add_fragment(
+        &mut self,
+        fragment: &ControlMessage<'_>,
+    ) -> Result<bool, SntpProtocolError> {
+        if !fragment.response
+            || fragment.opcode != self.opcode
+            || fragment.sequence != self.sequence
+        {
+            return Err(SntpProtocolError::StalePacket);
+        }
+        if fragment.error {
+            return Err(SntpProtocolError::ControlError(
+                (fragment.status >> 8) as u8,
+            ));
+        }
+
+        let start = usize::from(fragment.offset);
+        let end = start + fragment.data.len();
+        let fragments = &self.fragments[..self.fragment_count];
+        if fragments.contains(&(start, fragment.data.len())) {
+            return Ok(self.is_complete());
+        }
+        let invalid = SntpProtocolError::InvalidControlFragment { offset: start };
+        if fragments
+            .iter()
+            .any(|&(offset, len)| start < offset + len && offset < end)
+            || self.fragment_count == MAX_FRAGMENTS
+            || self.total.is_some_and(|total| end > total)
+            || (!fragment.more && self.fragments().any(|(offset, len)| offset + len > end))
+        {
+            return Err(invalid);
+        }
+        if end > self.buffer.len() {
+            return Err(SntpProtocolError::SntpBufferTooSmall {
+                size: self.buffer.len(),
+                expected: end,
+            });
+        }
+
+        self.buffer[start..end].copy_from_slice(fragment.data);
+        self.fragments[self.fragment_count] = (start, fragment.data.len());
+        self.fragment_count += 1;
+        self.received += fragment.data.len();
+        if !fragment.more {
+            self.total = Some(end);
+        }
+        self.status = fragment.status;
+        self.association_id = fragment.association_id;
+        Ok(self.is_complete())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.received)
+    }
+
+    /// Returns the complete response, or `None` if fragments are still missing.
+    pub fn finish(self) -> Option<ControlResponse<'b>> {
+        let total = self.total.filter(|&total| total == self.received)?;
+        Some(ControlResponse {
+            status: self.status,
+            association_id: self.association_id,
+            data: &self.buffer[..total],
+        })
+    }
+
+    fn fragments(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.fragments[..self.fragment_count].iter().copied()
+    }
+}
+
+/// A complete response to a control request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlResponse<'b> {
+    /// System status if the request was for association 0, otherwise the association's status.
+    pub status: u16,
+    pub association_id: u16,
+    pub data: &'b [u8],
+}
+
+impl<'b> ControlResponse<'b> {
+    /// Status of the server, if the request was for association 0.
+    pub fn system_status(&self) -> SystemStatus {
+        SystemStatus(self.status)
+    }
+
+    /// The associations listed in the response to a [`ControlOpcode::READSTAT`] request for
+    /// association 0.
+    pub fn associations(&self) -> impl Iterator<Item = AssociationStatus> + 'b {
+        self.data.chunks_exact(4).map(|entry| AssociationStatus {
+            association_id: u16::from_be_bytes([entry[0], entry[1]]),
+            status: PeerStatus(u16::from_be_bytes([entry[2], entry[3]])),
+        })
+    }
+
+    /// The variables in the response to a [`ControlOpcode::READVAR`] request.
+    pub fn variables(&self) -> Variables<'b> {
+        Variables { data: self.data }
+    }
+}
+
+/// System status word of a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SystemStatus(pub u16);
+
+impl SystemStatus {
+    pub fn leap_indicator(&self) -> LeapIndicator {
+        match self.0 >> 14 {
+            0 => LeapIndicator::NoWarning,
+            1 => LeapIndicator::LastMinuteHas61Seconds,
+            2 => LeapIndicator::LastMinuteHas59Seconds,
+            _ => LeapIndicator::AlarmCondition,
+        }
+    }
+
+    /// Kind of source the server is synchronized to, e.g. 6 for another NTP server.
+    pub fn clock_source(&self) -> u8 {
+        ((self.0 >> 8) & 0x3f) as u8
+    }
+
+    /// Number of events since the counter was last reset, saturating at 15.
+    pub fn event_count(&self) -> u8 {
+        ((self.0 >> 4) & 0xf) as u8
+    }
+
+    /// Code of the most recent event.
+    pub fn event_code(&self) -> u8 {
+        (self.0 & 0xf) as u8
+    }
+}
+
+/// One entry of the association list in a [`ControlOpcode::READSTAT`] response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AssociationStatus {
+    pub association_id: u16,
+    pub status: PeerStatus,
+}
+
+/// Status word of one of a server's associations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerStatus(pub u16);
+
+impl PeerStatus {
+    /// The association was configured rather than created on demand.
+    pub fn is_configured(&self) -> bool {
+        self.0 & 0x8000 != 0
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.0 & 0x2000 != 0
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.0 & 0x1000 != 0
+    }
+
+    /// Result of clock selection for this peer, from 0 (rejected) to 6 (system peer).
+    pub fn selection(&self) -> u8 {
+        ((self.0 >> 8) & 0x7) as u8
+    }
+
+    /// Number of events since the counter was last reset, saturating at 15.
+    pub fn event_count(&self) -> u8 {
+        ((self.0 >> 4) & 0xf) as u8
+    }
+
+    /// Code of the most recent event.
+    pub fn event_code(&self) -> u8 {
+        (self.0 & 0xf) as u8
+    }
+}
+
+/// A `name=value` pair from a [`ControlOpcode::READVAR`] response. Quotes around the value are
+/// removed, and variables without a value have an empty one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variable<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// Iterator over the variables of a [`ControlOpcode::READVAR`] response.
+///
+/// Variables are separated by commas, which may also appear inside quoted values. Variables that
+/// are not valid UTF-8 are skipped.
+#[derive(Debug, Clone)]
+pub struct Variables<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Variables<'a> {
+    type Item = Variable<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self
+                .data
+                .iter()
+                .position(|&byte| byte != b',' && !byte.is_ascii_whitespace() && byte != 0)?;
+            let data = &self.data[start..];
+
+            let mut quoted = false;
+            let end = data
+                .iter()
+                .position(|&byte| {
+                    if byte == b'"' {
+                        quoted = !quoted;
+                    }
+                    byte == b',' && !quoted
+                })
+                .unwrap_or(data.len());
+            self.data = &data[end..];
+
+            let Ok(entry) = core::str::from_utf8(&data[..end]) else {
+                continue;
+            };
+            let (name, value) = entry.split_once('=').unwrap_or((entry, ""));
+            let value = value.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\0');
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            return Some(Variable {
+                name: name.trim(),
+                value,
+            });
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Minimal SHA-1 and MD5 implementations for NTP symmetric key authentication.
+//!
+//! Both are broken as general purpose hash functions, but they are what NTP uses for keyed
+//! message digests and for checksums of other data such as the leap second list.
+
+/// Streaming SHA-1 (FIPS 180-4).
+#[derive(Debug, Clone)]
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha1 {
+    pub(crate) const OUTPUT_SIZE: usize = 20;
+
+    pub(crate) fn new() -> Self {
+        Sha1 {
+            state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        update_blocks(&mut self.block, &mut self.block_len, data, |block| {
+            sha1_compress(&mut self.state, block)
+        });
+    }
+
+    pub(crate) fn finalize(mut self) -> [u8; Self::OUTPUT_SIZE] {
+        let bit_len = self.total_len.wrapping_mul(8).to_be_bytes();
+        finish_blocks(&mut self.block, self.block_len, bit_len, |block| {
+            sha1_compress(&mut self.state, block)
+        });
+
+        let mut output = [0; Self::OUTPUT_SIZE];
+        for (chunk, word) in output.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        output
+    }
+}
+
+fn sha1_compress(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for (i, chunk) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, &w) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A827999),
+            20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// Streaming MD5 (RFC 1321).
+#[derive(Debug, Clone)]
+pub(crate) struct Md5 {
+    state: [u32; 4],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+/// Per-round shift amounts of MD5.
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// `floor(abs(sin(i + 1)) * 2^32)` for each step of MD5.
+const MD5_CONSTANTS: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+impl Md5 {
+    pub(crate) const OUTPUT_SIZE: usize = 16;
+
+    pub(crate) fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        update_blocks(&mut self.block, &mut self.block_len, data, |block| {
+            md5_compress(&mut self.state, block)
+        });
+    }
+
+    pub(crate) fn finalize(mut self) -> [u8; Self::OUTPUT_SIZE] {
+        let bit_len = self.total_len.wrapping_mul(8).to_le_bytes();
+        finish_blocks(&mut self.block, self.block_len, bit_len, |block| {
+            md5_compress(&mut self.state, block)
+        });
+
+        let mut output = [0; Self::OUTPUT_SIZE];
+        for (chunk, word) in output.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        output
+    }
+}
+
+fn md5_compress(state: &mut [u32; 4], block: &[u8; 64]) {
+    let mut m = [0u32; 16];
+    for (i, chunk) in block.chunks_exact(4).enumerate() {
+        m[i] = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    let [mut a, mut b, mut c, mut d] = *state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let shift = MD5_SHIFTS[(i / 16) * 4 + i % 4];
+        let f = f
+            .wrapping_add(a)
+            .wrapping_add(MD5_CONSTANTS[i])
+            .wrapping_add(m[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(shift));
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// Buffers `data` into 64 byte blocks, compressing each block as it is filled.
+fn update_blocks(
+    block: &mut [u8; 64],
+    block_len: &mut usize,
+    mut data: &[u8],
+    mut compress: impl FnMut(&[u8; 64]),
+) {
+    while !data.is_empty() {
+        let take = (64 - *block_len).min(data.len());
+        block[*block_len..*block_len + take].copy_from_slice(&data[..take]);
+        *block_len += take;
+        data = &data[take..];
+        if *block_len == 64 {
+            compress(block);
+            *block_len = 0;
+        }
+    }
+}
+
+/// Appends the Merkle–Damgård padding and the message length in bits, which SHA-1 and MD5 share
+/// apart from the byte order of the length.
+fn finish_blocks(
+    block: &mut [u8; 64],
+    block_len: usize,
+    bit_len: [u8; 8],
+    mut compress: impl FnMut(&[u8; 64]),
+) {
+    block[block_len] = 0x80;
+    block[block_len + 1..].fill(0);
+    if block_len >= 56 {
+        compress(block);
+        block.fill(0);
+    }
+    block[56..].copy_from_slice(&bit_len);
+    compress(block);
+}
+
+/// Compares two digests in constant time.
+pub(crate) fn digest_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,378 @@
+//! Clock discipline for keeping a local clock synchronized.
+//!
+//! This implements the hybrid phase-locked loop (PLL) / frequency-locked loop (FLL) from RFC 5905.
+//! Offsets are fed to [`ClockDiscipline::update`], usually from the output of a
+//! [`ClockFilter`](crate::filter::ClockFilter) or from [`select`](crate::select::select). Large
+//! offsets step the clock, small offsets are corrected by adjusting the frequency of the clock and
+//! by slewing its phase in small increments from [`ClockDiscipline::tick`], which must be called
+//! once per second.
+//!
+//! Corrections are applied through the [`ClockControl`](ClockControl) trait.
+
+use crate::{
+    clock::ClockControl,
+    protocol::{NtpDuration, Timestamp},
+};
+
+/// Minimum poll exponent allowed by RFC 5905 (16 seconds).
+pub const MIN_POLL: i8 = 4;
+
+/// Maximum poll exponent allowed by RFC 5905 (36.4 hours).
+pub const MAX_POLL: i8 = 17;
+
+/// Compromise Allan intercept in seconds. Above this poll interval the FLL contributes to the
+/// frequency estimate.
+const ALLAN: f64 = 1500.0;
+
+/// Time constant multiplier of the PLL.
+const PLL_GAIN: f64 = 16.0;
+
+/// Time constant of the FLL.
+const FLL_GAIN: f64 = MAX_POLL as f64 + 1.0;
+
+/// Averaging constant for jitter and wander.
+const AVG: f64 = 4.0;
+
+/// Maximum frequency correction in seconds per second (500 PPM).
+const MAX_FREQUENCY: f64 = 500e-6;
+
+/// Hysteresis limit of the poll interval counter.
+const POLL_LIMIT: i32 = 30;
+
+/// Multiple of jitter within which an offset counts towards increasing the poll interval.
+pub(crate) const POLL_GATE: f64 = 4.0;
+
+/// Configuration for a [`ClockDiscipline`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisciplineConfig {
+    /// Offsets larger than this are stepped instead of slewed, as long as they persist for longer
+    /// than [`DisciplineConfig::stepout`]. Defaults to 128 milliseconds.
+    pub step_threshold: NtpDuration,
+    /// Offsets larger than this are refused. Defaults to 1000 seconds, `None` accepts any offset.
+    pub panic_threshold: Option<NtpDuration>,
+    /// How long a large offset must persist before the clock is stepped. Defaults to 900 seconds.
+    pub stepout: NtpDuration,
+    /// Minimum poll exponent. Defaults to 6 (64 seconds).
+    pub min_poll: i8,
+    /// Maximum poll exponent. Defaults to 10 (1024 seconds).
+    pub max_poll: i8,
+}
+
+impl Default for DisciplineConfig {
+    fn default() -> Self {
+        DisciplineConfig {
+            step_threshold: NtpDuration::from_millis(128),
+            panic_threshold: Some(NtpDuration::from_seconds(1000)),
+            stepout: NtpDuration::from_seconds(900),
+            min_poll: 6,
+            max_poll: 10,
+        }
+    }
+}
+
+/// State of the discipline state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisciplineState {
+    /// No offset has been received yet.
+    Unset,
+    /// The frequency is being measured directly from the first offsets.
+    Frequency,
+    /// A large offset was received and the discipline is waiting to see if it persists.
+    Spike,
+    /// The clock is being disciplined by the PLL/FLL.
+    Sync,
+}
+
+/// What [`ClockDiscipline::update`] did with an offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisciplineAction {
+    /// The offset was not used, either because it was a possible spike or because the
+    /// discipline is still measuring the frequency.
+    Ignore,
+    /// The offset will be slewed out gradually.
+    Slew,
+    /// The clock was stepped.
+    Step,
+    /// The offset was larger than the panic threshold and was refused.
+    Panic,
+}
+
+/// Hybrid PLL/FLL clock discipline from RFC 5905.
+#[derive(Debug, Clone)]
+pub struct ClockDiscipline {
+    config: DisciplineConfig,
+    state: DisciplineState,
+    /// Remaining phase correction in seconds.
+    offset: f64,
+    /// Last offset passed to the loop, in seconds.
+    last: f64,
+    /// Local time of the last update.
+    last_update: Option<Timestamp>,
+    /// Frequency correction in seconds per second.
+    frequency: f64,
+    jitter: f64,
+    wander: f64,
+    poll: PollController,
+    precision: f64,
+}
+
+impl ClockDiscipline {
+    /// Creates a discipline for a clock with the given precision, as a power of two in seconds.
+    pub fn new(config: DisciplineConfig, precision: i8) -> Self {
+        let precision = NtpDuration::from_log2(precision).to_seconds_f64();
+
+        ClockDiscipline {
+            config,
+            state: DisciplineState::Unset,
+            offset: 0.0,
+            last: 0.0,
+            last_update: None,
+            frequency: 0.0,
+            jitter: precision,
+            wander: 0.0,
+            poll: PollController::new(config.min_poll, config.max_poll),
+            precision,
+        }
+    }
+
+    /// Creates a discipline that starts with a previously measured frequency correction, e.g. one
+    /// that was saved from [`ClockDiscipline::frequency`] before a restart. This skips the initial
+    /// frequency measurement.
+    pub fn with_frequency(config: DisciplineConfig, precision: i8, frequency: f64) -> Self {
+        let mut discipline = Self::new(config, precision);
+        discipline.frequency = frequency.clamp(-MAX_FREQUENCY, MAX_FREQUENCY);
+        discipline.state = DisciplineState::Sync;
+        discipline
+    }
+
+    pub fn state(&self) -> DisciplineState {
+        self.state
+    }
+
+    /// Current poll exponent. Samples should be fed to the discipline every `2^poll` seconds.
+    pub fn poll(&self) -> i8 {
+        self.poll.poll()
+    }
+
+    /// Current frequency correction in seconds per second.
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Phase correction that has not been slewed out yet.
+    pub fn residual_offset(&self) -> NtpDuration {
+        NtpDuration::from_seconds_f64(self.offset)
+    }
+
+    /// Exponential average of the differences between consecutive offsets.
+    pub fn jitter(&self) -> NtpDuration {
+        NtpDuration::from_seconds_f64(self.jitter)
+    }
+
+    /// Exponential average of the changes in frequency, in seconds per second.
+    pub fn wander(&self) -> f64 {
+        self.wander
+    }
+
+    /// Passes an offset measured at local time `time` through the loop filter.
+    ///
+    /// The clock is stepped immediately if necessary. Otherwise the new frequency correction is
+    /// applied and the phase correction is left for [`ClockDiscipline::tick`].
+    pub fn update<C>(
+        &mut self,
+        offset: NtpDuration,
+        time: Timestamp,
+        clock: &mut C,
+    ) -> Result<DisciplineAction, C::Error>
+    where
+        C: ClockControl + ?Sized,
+    {
+        if self
+            .config
+            .panic_threshold
+            .is_some_and(|panic| offset.abs() > panic)
+        {
+            return Ok(DisciplineAction::Panic);
+        }
+
+        let mu = self
+            .last_update
+            .map_or(0.0, |last| (time - last).to_seconds_f64().max(0.0));
+        let stepout = self.config.stepout.to_seconds_f64();
+        let offset_seconds = offset.to_seconds_f64();
+        let mut frequency = 0.0;
+        let mut action = DisciplineAction::Slew;
+
+        if offset.abs() > self.config.step_threshold {
+            match self.state {
+                DisciplineState::Sync => {
+                    // The first large offset might just be a spike, wait to see if it persists.
+                    self.state = DisciplineState::Spike;
+                    return Ok(DisciplineAction::Ignore);
+                }
+                DisciplineState::Frequency | DisciplineState::Spike if mu < stepout => {
+                    return Ok(DisciplineAction::Ignore);
+                }
+                DisciplineState::Frequency => {
+                    frequency = (offset_seconds - self.offset) / mu;
+                }
+                DisciplineState::Spike | DisciplineState::Unset => {}
+            }
+
+            clock.step(offset)?;
+            self.poll.reset();
+            action = DisciplineAction::Step;
+            if self.state == DisciplineState::Unset {
+                self.reset(DisciplineState::Frequency, time, 0.0);
+                return Ok(action);
+            }
+            self.reset(DisciplineState::Sync, time, 0.0);
+        } else {
+            let difference = (offset_seconds - self.last).abs().max(self.precision);
+            self.jitter = exponential_average(self.jitter, difference);
+
+            match self.state {
+                DisciplineState::Unset => {
+                    self.reset(DisciplineState::Frequency, time, offset_seconds);
+                    return Ok(DisciplineAction::Ignore);
+                }
+                DisciplineState::Frequency => {
+                    if mu < stepout {
+                        return Ok(DisciplineAction::Ignore);
+                    }
+                    frequency = (offset_seconds - self.offset) / mu;
+                }
+                DisciplineState::Spike | DisciplineState::Sync => {
+                    let poll_interval = poll_seconds(self.poll.poll());
+
+                    // The FLL is only used at long poll intervals, where it is more accurate.
+                    if poll_interval > ALLAN / 2.0 {
+                        let gain = (FLL_GAIN - self.poll.poll() as f64).max(AVG);
+                        frequency += (offset_seconds - self.offset) / (mu.max(ALLAN) * gain);
+                    }
+
+                    let time_constant = 4.0 * PLL_GAIN * poll_interval;
+                    frequency +=
+                        offset_seconds * mu.min(poll_interval) / (time_constant * time_constant);
+                }
+            }
+            self.reset(DisciplineState::Sync, time, offset_seconds);
+        }
+
+        let frequency = (self.frequency + frequency).clamp(-MAX_FREQUENCY, MAX_FREQUENCY);
+        self.wander = exponential_average(self.wander, frequency - self.frequency);
+        self.frequency = frequency;
+        clock.set_frequency(self.frequency)?;
+
+        self.poll
+            .adjust(self.offset.abs() < POLL_GATE * self.jitter);
+        Ok(action)
+    }
+
+    /// Slews out the next part of the remaining phase correction. This must be called once per
+    /// second while the clock is being disciplined.
+    pub fn tick<C>(&mut self, clock: &mut C) -> Result<(), C::Error>
+    where
+        C: ClockControl + ?Sized,
+    {
+        if self.state != DisciplineState::Sync && self.state != DisciplineState::Spike {
+            return Ok(());
+        }
+
+        let adjustment = self.offset / (PLL_GAIN * poll_seconds(self.poll.poll()).min(ALLAN));
+        self.offset -= adjustment;
+        clock.slew(NtpDuration::from_seconds_f64(adjustment))
+    }
+
+    fn reset(&mut self, state: DisciplineState, time: Timestamp, offset: f64) {
+        self.state = state;
+        self.offset = offset;
+        self.last = offset;
+        self.last_update = Some(time);
+    }
+}
+
+/// Poll exponent with the hysteresis from RFC 5905. The poll interval is lengthened while offsets
+/// are small compared to the jitter, and shortened quickly when they are not.
+#[derive(Debug, Clone)]
+pub(crate) struct PollController {
+    poll: i8,
+    count: i32,
+    min_poll: i8,
+    max_poll: i8,
+}
+
+impl PollController {
+    pub(crate) fn new(min_poll: i8, max_poll: i8) -> Self {
+        let min_poll = min_poll.clamp(MIN_POLL, MAX_POLL);
+        let max_poll = max_poll.clamp(min_poll, MAX_POLL);
+        PollController {
+            poll: min_poll,
+            count: 0,
+            min_poll,
+            max_poll,
+        }
+    }
+
+    pub(crate) fn poll(&self) -> i8 {
+        self.poll
+    }
+
+    /// Goes back to the minimum poll exponent.
+    pub(crate) fn reset(&mut self) {
+        self.poll = self.min_poll;
+        self.count = 0;
+    }
+
+    /// Immediately lengthens the poll interval, e.g. when a server asks for a lower rate.
+    pub(crate) fn back_off(&mut self) {
+        self.poll = (self.poll + 1).min(self.max_poll);
+        self.count = 0;
+    }
+
+    pub(crate) fn adjust(&mut self, stable: bool) {
+        if stable {
+            self.count += self.poll as i32;
+            if self.count > POLL_LIMIT {
+                self.count = POLL_LIMIT;
+                if self.poll < self.max_poll {
+                    self.count = 0;
+                    self.poll += 1;
+                }
+            }
+        } else {
+            self.count -= (self.poll as i32) << 1;
+            if self.count < -POLL_LIMIT {
+                self.count = -POLL_LIMIT;
+                if self.poll > self.min_poll {
+                    self.count = 0;
+                    self.poll -= 1;
+                }
+            }
+        }
+    }
+}
+
+fn poll_seconds(poll: i8) -> f64 {
+    (1u64 << poll.clamp(0, 63)) as f64
+}
+
+/// Root mean square exponential average, as used by RFC 5905 for jitter and wander.
+fn exponential_average(average: f64, sample: f64) -> f64 {
+    let squared = average * average;
+    sqrt(squared + (sample * sample - squared) / AVG)
+}
+
+/// Square root using Newton's method, since `f64::sqrt` requires the standard library.
+fn sqrt(value: f64) -> f64 {
+    if value <= 0.0 || !value.is_finite() {
+        return 0.0;
+    }
+
+    // Halving the exponent gives an initial guess that is within a factor of two.
+    let mut x = f64::from_bits((value.to_bits() >> 1) + (1023 << 51));
+    for _ in 0..6 {
+        x = 0.5 * (x + value / x);
+    }
+    x
+}
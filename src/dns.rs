@@ -0,0 +1,442 @@
+//! DNS resolution of NTP servers with the standard library.
+//!
+//! [`resolve`] looks up every IPv4 and IPv6 address of a server, and [`connect`] creates a UDP
+//! socket connected to one of them that can be used as an [`NtpTransport`](crate::NtpTransport).
+//! [`sntp_query_host`] tries each address of a server in turn until one replies,
+//! [`sntp_query_host_parallel`] queries all of them at once, and
+//! [`sntp_query_host_happy_eyeballs`] races IPv6 and IPv4 addresses like browsers do, which is
+//! the best choice for dual-stack servers:
+//!
+//! ```no_run
+//! # fn example() -> Result<(), barentp::error::Error<std::io::Error, std::io::Error>> {
+//! use barentp::dns::sntp_query_host;
+//! use barentp::SystemClock;
+//! use std::time::Duration;
+//!
+//! let reply = sntp_query_host("time.cloudflare.com", &SystemClock, Duration::from_secs(5))?;
+//! println!("{}: offset {}", reply.addr, reply.sample.offset);
+//!
+//! // The socket stays connected to the server that replied, for polling it again later.
+//! let sample = barentp::sntp_query(&reply.socket, &SystemClock)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Pools such as `pool.ntp.org` answer each lookup with a few servers picked from a much larger
+//! set. A [`ServerPool`] hands out connected sockets for distinct servers of a pool, looking it up
+//! again whenever it runs out, and takes servers back once they are no longer used.
+
+use crate::{clock::NtpClock, error::Error, sample::SntpSample, NtpTransport};
+use std::{
+    collections::VecDeque,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The port NTP servers listen on.
+pub const NTP_PORT: u16 = 123;
+
+/// Delay between starting the queries of [`sntp_query_happy_eyeballs`], as recommended by
+/// RFC 8305.
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How often a parallel query that is still waiting checks whether another one has finished.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Resolves `host` to all of its IPv4 and IPv6 addresses, in the order the resolver returned
+/// them.
+///
+/// `host` is a host name or an IP address, optionally followed by a port, e.g.
+/// `time.example.com:1123` or `[2001:db8::1]:1123`. The port defaults to [`NTP_PORT`].
+pub fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let (name, port) = split_port(host)?;
+    let mut addrs = Vec::new();
+    for addr in (name, port).to_socket_addrs()? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "host has no addresses",
+        ));
+    }
+    Ok(addrs)
+}
+
+/// Binds a UDP socket of the same address family as `addr` and connects it to `addr`, with
+/// `timeout` as its read and write timeout.
+pub fn connect(addr: SocketAddr, timeout: Duration) -> io::Result<UdpSocket> {
+    let local = match addr {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0; 16], 0)),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    Ok(socket)
+}
+
+/// A reply from one of the addresses of a host.
+#[derive(Debug)]
+pub struct HostSample {
+    /// The address that replied.
+    pub addr: SocketAddr,
+    /// A socket connected to `addr`.
+    pub socket: UdpSocket,
+    pub sample: SntpSample,
+}
+
+/// Queries each address of `host` in turn, waiting up to `timeout` for each, and returns the
+/// first reply.
+///
+/// If no address replies the error from the last one is returned. Failing to resolve `host` is
+/// reported as [`Error::Resolve`].
+pub fn sntp_query_host<C>(
+    host: &str,
+    clock: &C,
+    timeout: Duration,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + ?Sized,
+{
+    let mut last_error = None;
+    for addr in resolve(host).map_err(Error::Resolve)? {
+        let socket = match connect(addr, timeout) {
+            Ok(socket) => socket,
+            Err(err) => {
+                last_error = Some(Error::TransportSend(err));
+                continue;
+            }
+        };
+        match crate::sntp_query(&socket, clock) {
+            Ok(sample) => {
+                return Ok(HostSample {
+                    addr,
+                    socket,
+                    sample,
+                })
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.expect("resolve returns at least one address"))
+}
+
+/// Queries every address of `host` at the same time and returns the first reply, so that
+/// addresses that do not reply cost at most `timeout` in total.
+///
+/// Errors are reported like [`sntp_query_host`].
+pub fn sntp_query_host_parallel<C>(
+    host: &str,
+    clock: &C,
+    timeout: Duration,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + Sync + ?Sized,
+{
+    let addrs = resolve(host).map_err(Error::Resolve)?;
+    race(&addrs, clock, timeout, Duration::ZERO)
+}
+
+/// Resolves `host` and queries it with [`sntp_query_happy_eyeballs`].
+///
+/// Errors are reported like [`sntp_query_host`].
+pub fn sntp_query_host_happy_eyeballs<C>(
+    host: &str,
+    clock: &C,
+    timeout: Duration,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + Sync + ?Sized,
+{
+    let addrs = resolve(host).map_err(Error::Resolve)?;
+    sntp_query_happy_eyeballs(&addrs, clock, timeout)
+}
+
+/// Queries the addresses of a dual-stack server Happy Eyeballs style (RFC 8305), and returns the
+/// first reply.
+///
+/// Addresses are tried alternating between IPv6 and IPv4, starting with IPv6. A new query is
+/// started every [`ATTEMPT_DELAY`], or as soon as every earlier one has failed, until one of them
+/// gets a reply, so that a network with broken IPv6 only costs the attempt delay. All queries
+/// stop once `timeout` has passed.
+pub fn sntp_query_happy_eyeballs<C>(
+    addrs: &[SocketAddr],
+    clock: &C,
+    timeout: Duration,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + Sync + ?Sized,
+{
+    if addrs.is_empty() {
+        return Err(Error::Resolve(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no addresses to query",
+        )));
+    }
+    race(&interleave_families(addrs), clock, timeout, ATTEMPT_DELAY)
+}
+
+/// Queries `addrs` concurrently, starting the query of each address `attempt_delay` after the
+/// previous one, and returns the first reply.
+fn race<C>(
+    addrs: &[SocketAddr],
+    clock: &C,
+    timeout: Duration,
+    attempt_delay: Duration,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + Sync + ?Sized,
+{
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let done = AtomicBool::new(false);
+    let failed = AtomicUsize::new(0);
+    let first = Mutex::new(None);
+    let last_error = Mutex::new(None);
+
+    std::thread::scope(|scope| {
+        for (index, &addr) in addrs.iter().enumerate() {
+            let (done, failed, first, last_error) = (&done, &failed, &first, &last_error);
+            let start_at = start + attempt_delay * index as u32;
+            scope.spawn(move || {
+                loop {
+                    let now = Instant::now();
+                    if done.load(Ordering::Relaxed) || now >= deadline {
+                        return;
+                    }
+                    if now >= start_at || failed.load(Ordering::Relaxed) >= index {
+                        break;
+                    }
+                    std::thread::sleep((start_at - now).min(CANCEL_INTERVAL));
+                }
+
+                match query_until(addr, clock, timeout, deadline, done) {
+                    Ok(reply) => {
+                        let mut first = first.lock().unwrap();
+                        if first.is_none() {
+                            *first = Some(reply);
+                            done.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    Err(err) => {
+                        *last_error.lock().unwrap() = Some(err);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+
+    // No query may have started at all if the timeout is shorter than starting a thread.
+    match first.into_inner().unwrap() {
+        Some(reply) => Ok(reply),
+        None => Err(last_error.into_inner().unwrap().unwrap_or_else(|| {
+            Error::TransportRecv(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no reply from server",
+            ))
+        })),
+    }
+}
+
+/// Queries `addr` until `deadline`, or until another query is `done`.
+fn query_until<C>(
+    addr: SocketAddr,
+    clock: &C,
+    timeout: Duration,
+    deadline: Instant,
+    done: &AtomicBool,
+) -> Result<HostSample, Error<io::Error>>
+where
+    C: NtpClock + ?Sized,
+{
+    let socket = connect(addr, timeout).map_err(Error::TransportSend)?;
+    let transport = CancellableSocket {
+        socket: &socket,
+        deadline,
+        done,
+    };
+    let sample = crate::sntp_query(&transport, clock)?;
+    socket
+        .set_read_timeout(Some(timeout))
+        .map_err(Error::TransportRecv)?;
+    Ok(HostSample {
+        addr,
+        socket,
+        sample,
+    })
+}
+
+/// Orders `addrs` alternating between IPv6 and IPv4, starting with IPv6, and otherwise in the
+/// order the resolver returned them.
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    let mut ordered = Vec::with_capacity(addrs.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+/// A connected socket that stops waiting for a reply at a deadline, or once another query has
+/// finished.
+struct CancellableSocket<'a> {
+    socket: &'a UdpSocket,
+    deadline: Instant,
+    done: &'a AtomicBool,
+}
+
+impl NtpTransport for CancellableSocket<'_> {
+    type SendError = io::Error;
+    type RecvError = io::Error;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        NtpTransport::send(self.socket, buffer)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        // Waiting in short slices does not delay the reply, since `recv` returns as soon as it
+        // arrives.
+        loop {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.done.load(Ordering::Relaxed) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no reply from server",
+                ));
+            }
+            self.socket
+                .set_read_timeout(Some(remaining.min(CANCEL_INTERVAL)))?;
+            match self.socket.recv(buffer) {
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Connected sockets for distinct servers of a pool such as `pool.ntp.org`.
+///
+/// ```no_run
+/// # fn example() -> std::io::Result<()> {
+/// use barentp::dns::ServerPool;
+/// use barentp::SystemClock;
+///
+/// let mut pool = ServerPool::new("pool.ntp.org");
+/// let mut sockets = Vec::new();
+/// for _ in 0..4 {
+///     sockets.push(pool.next_server()?);
+/// }
+///
+/// // Replace servers that stop replying with new ones from the pool.
+/// for socket in &mut sockets {
+///     if barentp::sntp_query(&*socket, &SystemClock).is_err() {
+///         pool.release(socket.peer_addr()?);
+///         *socket = pool.next_server()?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerPool {
+    host: String,
+    timeout: Duration,
+    /// Addresses from the last lookup that have not been handed out yet.
+    pending: VecDeque<SocketAddr>,
+    in_use: Vec<SocketAddr>,
+}
+
+impl ServerPool {
+    /// Creates a pool of the servers that `host` resolves to, see [`resolve`].
+    pub fn new(host: impl Into<String>) -> Self {
+        ServerPool {
+            host: host.into(),
+            timeout: Duration::from_secs(5),
+            pending: VecDeque::new(),
+            in_use: Vec::new(),
+        }
+    }
+
+    /// Sets the read and write timeout of the sockets handed out by the pool. Defaults to 5
+    /// seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Addresses of the servers that have been handed out and not released.
+    pub fn in_use(&self) -> &[SocketAddr] {
+        &self.in_use
+    }
+
+    /// Returns a socket connected to a server that is not in use yet, looking up the pool again
+    /// once every address from the previous lookup has been handed out.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if every server the pool resolves to is in use.
+    pub fn next_server(&mut self) -> io::Result<UdpSocket> {
+        if self.pending.is_empty() {
+            let addrs = resolve(&self.host)?;
+            self.pending
+                .extend(addrs.into_iter().filter(|addr| !self.in_use.contains(addr)));
+        }
+        let addr = self.pending.pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "every server of the pool is in use",
+            )
+        })?;
+        let socket = connect(addr, self.timeout)?;
+        self.in_use.push(addr);
+        Ok(socket)
+    }
+
+    /// Gives a server back to the pool, e.g. because it stopped replying. It may be handed out
+    /// again by a later lookup.
+    pub fn release(&mut self, addr: SocketAddr) {
+        self.in_use.retain(|&in_use| in_use != addr);
+    }
+}
+
+/// Splits an optional port off `host`.
+fn split_port(host: &str) -> io::Result<(&str, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid port");
+    if let Some((ip, rest)) = host
+        .strip_prefix('[')
+        .and_then(|bracketed| bracketed.split_once(']'))
+    {
+        return match rest.strip_prefix(':') {
+            Some(port) => Ok((ip, port.parse().map_err(|_| invalid())?)),
+            None if rest.is_empty() => Ok((ip, NTP_PORT)),
+            None => Err(invalid()),
+        };
+    }
+    match host.rsplit_once(':') {
+        // More than one colon is an IPv6 address without a port.
+        Some((name, port)) if !name.contains(':') => {
+            Ok((name, port.parse().map_err(|_| invalid())?))
+        }
+        _ => Ok((host, NTP_PORT)),
+    }
+}
@@ -1,9 +1,16 @@
+use crate::protocol::{KissCode, Mode};
+
+/// An error from querying a server through a transport with send errors `S` and receive errors
+/// `R`, which are usually the same type, e.g. `Error<io::Error>`.
 #[derive(Debug)]
 #[non_exhaustive]
-pub enum Error<S, R> {
+pub enum Error<S, R = S> {
     TransportSend(S),
     TransportRecv(R),
     SntpProtocol(SntpProtocolError),
+    /// The host name of a server could not be resolved, see [`dns`](crate::dns).
+    #[cfg(feature = "std")]
+    Resolve(std::io::Error),
 }
 
 impl<S, R> core::fmt::Display for Error<S, R>
@@ -13,25 +20,22 @@ where
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Error::TransportSend(_) => write!(f, "transport send error"),
-            Error::TransportRecv(_) => write!(f, "transport recv error"),
-            Error::SntpProtocol(_) => write!(f, "SNTP protocol error"),
+            Error::TransportSend(e) => write!(f, "transport send error: {e}"),
+            Error::TransportRecv(e) => write!(f, "transport recv error: {e}"),
+            Error::SntpProtocol(e) => write!(f, "SNTP protocol error: {e}"),
+            #[cfg(feature = "std")]
+            Error::Resolve(e) => write!(f, "failed to resolve host: {e}"),
         }
     }
 }
 
+// The cause is already part of the message, so it is not returned as the source as well, which
+// would print it twice when the chain of sources is printed.
 impl<S, R> core::error::Error for Error<S, R>
 where
     S: 'static + core::error::Error,
     R: 'static + core::error::Error,
 {
-    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
-        match self {
-            Error::TransportSend(e) => Some(e),
-            Error::TransportRecv(e) => Some(e),
-            Error::SntpProtocol(e) => Some(e),
-        }
-    }
 }
 
 impl<S, R> From<SntpProtocolError> for Error<S, R> {
@@ -40,13 +44,136 @@ impl<S, R> From<SntpProtocolError> for Error<S, R> {
     }
 }
 
+impl<S, R> Error<S, R> {
+    /// Records which server the error happened with and on which attempt, starting at 1.
+    pub fn context<A>(self, server: A, attempt: u32) -> QueryError<A, Self> {
+        QueryError {
+            server,
+            attempt,
+            error: self,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S, R> Error<S, R>
+where
+    S: 'static + core::error::Error + Send + Sync,
+    R: 'static + core::error::Error + Send + Sync,
+{
+    /// Boxes the transport errors, so that errors from different transports have the same type.
+    pub fn erase(self) -> Error<TransportError> {
+        match self {
+            Error::TransportSend(e) => Error::TransportSend(TransportError(Box::new(e))),
+            Error::TransportRecv(e) => Error::TransportRecv(TransportError(Box::new(e))),
+            Error::SntpProtocol(e) => Error::SntpProtocol(e),
+            Error::Resolve(e) => Error::Resolve(e),
+        }
+    }
+}
+
+/// Protocol errors become [`InvalidData`](std::io::ErrorKind::InvalidData) errors, and
+/// transport and resolve errors are returned as they are.
+#[cfg(feature = "std")]
+impl From<Error<std::io::Error>> for std::io::Error {
+    fn from(e: Error<std::io::Error>) -> Self {
+        match e {
+            Error::TransportSend(e) | Error::TransportRecv(e) | Error::Resolve(e) => e,
+            Error::SntpProtocol(e) => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// A boxed transport error, see [`Error::erase`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct TransportError(BoxError);
+
+#[cfg(feature = "std")]
+impl TransportError {
+    pub fn get_ref(&self) -> &(dyn core::error::Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    pub fn into_inner(self) -> BoxError {
+        self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// An error along with the server it happened with and the attempt that failed, so that a
+/// failure in a log can be traced back to a server.
+///
+/// Created with [`Error::context`]. `server` is usually an address or a host name.
+#[derive(Debug)]
+pub struct QueryError<A, E> {
+    pub server: A,
+    pub attempt: u32,
+    pub error: E,
+}
+
+impl<A, E> core::fmt::Display for QueryError<A, E>
+where
+    A: core::fmt::Display,
+    E: core::fmt::Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "query of {} failed on attempt {}: {}",
+            self.server, self.attempt, self.error
+        )
+    }
+}
+
+// Like `Error`, the message already includes the error.
+impl<A, E> core::error::Error for QueryError<A, E>
+where
+    A: core::fmt::Debug + core::fmt::Display,
+    E: 'static + core::error::Error,
+{
+}
+
+/// Any error, for applications that do not need to handle transport errors by type.
+///
+/// [`Error`] and [`QueryError`] convert into it with `?` or `into()` when their transport errors
+/// are `Send + Sync + 'static`, keeping their messages.
+#[cfg(feature = "std")]
+pub type BoxError = std::boxed::Box<dyn core::error::Error + Send + Sync + 'static>;
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum SntpProtocolError {
     SntpBufferTooSmall { size: usize, expected: usize },
+    TruncatedSntpPacket { size: usize, expected: usize },
+    InvalidSntpExtensionField { offset: usize },
+    // Never returned, since every value of the three bit mode field is a `Mode`. Kept so that
+    // existing matches keep compiling.
     InvalidSntpMode(u8),
     InvalidSntpVersion(u8),
     InvalidSntpLeadIndicator(u8),
+    UnexpectedSntpMode(Mode),
+    OriginateTimestampMismatch,
+    KissOfDeath(KissCode),
+    ServerUnsynchronized,
+    StalePacket,
+    AuthenticationFailed,
+    ControlError(u8),
+    InvalidControlFragment { offset: usize },
+    ControlDataTooLarge { size: usize, max: usize },
 }
 
 impl core::fmt::Display for SntpProtocolError {
@@ -58,6 +185,15 @@ impl core::fmt::Display for SntpProtocolError {
                     "SNTP buffer is too small for message: size={size}, expected={expected}"
                 )
             }
+            SntpProtocolError::TruncatedSntpPacket { size, expected } => {
+                write!(
+                    f,
+                    "SNTP packet is truncated: size={size}, expected={expected}"
+                )
+            }
+            SntpProtocolError::InvalidSntpExtensionField { offset } => {
+                write!(f, "invalid SNTP extension field at offset {offset}")
+            }
             SntpProtocolError::InvalidSntpMode(mode) => write!(f, "invalid SNTP mode: 0x{mode:x}"),
             SntpProtocolError::InvalidSntpVersion(version) => {
                 write!(f, "invalid SNTP version: 0x{version:x}")
@@ -65,8 +201,65 @@ impl core::fmt::Display for SntpProtocolError {
             SntpProtocolError::InvalidSntpLeadIndicator(lead_indicator) => {
                 write!(f, "invalid SNTP lead indicator: 0x{lead_indicator:x}")
             }
+            SntpProtocolError::UnexpectedSntpMode(mode) => {
+                write!(f, "unexpected SNTP mode: {mode:?}")
+            }
+            SntpProtocolError::OriginateTimestampMismatch => {
+                write!(f, "originate timestamp does not match the request")
+            }
+            SntpProtocolError::KissOfDeath(code) => write!(f, "kiss-o'-death received: {code}"),
+            SntpProtocolError::ServerUnsynchronized => write!(f, "server is not synchronized"),
+            SntpProtocolError::AuthenticationFailed => write!(f, "message authentication failed"),
+            SntpProtocolError::ControlError(code) => {
+                write!(f, "control message request failed with error code {code}")
+            }
+            SntpProtocolError::InvalidControlFragment { offset } => {
+                write!(f, "invalid control message fragment at offset {offset}")
+            }
+            SntpProtocolError::ControlDataTooLarge { size, max } => {
+                write!(
+                    f,
+                    "control message data is too large: size={size}, max={max}"
+                )
+            }
+            SntpProtocolError::StalePacket => {
+                write!(
+                    f,
+                    "packet is a duplicate of or older than a previous packet"
+                )
+            }
         }
     }
 }
 
 impl core::error::Error for SntpProtocolError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LeapTableError {
+    InvalidLine { line: usize },
+    MissingField(&'static str),
+    TooManyEntries { capacity: usize },
+    HashMismatch,
+    Expired,
+}
+
+impl core::fmt::Display for LeapTableError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LeapTableError::InvalidLine { line } => {
+                write!(f, "invalid leap second table line {line}")
+            }
+            LeapTableError::MissingField(field) => {
+                write!(f, "leap second table has no {field}")
+            }
+            LeapTableError::TooManyEntries { capacity } => {
+                write!(f, "leap second table has more than {capacity} entries")
+            }
+            LeapTableError::HashMismatch => write!(f, "leap second table hash does not match"),
+            LeapTableError::Expired => write!(f, "leap second table has expired"),
+        }
+    }
+}
+
+impl core::error::Error for LeapTableError {}
@@ -0,0 +1,162 @@
+//! Clock filter for the samples of a single server.
+//!
+//! This implements the clock filter algorithm from RFC 5905. The last eight samples are kept in a
+//! shift register, and the sample with the lowest round-trip delay is chosen as the best estimate
+//! of the server's offset, since it is the one least affected by queuing in the network. The
+//! dispersion of each sample grows over time to account for the frequency tolerance of the local
+//! clock.
+
+use crate::{
+    protocol::{NtpDuration, Timestamp},
+    sample::{frequency_tolerance, SntpSample},
+};
+
+/// Dispersion of an empty stage, and the maximum dispersion of any sample.
+const MAX_DISPERSION: NtpDuration = NtpDuration::from_seconds(16);
+
+/// A sample stored in the clock filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterSample {
+    pub offset: NtpDuration,
+    pub delay: NtpDuration,
+    pub dispersion: NtpDuration,
+    /// Local time at which the sample was taken.
+    pub time: Timestamp,
+}
+
+impl From<&SntpSample> for FilterSample {
+    fn from(sample: &SntpSample) -> Self {
+        FilterSample {
+            offset: sample.offset,
+            delay: sample.delay,
+            dispersion: sample.dispersion,
+            time: sample.time,
+        }
+    }
+}
+
+/// Current estimate of a server's offset produced by the clock filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilterOutput {
+    /// Offset of the sample with the lowest delay.
+    pub offset: NtpDuration,
+    /// Delay of the sample with the lowest delay.
+    pub delay: NtpDuration,
+    /// Dispersion of all of the samples, weighted towards those with the lowest delay.
+    pub dispersion: NtpDuration,
+    /// Root mean square of the differences between the offset of each sample and
+    /// [`FilterOutput::offset`].
+    pub jitter: NtpDuration,
+    /// Local time at which the chosen sample was taken.
+    pub time: Timestamp,
+}
+
+/// Shift register of the last [`ClockFilter::STAGES`] samples from a server.
+#[derive(Debug, Clone)]
+pub struct ClockFilter {
+    stages: [Option<FilterSample>; ClockFilter::STAGES],
+    last_update: Option<Timestamp>,
+    output: Option<FilterOutput>,
+    local_precision: i8,
+}
+
+impl ClockFilter {
+    /// Number of samples kept by the filter.
+    pub const STAGES: usize = 8;
+
+    /// Creates an empty filter. `local_precision` is the precision of the local clock as a power
+    /// of two in seconds (see [`NtpClock::precision`](crate::NtpClock::precision)) and is used
+    /// as the lower bound for jitter.
+    pub const fn new(local_precision: i8) -> Self {
+        ClockFilter {
+            stages: [None; Self::STAGES],
+            last_update: None,
+            output: None,
+            local_precision,
+        }
+    }
+
+    /// Adds a sample from [`sntp_query`](crate::sntp_query) to the filter.
+    ///
+    /// Returns the new output of the filter if it changed. The output only changes when the best
+    /// sample is newer than the one that was last returned, so the same sample is never used
+    /// twice.
+    pub fn add_sample(&mut self, sample: &SntpSample) -> Option<FilterOutput> {
+        self.add(FilterSample::from(sample))
+    }
+
+    /// Adds a sample to the filter. See [`ClockFilter::add_sample`].
+    pub fn add(&mut self, sample: FilterSample) -> Option<FilterOutput> {
+        // Age the existing samples and shift them along to make room for the new one.
+        let elapsed = self.last_update.map_or(NtpDuration::ZERO, |last| {
+            (sample.time - last).max(NtpDuration::ZERO)
+        });
+        for stage in self.stages.iter_mut().flatten() {
+            stage.dispersion =
+                (stage.dispersion + frequency_tolerance(elapsed)).min(MAX_DISPERSION);
+        }
+        self.stages.rotate_right(1);
+        self.stages[0] = Some(FilterSample {
+            dispersion: sample.dispersion.min(MAX_DISPERSION),
+            ..sample
+        });
+        self.last_update = Some(sample.time);
+
+        // Sort by delay with empty stages at the end.
+        let mut sorted = self.stages;
+        sorted.sort_unstable_by_key(|stage| stage.map_or(NtpDuration::MAX, |s| s.delay));
+        let best = sorted[0].expect("filter contains at least one sample");
+
+        let mut dispersion = NtpDuration::ZERO;
+        for (i, stage) in sorted.iter().enumerate() {
+            let stage_dispersion = stage.map_or(MAX_DISPERSION, |s| s.dispersion);
+            dispersion += NtpDuration(stage_dispersion.0 >> (i + 1));
+        }
+
+        let jitter = NtpDuration::rms(
+            sorted[1..]
+                .iter()
+                .flatten()
+                .map(|s| s.offset.saturating_sub(best.offset)),
+        )
+        .max(NtpDuration::from_log2(self.local_precision));
+
+        let output = FilterOutput {
+            offset: best.offset,
+            delay: best.delay,
+            dispersion,
+            jitter,
+            time: best.time,
+        };
+
+        let previous = self.output.replace(output);
+        match previous {
+            Some(previous) if (output.time - previous.time) <= NtpDuration::ZERO => None,
+            _ => Some(output),
+        }
+    }
+
+    /// Returns the current output of the filter, if it has any samples.
+    pub fn output(&self) -> Option<FilterOutput> {
+        self.output
+    }
+
+    /// Dispersion of the current output aged to the local time `now`.
+    pub fn dispersion_at(&self, now: Timestamp) -> Option<NtpDuration> {
+        let last_update = self.last_update?;
+        self.output.map(|output| {
+            let elapsed = (now - last_update).max(NtpDuration::ZERO);
+            (output.dispersion + frequency_tolerance(elapsed)).min(MAX_DISPERSION)
+        })
+    }
+
+    /// Returns the samples in the filter from newest to oldest.
+    pub fn samples(&self) -> impl Iterator<Item = &FilterSample> {
+        self.stages.iter().flatten()
+    }
+
+    /// Removes all samples from the filter.
+    pub fn clear(&mut self) {
+        *self = Self::new(self.local_precision);
+    }
+}
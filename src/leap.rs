@@ -0,0 +1,567 @@
+//! Leap seconds.
+//!
+//! NTP timestamps, like UNIX time, do not count leap seconds: when one is inserted the last
+//! second of the day is repeated, and servers step their clocks back by a second at midnight UTC.
+//! Servers announce an upcoming leap second in the leap indicator of their replies, usually
+//! during the month or the day before it.
+//!
+//! A [`LeapHandler`] tracks a pending leap second, either announced by servers through
+//! [`LeapHandler::handle_sample`] or scheduled directly, e.g. from a [`LeapTable`]. It corrects
+//! the time of a clock that has not applied the leap second yet, either by stepping it at midnight
+//! like NTP servers do, or by smearing the leap second linearly over a longer period so that the
+//! time never jumps and no second is repeated:
+//!
+//! ```
+//! use barentp::leap::{LeapHandler, LeapKind, LeapMode, LeapSecond};
+//! use barentp::{NtpDuration, Timestamp};
+//!
+//! // 1 January 2017, after the leap second inserted at the end of 2016.
+//! let midnight = Timestamp::new(3_692_217_600, 0);
+//! let mut leap = LeapHandler::new(LeapMode::SMEAR_24H);
+//! leap.schedule(Some(LeapSecond { at: midnight, kind: LeapKind::Insert }));
+//!
+//! // Half of the leap second has been smeared by midnight.
+//! assert_eq!(leap.offset_at(midnight), NtpDuration::from_millis(-500));
+//! ```
+//!
+//! A [`LeapTable`] parses the IETF `leap-seconds.list` file published by the IERS and shipped with
+//! tzdata, e.g. as `/usr/share/zoneinfo/leap-seconds.list`. It gives the TAI-UTC offset at any
+//! time covered by the table, converts between UTC, TAI and GPS time, and gives the next leap
+//! second to schedule on a [`LeapHandler`]:
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use barentp::leap::{LeapEntry, LeapHandler, LeapMode, LeapTable};
+//! use barentp::{NtpClock, SystemClock};
+//!
+//! let text = std::fs::read_to_string("/usr/share/zoneinfo/leap-seconds.list")?;
+//! let now = SystemClock.now();
+//! let mut entries = [LeapEntry::EMPTY; 64];
+//! let table = LeapTable::parse(&text, now, &mut entries)?;
+//! println!("TAI-UTC: {:?}", table.tai_offset(now));
+//!
+//! let mut leap = LeapHandler::new(LeapMode::Step);
+//! leap.schedule(table.next_leap(now));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    clock::NtpClock,
+    digest::{digest_eq, Sha1},
+    error::LeapTableError,
+    protocol::{LeapIndicator, NtpDuration, Timestamp},
+    sample::SntpSample,
+};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Whether a leap second is inserted or deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeapKind {
+    /// The last minute of the day has 61 seconds, which moves UTC back relative to a clock that
+    /// ignores leap seconds.
+    Insert,
+    /// The last minute of the day has 59 seconds. This has never happened so far.
+    Delete,
+}
+
+impl LeapKind {
+    /// Correction to a clock that ignores the leap second once it has happened.
+    fn correction(self) -> NtpDuration {
+        match self {
+            LeapKind::Insert => NtpDuration::from_seconds(-1),
+            LeapKind::Delete => NtpDuration::from_seconds(1),
+        }
+    }
+}
+
+/// A leap second at the end of a UTC day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LeapSecond {
+    /// Midnight UTC at the end of the day with the leap second, i.e. the first second after it.
+    pub at: Timestamp,
+    pub kind: LeapKind,
+}
+
+/// How a [`LeapHandler`] applies a leap second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeapMode {
+    /// Step the time by a second at midnight, like NTP servers do.
+    Step,
+    /// Spread the leap second linearly over `duration`, centered on midnight.
+    Smear { duration: NtpDuration },
+}
+
+impl LeapMode {
+    /// Smears leap seconds from noon to noon UTC, which is what Google's public NTP servers do.
+    pub const SMEAR_24H: LeapMode = LeapMode::Smear {
+        duration: NtpDuration::from_seconds(SECONDS_PER_DAY as i32),
+    };
+}
+
+/// Tracks a pending leap second and corrects the time around it.
+#[derive(Debug, Clone)]
+pub struct LeapHandler {
+    mode: LeapMode,
+    pending: Option<LeapSecond>,
+    /// Whether the pending leap second was announced by servers rather than scheduled directly.
+    announced: bool,
+}
+
+impl LeapHandler {
+    pub fn new(mode: LeapMode) -> Self {
+        LeapHandler {
+            mode,
+            pending: None,
+            announced: false,
+        }
+    }
+
+    pub fn mode(&self) -> LeapMode {
+        self.mode
+    }
+
+    /// The leap second that has been announced or scheduled, if any.
+    pub fn pending(&self) -> Option<LeapSecond> {
+        self.pending
+    }
+
+    /// Schedules a leap second directly, or cancels the pending one with `None`. A leap second
+    /// scheduled this way is not cancelled by servers that do not announce it.
+    pub fn schedule(&mut self, leap: Option<LeapSecond>) {
+        self.pending = leap;
+        self.announced = false;
+    }
+
+    /// Updates the pending leap second from the leap indicator of a server, received at local
+    /// time `now`.
+    ///
+    /// A warning schedules a leap second at the end of the current month, since leap seconds only
+    /// happen at the end of a month and servers announce them at most a month ahead. A server
+    /// without a warning cancels a leap second announced by servers, unless it has already
+    /// started to be applied. Servers that are not synchronized are ignored.
+    pub fn handle_leap_indicator(&mut self, leap_indicator: LeapIndicator, now: Timestamp) {
+        let kind = match leap_indicator {
+            LeapIndicator::LastMinuteHas61Seconds => LeapKind::Insert,
+            LeapIndicator::LastMinuteHas59Seconds => LeapKind::Delete,
+            LeapIndicator::NoWarning => {
+                let started = |leap| now - self.start(leap) >= NtpDuration::ZERO;
+                if self.announced && !self.pending.is_some_and(started) {
+                    self.pending = None;
+                }
+                return;
+            }
+            LeapIndicator::AlarmCondition => return,
+        };
+
+        let at = end_of_month(now);
+        if self.pending != Some(LeapSecond { at, kind }) {
+            self.pending = Some(LeapSecond { at, kind });
+            self.announced = true;
+        }
+    }
+
+    /// Updates the pending leap second from a sample, see [`LeapHandler::handle_leap_indicator`].
+    pub fn handle_sample(&mut self, sample: &SntpSample) {
+        self.handle_leap_indicator(sample.leap_indicator, sample.time);
+    }
+
+    /// Correction to add to time `t` of a clock that has not applied the pending leap second.
+    ///
+    /// This is zero before the leap second and one second, backwards for an inserted leap
+    /// second, once it has been applied.
+    pub fn offset_at(&self, t: Timestamp) -> NtpDuration {
+        let Some(leap) = self.pending else {
+            return NtpDuration::ZERO;
+        };
+        let correction = leap.kind.correction();
+
+        match self.mode {
+            LeapMode::Step if t - leap.at < NtpDuration::ZERO => NtpDuration::ZERO,
+            LeapMode::Step => correction,
+            LeapMode::Smear { duration } => {
+                let elapsed = t - self.start(leap);
+                if elapsed <= NtpDuration::ZERO {
+                    NtpDuration::ZERO
+                } else if elapsed >= duration {
+                    correction
+                } else {
+                    let smeared =
+                        i128::from(correction.0) * i128::from(elapsed.0) / i128::from(duration.0);
+                    NtpDuration(smeared as i64)
+                }
+            }
+        }
+    }
+
+    /// Time `t` of a clock that has not applied the pending leap second, corrected for it.
+    pub fn corrected(&self, t: Timestamp) -> Timestamp {
+        t + self.offset_at(t)
+    }
+
+    /// The current time of `clock`, corrected for the pending leap second.
+    pub fn now<C>(&self, clock: &C) -> Timestamp
+    where
+        C: NtpClock + ?Sized,
+    {
+        self.corrected(clock.now())
+    }
+
+    /// Returns true if the pending leap second is being smeared at time `t`.
+    pub fn is_smearing(&self, t: Timestamp) -> bool {
+        match (self.mode, self.pending) {
+            (LeapMode::Smear { duration }, Some(leap)) => {
+                let elapsed = t - self.start(leap);
+                elapsed > NtpDuration::ZERO && elapsed < duration
+            }
+            _ => false,
+        }
+    }
+
+    /// Once the pending leap second has been fully applied at time `t`, clears it and returns the
+    /// correction, so that the underlying clock can be stepped by it, e.g. with
+    /// [`ClockControl::step`](crate::ClockControl::step).
+    pub fn take_completed(&mut self, t: Timestamp) -> Option<NtpDuration> {
+        let leap = self.pending?;
+        let end = match self.mode {
+            LeapMode::Step => leap.at,
+            LeapMode::Smear { duration } => self.start(leap) + duration,
+        };
+        if t - end < NtpDuration::ZERO {
+            return None;
+        }
+        self.pending = None;
+        Some(leap.kind.correction())
+    }
+
+    /// Leap indicator that a server using this handler should send at time `t`.
+    ///
+    /// A smeared leap second is not announced, since clients of a smearing server never see the
+    /// leap second itself.
+    pub fn leap_indicator(&self, t: Timestamp) -> LeapIndicator {
+        match (self.mode, self.pending) {
+            (LeapMode::Step, Some(leap))
+                if t - leap.at < NtpDuration::ZERO && end_of_month(t) == leap.at =>
+            {
+                match leap.kind {
+                    LeapKind::Insert => LeapIndicator::LastMinuteHas61Seconds,
+                    LeapKind::Delete => LeapIndicator::LastMinuteHas59Seconds,
+                }
+            }
+            _ => LeapIndicator::NoWarning,
+        }
+    }
+
+    /// Time at which applying `leap` starts.
+    fn start(&self, leap: LeapSecond) -> Timestamp {
+        match self.mode {
+            LeapMode::Step => leap.at,
+            LeapMode::Smear { duration } => leap.at - duration / 2,
+        }
+    }
+}
+
+/// An entry of a [`LeapTable`]: the TAI-UTC offset from `at` onwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LeapEntry {
+    pub at: Timestamp,
+    /// TAI-UTC in seconds.
+    pub tai_offset: i32,
+}
+
+impl LeapEntry {
+    pub const EMPTY: LeapEntry = LeapEntry {
+        at: Timestamp::new(0, 0),
+        tai_offset: 0,
+    };
+}
+
+/// A leap second table in the IETF `leap-seconds.list` format.
+///
+/// Entries are stored in a caller-provided slice, which needs room for every line of the table:
+/// 28 entries as of 2017, when the last leap second was inserted.
+#[derive(Debug, Clone, Copy)]
+pub struct LeapTable<'a> {
+    entries: &'a [LeapEntry],
+    updated: Timestamp,
+    expires: Timestamp,
+}
+
+impl<'a> LeapTable<'a> {
+    /// Parses `text` into `entries`, and checks its SHA-1 hash and that it has not expired at
+    /// time `now`.
+    pub fn parse(
+        text: &str,
+        now: Timestamp,
+        entries: &'a mut [LeapEntry],
+    ) -> Result<Self, LeapTableError> {
+        // The hash covers the digits of the update time, the expiry date and the entries, in the
+        // order that they appear in, without whitespace or comments.
+        let mut sha1 = Sha1::new();
+        let mut updated = None;
+        let mut expires = None;
+        let mut hash = None;
+        let mut len = 0;
+
+        for (index, line) in text.lines().enumerate() {
+            let invalid = LeapTableError::InvalidLine { line: index + 1 };
+
+            if let Some(value) = line.strip_prefix("#$") {
+                let field = single_field(value).ok_or(invalid)?;
+                updated = Some(ntp_seconds(field).ok_or(invalid)?);
+                sha1.update(field.as_bytes());
+            } else if let Some(value) = line.strip_prefix("#@") {
+                let field = single_field(value).ok_or(invalid)?;
+                expires = Some(ntp_seconds(field).ok_or(invalid)?);
+                sha1.update(field.as_bytes());
+            } else if let Some(value) = line.strip_prefix("#h") {
+                hash = Some(parse_hash(value).ok_or(invalid)?);
+            } else if !line.starts_with('#') {
+                let data = line.split('#').next().unwrap_or_default();
+                let mut fields = data.split_whitespace();
+                let (at_field, offset_field) = match (fields.next(), fields.next(), fields.next()) {
+                    (None, _, _) => continue,
+                    (Some(at), Some(offset), None) => (at, offset),
+                    _ => return Err(invalid),
+                };
+                let at = ntp_seconds(at_field).ok_or(invalid)?;
+                let tai_offset = offset_field.parse().map_err(|_| invalid)?;
+                if len > 0 && entries[len - 1].at.utc_seconds() >= at.utc_seconds() {
+                    return Err(invalid);
+                }
+
+                let capacity = entries.len();
+                let entry = entries
+                    .get_mut(len)
+                    .ok_or(LeapTableError::TooManyEntries { capacity })?;
+                *entry = LeapEntry { at, tai_offset };
+                len += 1;
+                sha1.update(at_field.as_bytes());
+                sha1.update(offset_field.as_bytes());
+            }
+        }
+
+        let updated = updated.ok_or(LeapTableError::MissingField("update time"))?;
+        let expires = expires.ok_or(LeapTableError::MissingField("expiry date"))?;
+        let hash = hash.ok_or(LeapTableError::MissingField("hash"))?;
+        if len == 0 {
+            return Err(LeapTableError::MissingField("entries"));
+        }
+        if !digest_eq(&sha1.finalize(), &hash) {
+            return Err(LeapTableError::HashMismatch);
+        }
+
+        let table = LeapTable {
+            entries: &entries[..len],
+            updated,
+            expires,
+        };
+        if table.is_expired(now) {
+            return Err(LeapTableError::Expired);
+        }
+        Ok(table)
+    }
+
+    pub fn entries(&self) -> &'a [LeapEntry] {
+        self.entries
+    }
+
+    /// When the table was last updated.
+    pub fn updated(&self) -> Timestamp {
+        self.updated
+    }
+
+    /// When the table expires. Leap seconds after this time may be missing from it.
+    pub fn expires(&self) -> Timestamp {
+        self.expires
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        now.utc_seconds() >= self.expires.utc_seconds()
+    }
+
+    /// TAI-UTC in seconds at UTC time `t`, or `None` before the first entry of the table.
+    pub fn tai_offset(&self, t: Timestamp) -> Option<i32> {
+        self.entry_at(t.utc_seconds()).map(|entry| entry.tai_offset)
+    }
+
+    /// Converts UTC time `t` to TAI, as a timestamp that counts TAI seconds since the NTP epoch.
+    pub fn utc_to_tai(&self, t: Timestamp) -> Option<Timestamp> {
+        let tai = self.utc_to_unix_tai(t)?;
+        Some(Timestamp::from_utc_fraction(tai.seconds, tai.fraction))
+    }
+
+    /// Converts a TAI timestamp from [`LeapTable::utc_to_tai`] back to UTC.
+    ///
+    /// An inserted leap second has no UTC timestamp of its own, it converts to the midnight that
+    /// follows it, like in UNIX time.
+    pub fn tai_to_utc(&self, tai: Timestamp) -> Option<Timestamp> {
+        self.unix_tai_to_utc(UnixTaiTime {
+            seconds: tai.utc_seconds(),
+            fraction: tai.seconds_fraction(),
+        })
+    }
+
+    /// Converts UTC time `t` to TAI seconds since the UNIX epoch, like Linux's `CLOCK_TAI`.
+    pub fn utc_to_unix_tai(&self, t: Timestamp) -> Option<UnixTaiTime> {
+        let seconds = t.utc_seconds();
+        Some(UnixTaiTime {
+            seconds: seconds + i64::from(self.entry_at(seconds)?.tai_offset),
+            fraction: t.seconds_fraction(),
+        })
+    }
+
+    /// Converts TAI seconds since the UNIX epoch back to UTC, see [`LeapTable::tai_to_utc`].
+    pub fn unix_tai_to_utc(&self, tai: UnixTaiTime) -> Option<Timestamp> {
+        let index = self.entries.partition_point(|entry| {
+            entry.at.utc_seconds() + i64::from(entry.tai_offset) <= tai.seconds
+        });
+        let entry = self.entries.get(index.checked_sub(1)?)?;
+        let seconds = tai.seconds - i64::from(entry.tai_offset);
+        Some(Timestamp::from_utc_fraction(seconds, tai.fraction))
+    }
+
+    /// Converts UTC time `t` to GPS time, or `None` before the GPS epoch.
+    pub fn utc_to_gps(&self, t: Timestamp) -> Option<GpsTime> {
+        let seconds = self.utc_to_unix_tai(t)?.seconds - GpsTime::EPOCH_UNIX_TAI;
+        Some(GpsTime {
+            week: u32::try_from(seconds.div_euclid(GpsTime::SECONDS_PER_WEEK)).ok()?,
+            seconds: seconds.rem_euclid(GpsTime::SECONDS_PER_WEEK) as u32,
+            fraction: t.seconds_fraction(),
+        })
+    }
+
+    /// Converts GPS time back to UTC, see [`LeapTable::tai_to_utc`].
+    pub fn gps_to_utc(&self, gps: GpsTime) -> Option<Timestamp> {
+        if i64::from(gps.seconds) >= GpsTime::SECONDS_PER_WEEK {
+            return None;
+        }
+        self.unix_tai_to_utc(UnixTaiTime {
+            seconds: i64::from(gps.week) * GpsTime::SECONDS_PER_WEEK
+                + i64::from(gps.seconds)
+                + GpsTime::EPOCH_UNIX_TAI,
+            fraction: gps.fraction,
+        })
+    }
+
+    /// The entry that applies at `utc` seconds since the UNIX epoch.
+    fn entry_at(&self, utc: i64) -> Option<&LeapEntry> {
+        let index = self
+            .entries
+            .partition_point(|entry| entry.at.utc_seconds() <= utc);
+        self.entries.get(index.checked_sub(1)?)
+    }
+
+    /// The first leap second after time `t`, if the table has one.
+    pub fn next_leap(&self, t: Timestamp) -> Option<LeapSecond> {
+        let t = t.utc_seconds();
+        self.entries
+            .windows(2)
+            .filter(|pair| pair[1].tai_offset != pair[0].tai_offset)
+            .find(|pair| pair[1].at.utc_seconds() > t)
+            .map(|pair| LeapSecond {
+                at: pair[1].at,
+                kind: if pair[1].tai_offset > pair[0].tai_offset {
+                    LeapKind::Insert
+                } else {
+                    LeapKind::Delete
+                },
+            })
+    }
+}
+
+/// TAI time as seconds since the UNIX epoch, which is 1 January 1970 in TAI rather than UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnixTaiTime {
+    pub seconds: i64,
+    /// Fraction of a second in units of 2^-32 seconds, like [`Timestamp::seconds_fraction`].
+    pub fraction: u32,
+}
+
+/// GPS time as a week number and time of week.
+///
+/// GPS time started at midnight UTC on 6 January 1980 and has not counted leap seconds since,
+/// which keeps it 19 seconds behind TAI. The week number is not truncated to the 10 or 13 bits
+/// that satellites broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GpsTime {
+    pub week: u32,
+    /// Whole seconds since the start of the week, below [`GpsTime::SECONDS_PER_WEEK`].
+    pub seconds: u32,
+    /// Fraction of a second in units of 2^-32 seconds, like [`Timestamp::seconds_fraction`].
+    pub fraction: u32,
+}
+
+impl GpsTime {
+    pub const SECONDS_PER_WEEK: i64 = 7 * SECONDS_PER_DAY;
+
+    /// The GPS epoch in [`UnixTaiTime`] seconds.
+    const EPOCH_UNIX_TAI: i64 = 315_964_800 + 19;
+}
+
+/// Returns the only whitespace separated field of `value`.
+fn single_field(value: &str) -> Option<&str> {
+    let mut fields = value.split_whitespace();
+    let field = fields.next()?;
+    fields.next().is_none().then_some(field)
+}
+
+fn ntp_seconds(field: &str) -> Option<Timestamp> {
+    field.parse().ok().map(|seconds| Timestamp::new(seconds, 0))
+}
+
+/// Parses the five hexadecimal words of a SHA-1 hash, which may be written without leading zeros.
+fn parse_hash(value: &str) -> Option<[u8; Sha1::OUTPUT_SIZE]> {
+    let mut hash = [0; Sha1::OUTPUT_SIZE];
+    let mut words = value.split_whitespace();
+    for chunk in hash.chunks_exact_mut(4) {
+        let word = u32::from_str_radix(words.next()?, 16).ok()?;
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    words.next().is_none().then_some(hash)
+}
+
+/// Midnight UTC at the end of the month that `t` is in.
+fn end_of_month(t: Timestamp) -> Timestamp {
+    let days = t.utc_seconds().div_euclid(SECONDS_PER_DAY);
+    let (year, month, _) = civil_from_days(days);
+    let (year, month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    Timestamp::from_utc(days_from_civil(year, month, 1) * SECONDS_PER_DAY, 0)
+}
+
+/// Converts days since the UNIX epoch to a proleptic Gregorian `(year, month, day)`.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, with eras of 400 years starting on 1 March.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a proleptic Gregorian date to days since the UNIX epoch.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
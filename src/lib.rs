@@ -17,14 +17,76 @@
 //!
 //! Then you can use one of [`sntp_get_transmit_timestamp`](sntp_get_transmit_timestamp) or
 //! [`sntp_get_transmit_timestamp`](nonblocking::sntp_get_transmit_timestamp) to get the current time from
-//! an NTP server. DNS lookup functionality is not provided by this library.
+//! an NTP server. With the `std` feature, the [`dns`](dns) module resolves server host names and
+//! connects sockets to them, including to servers from pools such as `pool.ntp.org`.
+//!
+//! To measure how far the local clock is from a server's clock, use [`sntp_query`](sntp_query)
+//! with an [`NtpClock`](NtpClock) implementation. [`sntp_query_servers`](sntp_query_servers)
+//! queries several servers and uses the [`select`](select) module to discard those that
+//! disagree with the majority. Repeated samples from the same server can be smoothed with the
+//! [`filter`](filter) module, and a local clock can be kept synchronized over time with the
+//! [`discipline`](discipline) module. For a long-running client that keeps polling a server, see
+//! the [`association`](association) module.
+//!
+//! A single unconnected socket can be used to query several servers by implementing
+//! [`NtpAddressedTransport`](NtpAddressedTransport) and using [`sntp_query_to`](sntp_query_to)
+//! and its siblings, which discard replies that come from anywhere but the server queried.
+//!
+//! On Linux the `linux-clock` feature provides [`LinuxClock`](linux::LinuxClock), which steps and
+//! slews the system clock through `clock_settime` and `adjtimex`, and the `linux-timestamping`
+//! feature provides [`TimestampingSocket`](linux::TimestampingSocket), which uses kernel receive
+//! timestamps for more accurate offsets.
+//!
+//! Clients on a LAN can also listen for the time sent by a broadcast server with the
+//! [`broadcast`](broadcast) module, which also provides the broadcast server. Broadcast packets
+//! can be authenticated with a symmetric key from the [`auth`](auth) module.
+//!
+//! The [`server`](server) module answers requests from other clients, for serving the time of a
+//! local reference clock. Servers can also synchronize to each other as symmetric peers with the
+//! [`peer`](peer) module. The state of other NTP servers can be monitored with the `ntpq`-style
+//! control messages of the [`control`](control) module.
+//!
+//! Leap seconds announced by servers can be applied to the local time with a step or a smear
+//! using the [`leap`](leap) module.
+//!
+//! The `testing` feature provides the [`testing`](testing) module, a simulated network with a
+//! simulated server for testing code that uses this library without a real network.
+//!
+//! The packet format itself is available in the [`protocol`](protocol) module for applications
+//! that need to encode or decode messages directly.
 
 #![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+pub mod association;
+pub mod auth;
 mod blocking;
+pub mod broadcast;
+pub mod clock;
+pub mod control;
+mod digest;
+pub mod discipline;
+#[cfg(feature = "std")]
+pub mod dns;
 pub mod error;
+pub mod filter;
+pub mod leap;
+#[cfg(all(
+    any(feature = "linux-clock", feature = "linux-timestamping"),
+    target_os = "linux"
+))]
+pub mod linux;
 pub mod nonblocking;
-mod protocol;
+pub mod peer;
+pub mod protocol;
+mod sample;
+pub mod select;
+pub mod server;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use blocking::*;
-pub use protocol::Timestamp;
+#[cfg(feature = "std")]
+pub use clock::SystemClock;
+pub use clock::{ClockControl, NtpClock};
+pub use protocol::{NtpDuration, Timestamp};
+pub use sample::SntpSample;
@@ -0,0 +1,20 @@
+//! Linux system clock and socket support.
+//!
+//! With the `linux-clock` feature, [`LinuxClock`] reads a kernel clock with `clock_gettime`, steps
+//! it with `clock_settime` and slews it or adjusts its frequency with `adjtimex`. Changing the
+//! clock requires the `CAP_SYS_TIME` capability, unless the clock is in dry-run mode, in which case
+//! adjustments are only recorded.
+//!
+//! With the `linux-timestamping` feature, [`TimestampingSocket`] is a UDP transport that asks the
+//! kernel to timestamp received packets. This avoids adding the scheduling delay between a packet
+//! arriving and the client reading the clock after `recv` returns to the measured delay.
+
+#[cfg(feature = "linux-clock")]
+mod clock;
+#[cfg(feature = "linux-timestamping")]
+mod timestamping;
+
+#[cfg(feature = "linux-clock")]
+pub use clock::{Adjustments, LinuxClock};
+#[cfg(feature = "linux-timestamping")]
+pub use timestamping::{TimestampingMode, TimestampingSocket};
@@ -0,0 +1,168 @@
+use crate::{
+    clock::{ClockControl, NtpClock},
+    protocol::{NtpDuration, Timestamp},
+};
+
+/// Largest frequency correction accepted by the kernel in seconds per second (500 PPM).
+const MAX_FREQUENCY: f64 = 500e-6;
+
+/// Adjustments that have been requested from a [`LinuxClock`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    /// Sum of all of the steps.
+    pub stepped: NtpDuration,
+    /// Sum of all of the slews.
+    pub slewed: NtpDuration,
+    /// Last frequency correction that was set, in seconds per second.
+    pub frequency: Option<f64>,
+}
+
+/// [`NtpClock`] and [`ClockControl`] implementation for a Linux kernel clock.
+#[derive(Debug, Clone)]
+pub struct LinuxClock {
+    clock_id: libc::clockid_t,
+    dry_run: bool,
+    adjustments: Adjustments,
+}
+
+impl LinuxClock {
+    /// The system-wide realtime clock (`CLOCK_REALTIME`).
+    pub fn realtime() -> Self {
+        LinuxClock {
+            clock_id: libc::CLOCK_REALTIME,
+            dry_run: false,
+            adjustments: Adjustments::default(),
+        }
+    }
+
+    /// Never changes the clock, adjustments are only recorded in [`LinuxClock::adjustments`].
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Adjustments that have been requested so far, whether or not this is a dry run.
+    pub fn adjustments(&self) -> &Adjustments {
+        &self.adjustments
+    }
+
+    /// Reads the kernel's current frequency correction in seconds per second. This does not
+    /// require any privileges.
+    pub fn kernel_frequency(&self) -> std::io::Result<f64> {
+        let mut timex = empty_timex();
+        adjtimex(&mut timex)?;
+        Ok(timex.freq as f64 / 65536.0 / 1e6)
+    }
+
+    /// Returns true if the kernel reports that the clock is synchronized.
+    pub fn kernel_synchronized(&self) -> std::io::Result<bool> {
+        let mut timex = empty_timex();
+        adjtimex(&mut timex).map(|state| state != libc::TIME_ERROR)
+    }
+
+    fn gettime(&self) -> std::io::Result<libc::timespec> {
+        let mut timespec = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `timespec` is a valid pointer for the duration of the call.
+        if unsafe { libc::clock_gettime(self.clock_id, &mut timespec) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(timespec)
+    }
+}
+
+impl NtpClock for LinuxClock {
+    fn now(&self) -> Timestamp {
+        let timespec = self
+            .gettime()
+            .expect("clock_gettime failed for a valid clock");
+        Timestamp::from_utc(timespec.tv_sec as _, timespec.tv_nsec as _)
+    }
+
+    fn precision(&self) -> i8 {
+        let mut resolution = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `resolution` is a valid pointer for the duration of the call.
+        if unsafe { libc::clock_getres(self.clock_id, &mut resolution) } != 0 {
+            return -20;
+        }
+
+        let nanos = (resolution.tv_sec as u64 * 1_000_000_000 + resolution.tv_nsec as u64).max(1);
+        // log2(nanos / 1e9), rounded up.
+        (64 - (nanos - 1).leading_zeros()) as i8 - 30
+    }
+}
+
+impl ClockControl for LinuxClock {
+    type Error = std::io::Error;
+
+    fn step(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        if !self.dry_run {
+            let now = self.gettime()?;
+            let nanos = now.tv_sec as i128 * 1_000_000_000
+                + now.tv_nsec as i128
+                + offset.as_nanos() as i128;
+            let target = libc::timespec {
+                tv_sec: nanos.div_euclid(1_000_000_000) as libc::time_t,
+                tv_nsec: nanos.rem_euclid(1_000_000_000) as _,
+            };
+            // SAFETY: `target` is a valid pointer for the duration of the call.
+            if unsafe { libc::clock_settime(self.clock_id, &target) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        self.adjustments.stepped += offset;
+        Ok(())
+    }
+
+    fn slew(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        if !self.dry_run {
+            let mut timex = empty_timex();
+            timex.modes = libc::ADJ_OFFSET_SINGLESHOT;
+            timex.offset = offset.as_micros() as _;
+            adjtimex(&mut timex)?;
+        }
+
+        self.adjustments.slewed += offset;
+        Ok(())
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Self::Error> {
+        let frequency = frequency.clamp(-MAX_FREQUENCY, MAX_FREQUENCY);
+        if !self.dry_run {
+            // The kernel expects parts per million with a 16 bit fraction.
+            let mut timex = empty_timex();
+            timex.modes = libc::ADJ_FREQUENCY;
+            timex.freq = (frequency * 1e6 * 65536.0) as _;
+            adjtimex(&mut timex)?;
+        }
+
+        self.adjustments.frequency = Some(frequency);
+        Ok(())
+    }
+}
+
+fn empty_timex() -> libc::timex {
+    // SAFETY: `timex` is a plain C struct for which all zeroes is a valid value, and modes = 0
+    // makes adjtimex read-only.
+    unsafe { core::mem::zeroed() }
+}
+
+/// Calls `adjtimex`, returning the clock state on success.
+fn adjtimex(timex: &mut libc::timex) -> std::io::Result<libc::c_int> {
+    // SAFETY: `timex` is a valid pointer for the duration of the call.
+    let state = unsafe { libc::adjtimex(timex) };
+    if state < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(state)
+}
@@ -0,0 +1,181 @@
+use std::{
+    io,
+    mem::{self, MaybeUninit},
+    net::UdpSocket,
+    os::fd::AsRawFd,
+};
+
+use crate::{blocking::NtpTransport, nonblocking::NtpTransportAsync, protocol::Timestamp};
+
+/// Source of the receive timestamps of a [`TimestampingSocket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampingMode {
+    /// Timestamps taken by the kernel when it receives the packet (`SO_TIMESTAMPNS`).
+    Software,
+    /// Timestamps taken by the network interface (`SO_TIMESTAMPING`), falling back to software
+    /// timestamps for packets that the interface did not timestamp.
+    ///
+    /// Hardware timestamping must also be enabled on the interface itself, e.g. with
+    /// `hwstamp_ctl`, and the interface's clock must be synchronized to the system clock, e.g.
+    /// with `phc2sys`, for the timestamps to be comparable with the local clock.
+    Hardware,
+}
+
+/// UDP transport that reads kernel or hardware receive timestamps.
+///
+/// The timestamps are taken from `CLOCK_REALTIME`, so they are only meaningful if the
+/// [`NtpClock`](crate::NtpClock) used with this transport also reads the system time, such as
+/// [`SystemClock`](crate::SystemClock).
+#[derive(Debug)]
+pub struct TimestampingSocket {
+    socket: UdpSocket,
+    mode: TimestampingMode,
+}
+
+impl TimestampingSocket {
+    /// Enables receive timestamps on a socket, which should already be connected to a server.
+    pub fn new(socket: UdpSocket, mode: TimestampingMode) -> io::Result<Self> {
+        match mode {
+            TimestampingMode::Software => {
+                setsockopt(&socket, libc::SO_TIMESTAMPNS, 1)?;
+            }
+            TimestampingMode::Hardware => {
+                let flags = libc::SOF_TIMESTAMPING_RX_HARDWARE
+                    | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+                    | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+                    | libc::SOF_TIMESTAMPING_SOFTWARE;
+                setsockopt(&socket, libc::SO_TIMESTAMPING, flags as libc::c_int)?;
+            }
+        }
+        Ok(TimestampingSocket { socket, mode })
+    }
+
+    pub fn mode(&self) -> TimestampingMode {
+        self.mode
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+
+    /// Receives a packet along with the time it was received, if the kernel provided one.
+    pub fn recv_timestamped(&self, buffer: &mut [u8]) -> io::Result<(usize, Option<Timestamp>)> {
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr().cast(),
+            iov_len: buffer.len(),
+        };
+        // Large enough for one SCM_TIMESTAMPING message (three timespecs), aligned for cmsghdr.
+        let mut control = [MaybeUninit::<u64>::uninit(); 16];
+
+        // SAFETY: `msghdr` is a plain C struct for which all zeroes is a valid value.
+        let mut message: libc::msghdr = unsafe { mem::zeroed() };
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = mem::size_of_val(&control) as _;
+
+        // SAFETY: `message` points to `buffer` and `control`, which are both valid for writes of
+        // the lengths given and outlive the call.
+        let len = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut message, 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut timestamp = None;
+        // SAFETY: `message` was filled in by `recvmsg` and its control buffer is still alive, so
+        // the control message headers and their data are valid to read.
+        unsafe {
+            let mut header = libc::CMSG_FIRSTHDR(&message);
+            while !header.is_null() {
+                let data = libc::CMSG_DATA(header);
+                if (*header).cmsg_level == libc::SOL_SOCKET {
+                    match (*header).cmsg_type {
+                        libc::SCM_TIMESTAMPNS => {
+                            let time = data.cast::<libc::timespec>().read_unaligned();
+                            timestamp = timestamp_from_timespec(time);
+                        }
+                        libc::SCM_TIMESTAMPING => {
+                            // Software, deprecated and raw hardware timestamps, in that order.
+                            let times = data.cast::<[libc::timespec; 3]>().read_unaligned();
+                            timestamp = timestamp_from_timespec(times[2])
+                                .or_else(|| timestamp_from_timespec(times[0]));
+                        }
+                        _ => {}
+                    }
+                }
+                header = libc::CMSG_NXTHDR(&message, header);
+            }
+        }
+
+        Ok((len as usize, timestamp))
+    }
+}
+
+impl NtpTransport for TimestampingSocket {
+    type SendError = io::Error;
+    type RecvError = io::Error;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        NtpTransport::send(&self.socket, buffer)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        self.recv_timestamped(buffer).map(|(len, _)| len)
+    }
+
+    fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        TimestampingSocket::recv_timestamped(self, buffer)
+    }
+}
+
+impl NtpTransportAsync for TimestampingSocket {
+    type SendError = io::Error;
+    type RecvError = io::Error;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        NtpTransport::send(&self.socket, buffer)
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        self.recv_timestamped(buffer).map(|(len, _)| len)
+    }
+
+    async fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        TimestampingSocket::recv_timestamped(self, buffer)
+    }
+}
+
+/// Converts a kernel timestamp, which is all zeroes if the timestamp is not available.
+fn timestamp_from_timespec(time: libc::timespec) -> Option<Timestamp> {
+    if time.tv_sec == 0 && time.tv_nsec == 0 {
+        return None;
+    }
+    Some(Timestamp::from_utc(time.tv_sec as _, time.tv_nsec as _))
+}
+
+fn setsockopt(socket: &UdpSocket, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    // SAFETY: `value` is a valid `c_int` for the duration of the call and its size is passed.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&value as *const libc::c_int).cast(),
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
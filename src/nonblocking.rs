@@ -1,8 +1,20 @@
 use crate::{
-    error::Error,
+    association::Association,
+    broadcast::{BroadcastClient, Broadcaster},
+    clock::NtpClock,
+    control::{ControlMessage, ControlReassembler, ControlResponse},
+    error::{Error, SntpProtocolError},
+    peer::SymmetricPeer,
     protocol::{SntpMessage, Timestamp},
+    sample::{ClientRequest, Exchange, Reply, SntpSample},
+    select::MultiQuery,
+    server::{ClientAddress, SntpServer},
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
 };
-use core::future::Future;
 
 pub trait NtpTransportAsync {
     type SendError;
@@ -13,6 +25,73 @@ pub trait NtpTransportAsync {
         &self,
         buffer: &mut [u8],
     ) -> impl Future<Output = Result<usize, Self::RecvError>> + Send;
+
+    /// Sends a packet like [`NtpTransportAsync::send`] and returns the time it left if the
+    /// transport knows it, see [`NtpTransport::send_timestamped`](crate::NtpTransport::send_timestamped).
+    fn send_timestamped(
+        &self,
+        buffer: &[u8],
+    ) -> impl Future<Output = Result<Option<Timestamp>, Self::SendError>> + Send {
+        let send = self.send(buffer);
+        async move { send.await.map(|()| None) }
+    }
+
+    /// Receives a packet like [`NtpTransportAsync::recv`] along with the time it arrived if the
+    /// transport knows it, see [`NtpTransport::recv_timestamped`](crate::NtpTransport::recv_timestamped).
+    fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<(usize, Option<Timestamp>), Self::RecvError>> + Send {
+        let recv = self.recv(buffer);
+        async move { recv.await.map(|len| (len, None)) }
+    }
+}
+
+/// Transport that is not connected to a single server, see
+/// [`NtpAddressedTransport`](crate::NtpAddressedTransport).
+pub trait NtpAddressedTransportAsync {
+    /// Address of a server, e.g. `std::net::SocketAddr`.
+    type Address: PartialEq;
+    type SendError;
+    type RecvError;
+
+    fn send_to(
+        &self,
+        buffer: &[u8],
+        address: &Self::Address,
+    ) -> impl Future<Output = Result<(), Self::SendError>> + Send;
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<(usize, Self::Address), Self::RecvError>> + Send;
+
+    /// Returns true if a packet received from `source` comes from `address`, see
+    /// [`NtpAddressedTransport::is_same_address`](crate::NtpAddressedTransport::is_same_address).
+    fn is_same_address(source: &Self::Address, address: &Self::Address) -> bool {
+        source == address
+    }
+
+    /// Sends a packet like [`NtpAddressedTransportAsync::send_to`] and returns the time it left
+    /// if the transport knows it, see [`NtpTransport::send_timestamped`](crate::NtpTransport::send_timestamped).
+    fn send_to_timestamped(
+        &self,
+        buffer: &[u8],
+        address: &Self::Address,
+    ) -> impl Future<Output = Result<Option<Timestamp>, Self::SendError>> + Send {
+        let send = self.send_to(buffer, address);
+        async move { send.await.map(|()| None) }
+    }
+
+    /// Receives a packet like [`NtpAddressedTransportAsync::recv_from`] along with the time it
+    /// arrived if the transport knows it, see [`NtpTransport::recv_timestamped`](crate::NtpTransport::recv_timestamped).
+    fn recv_from_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<(usize, Self::Address, Option<Timestamp>), Self::RecvError>> + Send
+    {
+        let recv = self.recv_from(buffer);
+        async move { recv.await.map(|(len, address)| (len, address, None)) }
+    }
 }
 
 async fn sntp_send_and_recv<T>(
@@ -21,15 +100,18 @@ async fn sntp_send_and_recv<T>(
 where
     T: NtpTransportAsync,
 {
-    let mut buf = [0; SntpMessage::BUFFER_SIZE];
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
     let mut msg = SntpMessage::new_v4();
     msg.write_to_buffer(&mut buf)?;
-    transport.send(&buf).await.map_err(Error::TransportSend)?;
     transport
+        .send(&buf[..SntpMessage::BUFFER_SIZE])
+        .await
+        .map_err(Error::TransportSend)?;
+    let len = transport
         .recv(&mut buf)
         .await
         .map_err(Error::TransportRecv)?;
-    msg.read_from_buffer(&buf)?;
+    msg.read_from_packet(&buf[..len.min(buf.len())])?;
     Ok(msg)
 }
 
@@ -43,6 +125,405 @@ where
     Ok(msg.transmit_timestamp)
 }
 
+/// Queries a server and computes the offset of its clock relative to `clock`, along with the
+/// round-trip delay and error estimates.
+///
+/// Replies that do not answer this request are discarded while waiting for the one that does, so
+/// the transport should have a receive timeout in case the reply is lost.
+pub async fn sntp_query<T, C>(
+    transport: &T,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    query(transport, clock, None)
+        .await
+        .map(|reply| reply.sample)
+}
+
+/// Queries a server like [`sntp_query`], asking for an interleaved reply if `previous` is given.
+async fn query<T, C>(
+    transport: &T,
+    clock: &C,
+    previous: Option<Exchange>,
+) -> Result<Reply, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let mut request = ClientRequest::new(clock, &mut buf, previous)?;
+    let t1 = transport
+        .send_timestamped(&buf[..SntpMessage::BUFFER_SIZE])
+        .await
+        .map_err(Error::TransportSend)?;
+    request.sent(t1);
+
+    // Replies to other requests, e.g. late or duplicated replies to an earlier one, are
+    // discarded like any other bogus packet.
+    loop {
+        let (len, t4) = transport
+            .recv_timestamped(&mut buf)
+            .await
+            .map_err(Error::TransportRecv)?;
+        let t4 = t4.unwrap_or_else(|| clock.now());
+        match request.process_reply(&buf[..len.min(buf.len())], t4) {
+            Err(SntpProtocolError::OriginateTimestampMismatch) => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Queries all of the servers concurrently and combines their offsets using the clock selection
+/// algorithm from RFC 5905, discarding servers that disagree with the majority.
+pub async fn sntp_query_servers<T, C, const N: usize>(
+    transports: &[T; N],
+    clock: &C,
+) -> MultiQuery<T::SendError, T::RecvError, N>
+where
+    T: NtpTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let samples = join_all(core::array::from_fn(|i| sntp_query(&transports[i], clock))).await;
+    MultiQuery::new(samples)
+}
+
+/// Sends a control message request and waits for all fragments of the response, which is
+/// reassembled in `buffer`.
+///
+/// Packets that are not part of the response, such as late responses to earlier requests, are
+/// ignored. The transport should have a receive timeout in case a fragment is lost.
+pub async fn sntp_control<'b, T>(
+    transport: &T,
+    request: &ControlMessage<'_>,
+    buffer: &'b mut [u8],
+) -> Result<ControlResponse<'b>, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransportAsync,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = request.write_to_buffer(&mut buf)?;
+    transport
+        .send(&buf[..len])
+        .await
+        .map_err(Error::TransportSend)?;
+
+    let mut reassembler = ControlReassembler::new(request, buffer);
+    loop {
+        let len = transport
+            .recv(&mut buf)
+            .await
+            .map_err(Error::TransportRecv)?;
+        let fragment = match ControlMessage::read_from_packet(&buf[..len.min(buf.len())]) {
+            Ok(fragment) => fragment,
+            Err(_) => continue,
+        };
+        match reassembler.add_fragment(&fragment) {
+            Ok(true) => break,
+            Ok(false) | Err(SntpProtocolError::StalePacket) => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(reassembler.finish().expect("response is complete"))
+}
+
+/// Polls the server of `association` and records the result in it.
+///
+/// This should be called whenever [`Association::is_poll_due`] returns true.
+pub async fn sntp_poll<T, C>(
+    association: &mut Association,
+    transport: &T,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let result = query(transport, clock, association.interleaved_exchange())
+        .await
+        .map(|reply| association.record_reply(reply));
+    association.handle_result(&result, clock.now());
+    result
+}
+
+/// Queries the server at `address` through an unconnected transport, like [`sntp_query`].
+///
+/// Packets received from any other address, e.g. late replies from other servers queried through
+/// the same transport, are discarded.
+pub async fn sntp_query_to<T, C>(
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    query_to(transport, address, clock, None)
+        .await
+        .map(|reply| reply.sample)
+}
+
+/// Queries a server like [`sntp_query_to`], asking for an interleaved reply if `previous` is
+/// given.
+async fn query_to<T, C>(
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+    previous: Option<Exchange>,
+) -> Result<Reply, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let mut request = ClientRequest::new(clock, &mut buf, previous)?;
+    let t1 = transport
+        .send_to_timestamped(&buf[..SntpMessage::BUFFER_SIZE], address)
+        .await
+        .map_err(Error::TransportSend)?;
+    request.sent(t1);
+
+    loop {
+        let (len, source, t4) = transport
+            .recv_from_timestamped(&mut buf)
+            .await
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, address) {
+            continue;
+        }
+        let t4 = t4.unwrap_or_else(|| clock.now());
+        match request.process_reply(&buf[..len.min(buf.len())], t4) {
+            Err(SntpProtocolError::OriginateTimestampMismatch) => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Queries each of the servers in turn through one unconnected transport and combines their
+/// offsets, like [`sntp_query_servers`].
+///
+/// Unlike [`sntp_query_servers`] the servers are not queried concurrently, because every query
+/// would receive, and discard, the replies meant for the others.
+pub async fn sntp_query_servers_to<T, C, const N: usize>(
+    transport: &T,
+    addresses: &[T::Address; N],
+    clock: &C,
+) -> MultiQuery<T::SendError, T::RecvError, N>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut samples: [Option<_>; N] = core::array::from_fn(|_| None);
+    for (sample, address) in samples.iter_mut().zip(addresses) {
+        *sample = Some(sntp_query_to(transport, address, clock).await);
+    }
+    MultiQuery::new(samples.map(|sample| sample.expect("server was not queried")))
+}
+
+/// Polls the server of `association` at `address` through an unconnected transport and records
+/// the result in it, like [`sntp_poll`].
+pub async fn sntp_poll_to<T, C>(
+    association: &mut Association,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<SntpSample, Error<T::SendError, T::RecvError>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let result = query_to(
+        transport,
+        address,
+        clock,
+        association.interleaved_exchange(),
+    )
+    .await
+    .map(|reply| association.record_reply(reply));
+    association.handle_result(&result, clock.now());
+    result
+}
+
+type AddressedError<T> = Error<
+    <T as NtpAddressedTransportAsync>::SendError,
+    <T as NtpAddressedTransportAsync>::RecvError,
+>;
+
+/// Receives one request from `transport` and answers it with `server`.
+///
+/// Returns the address of the client that was answered, or `None` if the request was dropped.
+pub async fn sntp_serve_one<T, C>(
+    server: &mut SntpServer<'_>,
+    transport: &T,
+    clock: &C,
+) -> Result<Option<T::Address>, AddressedError<T>>
+where
+    T: NtpAddressedTransportAsync,
+    T::Address: ClientAddress,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let (len, client, received) = transport
+        .recv_from_timestamped(&mut buf)
+        .await
+        .map_err(Error::TransportRecv)?;
+    let received = received.unwrap_or_else(|| clock.now());
+
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    let request = &buf[..len.min(buf.len())];
+    let Some(len) = server.handle_request(request, client.ip_addr(), received, clock, &mut reply)
+    else {
+        return Ok(None);
+    };
+    let sent_at = transport
+        .send_to_timestamped(&reply[..len], &client)
+        .await
+        .map_err(Error::TransportSend)?;
+    server.sent(sent_at.unwrap_or_else(|| clock.now()));
+    Ok(Some(client))
+}
+
+/// Sends the next broadcast packet of `broadcaster` to `destination`, which is usually a subnet
+/// broadcast address or a multicast group.
+///
+/// This should be called whenever [`Broadcaster::time_until_next`] has passed.
+pub async fn sntp_broadcast<T, C>(
+    broadcaster: &mut Broadcaster<'_>,
+    transport: &T,
+    destination: &T::Address,
+    clock: &C,
+) -> Result<(), AddressedError<T>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = broadcaster.build_packet(clock, &mut buf)?;
+    transport
+        .send_to(&buf[..len], destination)
+        .await
+        .map_err(Error::TransportSend)
+}
+
+/// Waits for the next broadcast packet from `server` and passes it to `client`, see
+/// [`BroadcastClient::handle_packet`].
+///
+/// Packets received from any other address are discarded.
+pub async fn sntp_recv_broadcast<T, C>(
+    client: &mut BroadcastClient<'_>,
+    transport: &T,
+    server: &T::Address,
+    clock: &C,
+) -> Result<Option<SntpSample>, AddressedError<T>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    loop {
+        let (len, source, received) = transport
+            .recv_from_timestamped(&mut buf)
+            .await
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, server) {
+            continue;
+        }
+        let received = received.unwrap_or_else(|| clock.now());
+        let sample = client.handle_packet(&buf[..len.min(buf.len())], received)?;
+        return Ok(sample);
+    }
+}
+
+/// Sends the next packet of `peer` to the other side of the association at `address`.
+///
+/// This should be called whenever [`SymmetricPeer::time_until_poll`] has passed.
+pub async fn sntp_peer_send<T, C>(
+    peer: &mut SymmetricPeer<'_>,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<(), AddressedError<T>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = peer.build_packet(clock, &mut buf)?;
+    let sent_at = transport
+        .send_to_timestamped(&buf[..len], address)
+        .await
+        .map_err(Error::TransportSend)?;
+    peer.sent(sent_at);
+    Ok(())
+}
+
+/// Waits for the next packet from the other side of the association at `address`, ignoring
+/// packets from any other source.
+///
+/// Returns a sample if the packet completed an exchange, see [`SymmetricPeer::handle_packet`].
+pub async fn sntp_peer_recv<T, C>(
+    peer: &mut SymmetricPeer<'_>,
+    transport: &T,
+    address: &T::Address,
+    clock: &C,
+) -> Result<Option<SntpSample>, AddressedError<T>>
+where
+    T: NtpAddressedTransportAsync,
+    C: NtpClock + ?Sized,
+{
+    let mut buf = [0; SntpMessage::MAX_PACKET_SIZE];
+    loop {
+        let (len, source, received) = transport
+            .recv_from_timestamped(&mut buf)
+            .await
+            .map_err(Error::TransportRecv)?;
+        if !T::is_same_address(&source, address) {
+            continue;
+        }
+        let received = received.unwrap_or_else(|| clock.now());
+        let sample = peer.handle_packet(&buf[..len.min(buf.len())], received)?;
+        return Ok(sample);
+    }
+}
+
+/// Polls all of the futures concurrently and returns their outputs in order.
+pub(crate) async fn join_all<F, const N: usize>(futures: [F; N]) -> [F::Output; N]
+where
+    F: Future,
+{
+    let mut futures = core::pin::pin!(futures);
+    let mut outputs: [Option<F::Output>; N] = core::array::from_fn(|_| None);
+
+    core::future::poll_fn(|cx: &mut Context<'_>| {
+        let mut pending = false;
+        for (i, output) in outputs.iter_mut().enumerate() {
+            if output.is_some() {
+                continue;
+            }
+
+            // SAFETY: the futures are never moved out of the pinned array, so pinning is
+            // structural for its elements.
+            let future: Pin<&mut F> = unsafe { futures.as_mut().map_unchecked_mut(|f| &mut f[i]) };
+            match future.poll(cx) {
+                Poll::Ready(value) => *output = Some(value),
+                Poll::Pending => pending = true,
+            }
+        }
+
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(core::array::from_fn(|i| {
+                outputs[i].take().expect("future output is missing")
+            }))
+        }
+    })
+    .await
+}
+
 #[cfg(feature = "std")]
 impl NtpTransportAsync for std::net::UdpSocket {
     type SendError = std::io::Error;
@@ -60,3 +541,25 @@ impl NtpTransportAsync for std::net::UdpSocket {
         self.recv(buffer)
     }
 }
+
+#[cfg(feature = "std")]
+impl NtpAddressedTransportAsync for std::net::UdpSocket {
+    type Address = std::net::SocketAddr;
+    type SendError = std::io::Error;
+    type RecvError = std::io::Error;
+
+    async fn send_to(&self, buffer: &[u8], address: &Self::Address) -> Result<(), Self::SendError> {
+        self.send_to(buffer, *address).map(|_| ())
+    }
+
+    async fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Self::Address), Self::RecvError> {
+        self.recv_from(buffer)
+    }
+
+    fn is_same_address(source: &Self::Address, address: &Self::Address) -> bool {
+        crate::blocking::is_same_socket_addr(source, address)
+    }
+}
@@ -0,0 +1,327 @@
+//! Symmetric active and passive peer associations.
+//!
+//! Two servers that peer with each other exchange packets in symmetric mode, so that either of
+//! them can synchronize to the other, e.g. a redundant pair that falls back to the other server
+//! when its own reference fails. Each side sends packets at its own poll interval, and every
+//! packet carries the timestamps of the last packet received from the other side, which completes
+//! an exchange just like a server's reply does.
+//!
+//! A [`SymmetricPeer`] in [`PeerMode::Active`] starts sending packets on its own. One in
+//! [`PeerMode::Passive`] waits until it has heard from an active peer. Neither performs any I/O
+//! itself, they are driven by [`sntp_peer_send`](crate::sntp_peer_send) and
+//! [`sntp_peer_recv`](crate::sntp_peer_recv):
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example() -> std::io::Result<()> {
+//! use barentp::peer::{PeerMode, SymmetricPeer};
+//! use barentp::server::ServerConfig;
+//! use barentp::{NtpClock, SystemClock};
+//!
+//! let other = "192.168.1.2:123".parse().unwrap();
+//! let socket = std::net::UdpSocket::bind("0.0.0.0:123")?;
+//! socket.set_read_timeout(Some(std::time::Duration::from_secs(1)))?;
+//!
+//! let clock = SystemClock;
+//! let mut peer = SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 6, clock.precision());
+//! loop {
+//!     if peer.time_until_poll(clock.now()) == Some(std::time::Duration::ZERO) {
+//!         let _ = barentp::sntp_peer_send(&mut peer, &socket, &other, &clock);
+//!     }
+//!     if let Ok(Some(sample)) = barentp::sntp_peer_recv(&mut peer, &socket, &other, &clock) {
+//!         println!("offset: {}", sample.offset);
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! # Interleaved mode
+//!
+//! In basic mode each packet carries the time it was stamped just before it was sent, which is
+//! slightly earlier than when it actually left. With [`SymmetricPeer::with_interleaved`] a peer
+//! instead sends the time its previous packet actually left, as reported by the transport, and
+//! completes the exchange one packet later. Both peers need to enable it. A peer whose
+//! interleaved packet is not answered in interleaved mode falls back to basic mode until the
+//! other side sends it an interleaved packet.
+
+use crate::{
+    auth::SymmetricKey,
+    clock::NtpClock,
+    error::SntpProtocolError,
+    protocol::{Mode, NtpDuration, SntpMessage, Timestamp, Version},
+    sample::{check_synchronized, SntpSample},
+    server::ServerConfig,
+};
+
+/// Which side of a symmetric association a [`SymmetricPeer`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerMode {
+    /// Sends packets to the peer without waiting to hear from it first.
+    Active,
+    /// Only starts sending packets once an active peer has sent one.
+    Passive,
+}
+
+impl PeerMode {
+    fn mode(self) -> Mode {
+        match self {
+            PeerMode::Active => Mode::SymmetricActive,
+            PeerMode::Passive => Mode::SymmetricPassive,
+        }
+    }
+}
+
+/// State of one side of a symmetric association with another server.
+#[derive(Debug, Clone)]
+pub struct SymmetricPeer<'a> {
+    mode: PeerMode,
+    config: ServerConfig,
+    poll: i8,
+    local_precision: i8,
+    key: Option<SymmetricKey<'a>>,
+    interleaved: bool,
+    /// Transmit timestamp of the last packet from the peer.
+    org: Option<Timestamp>,
+    /// Receive timestamp of the last packet from the peer.
+    remote_rec: Option<Timestamp>,
+    /// Local time the last packet from the peer was received.
+    rec: Option<Timestamp>,
+    /// Transmit timestamp of the last packet sent to the peer, or `None` if it was interleaved.
+    xmt: Option<Timestamp>,
+    /// When that packet actually left, if the transport reported it.
+    sent_at: Option<Timestamp>,
+    /// When the packet answered by the last packet from the peer left, and when the peer
+    /// received it. The peer's next interleaved packet completes this exchange.
+    exchange: Option<(Timestamp, Timestamp)>,
+    /// Set when an interleaved packet was not answered in interleaved mode.
+    fallback: bool,
+    last_interleaved: bool,
+    next_poll: Option<Timestamp>,
+}
+
+impl<'a> SymmetricPeer<'a> {
+    /// Creates a peer that sends a packet every `2^poll` seconds and advertises `config` as its
+    /// own synchronization state. `local_precision` is the precision of the local clock, see
+    /// [`NtpClock::precision`](crate::NtpClock::precision).
+    pub fn new(mode: PeerMode, config: ServerConfig, poll: i8, local_precision: i8) -> Self {
+        SymmetricPeer {
+            mode,
+            config,
+            poll,
+            local_precision,
+            key: None,
+            interleaved: false,
+            org: None,
+            remote_rec: None,
+            rec: None,
+            xmt: None,
+            sent_at: None,
+            exchange: None,
+            fallback: false,
+            last_interleaved: false,
+            next_poll: None,
+        }
+    }
+
+    /// Authenticates sent packets with `key` and only accepts packets authenticated with it.
+    pub fn with_key(mut self, key: SymmetricKey<'a>) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Sends packets in interleaved mode once the peer has answered one, see the
+    /// [module documentation](self#interleaved-mode).
+    pub fn with_interleaved(mut self) -> Self {
+        self.interleaved = true;
+        self
+    }
+
+    /// Returns true if the last packet from the peer was sent in interleaved mode.
+    pub fn is_interleaved(&self) -> bool {
+        self.last_interleaved
+    }
+
+    pub fn mode(&self) -> PeerMode {
+        self.mode
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Replaces the synchronization state sent to the peer, e.g. after the local clock was
+    /// synchronized to another source.
+    pub fn set_config(&mut self, config: ServerConfig) {
+        self.config = config;
+    }
+
+    pub fn poll(&self) -> i8 {
+        self.poll
+    }
+
+    /// Time to wait from local time `now` until the next packet should be sent to the peer, or
+    /// `None` if this is a passive peer that has not heard from its peer yet.
+    pub fn time_until_poll(&self, now: Timestamp) -> Option<core::time::Duration> {
+        if self.mode == PeerMode::Passive && self.org.is_none() {
+            return None;
+        }
+
+        let wait = self.next_poll.map_or(NtpDuration::ZERO, |next| {
+            (next - now).max(NtpDuration::ZERO)
+        });
+        Some(core::time::Duration::from_nanos(wait.as_nanos() as u64))
+    }
+
+    /// Writes the next packet to the peer to `buffer`, stamped with the current time of `clock`,
+    /// and schedules the one after it. Returns the length of the packet.
+    pub fn build_packet<C>(
+        &mut self,
+        clock: &C,
+        buffer: &mut [u8],
+    ) -> Result<usize, SntpProtocolError>
+    where
+        C: NtpClock + ?Sized,
+    {
+        let config = &self.config;
+        let now = clock.now();
+
+        // An interleaved packet carries the time the previous one left instead of its own, and
+        // refers back to the peer's packet by the time it was received.
+        let zero = Timestamp::new(0, 0);
+        let interleaved = match (self.remote_rec, self.sent_at) {
+            (Some(remote_rec), Some(sent_at)) if self.interleaved && !self.fallback => {
+                (remote_rec != zero).then_some((remote_rec, sent_at))
+            }
+            _ => None,
+        };
+        let (originate_timestamp, transmit_timestamp) =
+            interleaved.unwrap_or((self.org.unwrap_or(zero), now));
+        let msg = SntpMessage {
+            leap_indicator: config.leap_indicator,
+            version: Version::V4,
+            mode: self.mode.mode(),
+            stratum: config.stratum,
+            poll: self.poll as u8,
+            precision: clock.precision() as u8,
+            root_delay: config.root_delay.to_ntp_short(),
+            root_dispersion: config.root_dispersion.to_ntp_short(),
+            reference_identifier: u32::from_be_bytes(config.reference_identifier),
+            reference_timestamp: config.reference_timestamp.unwrap_or(now),
+            originate_timestamp,
+            receive_timestamp: self.rec.unwrap_or(zero),
+            transmit_timestamp,
+        };
+        msg.write_to_buffer(buffer)?;
+
+        let len = match &self.key {
+            Some(key) => key.sign(buffer, SntpMessage::BUFFER_SIZE)?,
+            None => SntpMessage::BUFFER_SIZE,
+        };
+        self.xmt = interleaved.is_none().then_some(now);
+        self.sent_at = Some(now);
+        self.next_poll = Some(now + NtpDuration::from_log2(self.poll));
+        Ok(len)
+    }
+
+    /// Records the time the last packet was sent as reported by the transport, if it reported
+    /// one.
+    pub fn sent(&mut self, sent_at: Option<Timestamp>) {
+        if let Some(sent_at) = sent_at {
+            self.sent_at = Some(sent_at);
+        }
+    }
+
+    /// Validates a packet from the peer that was received at local time `received`.
+    ///
+    /// Returns a sample if the packet completed an exchange, or `None` if the peer has not
+    /// received a packet from this side yet or the first interleaved packet has no exchange to
+    /// complete. Packets from a peer that is not synchronized are still used to answer it, but
+    /// return [`SntpProtocolError::ServerUnsynchronized`].
+    pub fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        received: Timestamp,
+    ) -> Result<Option<SntpSample>, SntpProtocolError> {
+        let mut msg = SntpMessage::new_v4();
+        msg.read_from_packet(packet)?;
+        if let Some(key) = &self.key {
+            key.verify(packet)?;
+        }
+
+        // Two passive peers would wait for each other forever.
+        let valid_mode = match self.mode {
+            PeerMode::Active => {
+                msg.mode == Mode::SymmetricActive || msg.mode == Mode::SymmetricPassive
+            }
+            PeerMode::Passive => msg.mode == Mode::SymmetricActive,
+        };
+        if !valid_mode {
+            return Err(SntpProtocolError::UnexpectedSntpMode(msg.mode));
+        }
+
+        if msg.transmit_timestamp.0 == 0 {
+            return Err(SntpProtocolError::ServerUnsynchronized);
+        }
+        // Consecutive interleaved packets may carry the same transmit timestamp as a basic one
+        // that was sent just before them, but not the same receive timestamp as well.
+        if self.org == Some(msg.transmit_timestamp)
+            && self.remote_rec == Some(msg.receive_timestamp)
+        {
+            return Err(SntpProtocolError::StalePacket);
+        }
+
+        // An interleaved packet refers back to the last packet from this side by the time the
+        // peer's previous packet was received.
+        let interleaved = self.interleaved
+            && msg.originate_timestamp.0 != 0
+            && Some(msg.originate_timestamp) == self.rec;
+        let basic = msg.originate_timestamp.0 != 0 && Some(msg.originate_timestamp) == self.xmt;
+        let exchange = self.exchange.take();
+
+        // The peer's timestamps are remembered even if the packet is not usable for a sample, so
+        // that the next packet sent lets the peer complete its own exchange.
+        self.org = Some(msg.transmit_timestamp);
+        self.remote_rec = Some(msg.receive_timestamp);
+        self.rec = Some(received);
+        self.last_interleaved = interleaved;
+        if interleaved {
+            self.fallback = false;
+        }
+
+        if msg.originate_timestamp.0 == 0 {
+            return Ok(None);
+        }
+        if !interleaved && !basic {
+            // The peer did not understand the last interleaved packet.
+            self.fallback |= self.xmt.is_none() && self.sent_at.is_some();
+            return Err(SntpProtocolError::OriginateTimestampMismatch);
+        }
+        if msg.receive_timestamp.0 != 0 {
+            self.exchange = self.sent_at.map(|sent_at| (sent_at, msg.receive_timestamp));
+        }
+        check_synchronized(&msg)?;
+
+        if interleaved {
+            // The transmit timestamp is when the peer's previous packet left, which answered the
+            // packet it received when the exchange was recorded.
+            return Ok(exchange.map(|(t1, t2)| {
+                SntpSample::from_exchange(
+                    &msg,
+                    t1,
+                    t2,
+                    msg.transmit_timestamp,
+                    msg.originate_timestamp,
+                    self.local_precision,
+                )
+            }));
+        }
+        Ok(Some(SntpSample::from_exchange(
+            &msg,
+            self.sent_at.unwrap_or(msg.originate_timestamp),
+            msg.receive_timestamp,
+            msg.transmit_timestamp,
+            received,
+            self.local_precision,
+        )))
+    }
+}
@@ -59,6 +59,10 @@ pub struct SntpMessage {
 impl SntpMessage {
     pub const BUFFER_SIZE: usize = 48;
 
+    /// Size of the largest packet that will be read from a transport, including any extension
+    /// fields and message authentication code that follow the 48 byte header.
+    pub const MAX_PACKET_SIZE: usize = 1024;
+
     pub fn new_v4() -> Self {
         Self {
             leap_indicator: LeapIndicator::NoWarning,
@@ -131,17 +135,7 @@ impl SntpMessage {
             });
         }
 
-        self.mode = match buffer[0] & 0x7 {
-            0 => Mode::Reserved,
-            1 => Mode::SymmetricActive,
-            2 => Mode::SymmetricPassive,
-            3 => Mode::Client,
-            4 => Mode::Server,
-            5 => Mode::Broadcast,
-            6 => Mode::Reserved6,
-            7 => Mode::Reserved7,
-            invalid => return Err(SntpProtocolError::InvalidSntpMode(invalid)),
-        };
+        self.mode = Mode::from_bits(buffer[0]);
 
         self.version = match (buffer[0] >> 3) & 0x7 {
             4 => Version::V4,
@@ -185,10 +179,167 @@ impl SntpMessage {
 
         Ok(())
     }
+
+    /// Reads a complete packet as it was received from a transport.
+    ///
+    /// Unlike [`SntpMessage::read_from_buffer`], `packet` must contain exactly the bytes that were
+    /// received. Packets shorter than [`SntpMessage::BUFFER_SIZE`] are reported as truncated and
+    /// any bytes following the header are parsed as extension fields and/or a MAC.
+    pub fn read_from_packet<'a>(
+        &mut self,
+        packet: &'a [u8],
+    ) -> Result<SntpExtensions<'a>, SntpProtocolError> {
+        if packet.len() < Self::BUFFER_SIZE {
+            return Err(SntpProtocolError::TruncatedSntpPacket {
+                size: packet.len(),
+                expected: Self::BUFFER_SIZE,
+            });
+        }
+
+        self.read_from_buffer(&packet[..Self::BUFFER_SIZE])?;
+        SntpExtensions::parse(&packet[Self::BUFFER_SIZE..])
+    }
+}
+
+/// Extension fields and message authentication code that follow the header of an NTPv4 packet.
+///
+/// Relevant documentation from RFC 7822:
+///
+/// ```text
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |          Field Type           |            Length             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// .                                                               .
+/// .                            Value                              .
+/// .                                                               .
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                       Padding (as needed)                     |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SntpExtensions<'a> {
+    fields: &'a [u8],
+    mac: Option<SntpMac<'a>>,
+}
+
+impl<'a> SntpExtensions<'a> {
+    /// Minimum length of an extension field, including its 4 byte header.
+    const MIN_FIELD_LENGTH: usize = 16;
+
+    fn parse(trailer: &'a [u8]) -> Result<Self, SntpProtocolError> {
+        let mut offset = 0;
+
+        loop {
+            let remaining = &trailer[offset..];
+
+            // A crypto-NAK (4 bytes) or a MAC using a 128-bit or 160-bit digest (20 or 24 bytes)
+            // can only appear at the very end of the packet.
+            if let 0 | 4 | 20 | 24 = remaining.len() {
+                let mac = SntpMac::parse(remaining);
+                return Ok(SntpExtensions {
+                    fields: &trailer[..offset],
+                    mac,
+                });
+            }
+
+            let invalid = SntpProtocolError::InvalidSntpExtensionField {
+                offset: SntpMessage::BUFFER_SIZE + offset,
+            };
+            if remaining.len() < Self::MIN_FIELD_LENGTH {
+                return Err(invalid);
+            }
+
+            let length = u16::from_be_bytes([remaining[2], remaining[3]]) as usize;
+            if length < Self::MIN_FIELD_LENGTH || length % 4 != 0 || length > remaining.len() {
+                return Err(invalid);
+            }
+            offset += length;
+        }
+    }
+
+    /// Returns an iterator over the extension fields in the packet.
+    pub fn fields(&self) -> ExtensionFields<'a> {
+        ExtensionFields {
+            remaining: self.fields,
+        }
+    }
+
+    /// The message authentication code at the end of the packet, if there is one.
+    pub fn mac(&self) -> Option<SntpMac<'a>> {
+        self.mac
+    }
+
+    /// Returns true if the packet had no extension fields and no MAC.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.mac.is_none()
+    }
+}
+
+/// Iterator over the extension fields of a packet. See [`SntpExtensions::fields`].
+#[derive(Debug, Clone)]
+pub struct ExtensionFields<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for ExtensionFields<'a> {
+    type Item = ExtensionField<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        // Lengths were already validated by `SntpExtensions::parse`.
+        let field_type = u16::from_be_bytes([self.remaining[0], self.remaining[1]]);
+        let length = u16::from_be_bytes([self.remaining[2], self.remaining[3]]) as usize;
+        let (field, remaining) = self.remaining.split_at(length);
+        self.remaining = remaining;
+
+        Some(ExtensionField {
+            field_type,
+            value: &field[4..],
+        })
+    }
+}
+
+/// A single extension field. The value includes any padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionField<'a> {
+    pub field_type: u16,
+    pub value: &'a [u8],
+}
+
+/// Message authentication code consisting of a key identifier and a message digest.
+///
+/// A MAC with an empty digest is a crypto-NAK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SntpMac<'a> {
+    pub key_identifier: u32,
+    pub digest: &'a [u8],
+}
+
+impl<'a> SntpMac<'a> {
+    fn parse(buffer: &'a [u8]) -> Option<Self> {
+        if buffer.len() < 4 {
+            return None;
+        }
+
+        Some(SntpMac {
+            key_identifier: u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
+            digest: &buffer[4..],
+        })
+    }
+
+    /// Returns true if this is a crypto-NAK, which is a MAC without a digest.
+    pub fn is_crypto_nak(&self) -> bool {
+        self.digest.is_empty()
+    }
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LeapIndicator {
     NoWarning = 0,
     LastMinuteHas61Seconds = 1,
@@ -198,7 +349,7 @@ pub enum LeapIndicator {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Mode {
     Reserved = 0,
     SymmetricActive = 1,
@@ -206,29 +357,63 @@ pub enum Mode {
     Client = 3,
     Server = 4,
     Broadcast = 5,
-    /// reserved for NTP control message
+    /// NTP control message, see [`control`](crate::control)
     Reserved6 = 6,
     // reserved for private use
     Reserved7 = 7,
 }
 
+impl Mode {
+    /// Decodes the mode from the low three bits of the first byte of a packet.
+    pub(crate) fn from_bits(bits: u8) -> Mode {
+        match bits & 0x7 {
+            0 => Mode::Reserved,
+            1 => Mode::SymmetricActive,
+            2 => Mode::SymmetricPassive,
+            3 => Mode::Client,
+            4 => Mode::Server,
+            5 => Mode::Broadcast,
+            6 => Mode::Reserved6,
+            _ => Mode::Reserved7,
+        }
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Version {
     V4 = 4,
     V3 = 3,
 }
 
 #[repr(transparent)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Timestamp(pub(crate) u64);
 
 impl Timestamp {
-    pub fn new(seconds: u32, fraction: u32) -> Self {
+    /// Seconds between the NTP epoch (1900) and the UNIX epoch (1970).
+    pub(crate) const UNIX_EPOCH_OFFSET: i64 = 2208988800;
+
+    pub const fn new(seconds: u32, fraction: u32) -> Self {
         Self(((seconds as u64) << 32) | (fraction as u64))
     }
 
-    pub(crate) fn to_be_bytes(&self) -> [u8; 8] {
+    /// Creates a timestamp from seconds and nanoseconds since the UNIX epoch.
+    ///
+    /// Times from 2036 onwards wrap around into the next NTP era. See [`Timestamp::msb_set`].
+    pub fn from_utc(seconds: i64, nanos: u32) -> Self {
+        let fraction = ((nanos as u64) << 32) / 1_000_000_000;
+        Self::from_utc_fraction(seconds, fraction as u32)
+    }
+
+    /// Creates a timestamp from seconds since the UNIX epoch and a fraction of a second in units
+    /// of 2^-32 seconds.
+    pub(crate) fn from_utc_fraction(seconds: i64, fraction: u32) -> Self {
+        let seconds = seconds.wrapping_add(Self::UNIX_EPOCH_OFFSET) as u32;
+        Self::new(seconds, fraction)
+    }
+
+    pub(crate) fn to_be_bytes(self) -> [u8; 8] {
         self.0.to_be_bytes()
     }
 
@@ -320,6 +505,295 @@ impl Timestamp {
     }
 }
 
+/// The difference between two timestamps is only meaningful if they are less than 68 years apart,
+/// in which case it is also correct across NTP era boundaries.
+impl core::ops::Sub for Timestamp {
+    type Output = NtpDuration;
+
+    fn sub(self, rhs: Timestamp) -> NtpDuration {
+        NtpDuration(self.0.wrapping_sub(rhs.0) as i64)
+    }
+}
+
+impl core::ops::Add<NtpDuration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: NtpDuration) -> Timestamp {
+        Timestamp(self.0.wrapping_add_signed(rhs.0))
+    }
+}
+
+impl core::ops::Sub<NtpDuration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: NtpDuration) -> Timestamp {
+        Timestamp(self.0.wrapping_add_signed(rhs.0.wrapping_neg()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::time::SystemTime> for Timestamp {
+    fn from(time: std::time::SystemTime) -> Self {
+        match time.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since) => Timestamp::from_utc(since.as_secs() as i64, since.subsec_nanos()),
+            Err(err) => {
+                let before = err.duration();
+                Timestamp::from_utc(0, 0) - NtpDuration::from(before)
+            }
+        }
+    }
+}
+
+/// Signed span of time with the same 32.32 fixed-point resolution as a [`Timestamp`].
+///
+/// This is used for clock offsets, round-trip delays and error estimates. Arithmetic saturates at
+/// [`NtpDuration::MIN`] and [`NtpDuration::MAX`], since durations computed from the timestamps of
+/// a bogus packet can be arbitrarily large.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpDuration(pub(crate) i64);
+
+impl NtpDuration {
+    pub const ZERO: NtpDuration = NtpDuration(0);
+    pub const MAX: NtpDuration = NtpDuration(i64::MAX);
+    pub const MIN: NtpDuration = NtpDuration(i64::MIN);
+
+    /// Creates a duration from whole seconds and a fraction of a second in units of 2^-32.
+    pub const fn new(seconds: i32, fraction: u32) -> Self {
+        Self(((seconds as i64) << 32) | fraction as i64)
+    }
+
+    pub const fn from_seconds(seconds: i32) -> Self {
+        Self((seconds as i64) << 32)
+    }
+
+    pub fn from_millis(millis: i64) -> Self {
+        Self::from_nanos(millis.saturating_mul(1_000_000))
+    }
+
+    pub fn from_micros(micros: i64) -> Self {
+        Self::from_nanos(micros.saturating_mul(1_000))
+    }
+
+    pub fn from_nanos(nanos: i64) -> Self {
+        let fixed =
+            (((nanos as i128) << 32) + 500_000_000 * nanos.signum() as i128) / 1_000_000_000;
+        Self(fixed.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+
+    /// Creates a duration from a number of seconds, saturating at [`NtpDuration::MIN`] and
+    /// [`NtpDuration::MAX`].
+    pub fn from_seconds_f64(seconds: f64) -> Self {
+        Self((seconds * 4294967296.0) as i64)
+    }
+
+    /// Converts the 16.16 fixed-point "NTP short" format used by the root delay and root
+    /// dispersion fields of a packet.
+    pub const fn from_ntp_short(short: u32) -> Self {
+        Self((short as i64) << 16)
+    }
+
+    /// Converts a duration to the 16.16 fixed-point "NTP short" format, saturating at its bounds.
+    pub fn to_ntp_short(self) -> u32 {
+        (self.0 >> 16).clamp(0, u32::MAX as i64) as u32
+    }
+
+    /// Creates a duration of `2^exponent` seconds, as used by the poll and precision fields of a
+    /// packet.
+    pub fn from_log2(exponent: i8) -> Self {
+        let shift = 32 + exponent as i32;
+        match shift {
+            ..0 => Self::ZERO,
+            0..63 => Self(1 << shift),
+            _ => Self::MAX,
+        }
+    }
+
+    /// Whole seconds, rounded towards negative infinity.
+    pub fn seconds(&self) -> i64 {
+        self.0 >> 32
+    }
+
+    /// Fractional part of [`NtpDuration::seconds`].
+    pub fn seconds_fraction(&self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn to_seconds_f64(self) -> f64 {
+        self.0 as f64 / 4294967296.0
+    }
+
+    pub fn as_nanos(&self) -> i64 {
+        (self.0 as i128 * 1_000_000_000 / (1 << 32)) as i64
+    }
+
+    pub fn as_micros(&self) -> i64 {
+        (self.0 as i128 * 1_000_000 / (1 << 32)) as i64
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        (self.0 as i128 * 1_000 / (1 << 32)) as i64
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.saturating_abs())
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Root mean square of a set of durations, or zero if the set is empty.
+    pub(crate) fn rms<I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = NtpDuration>,
+    {
+        let mut count = 0u128;
+        let mut sum = 0u128;
+        for value in values {
+            let magnitude = value.0.unsigned_abs() as u128;
+            sum = sum.saturating_add(magnitude * magnitude);
+            count += 1;
+        }
+
+        if count == 0 {
+            return Self::ZERO;
+        }
+
+        Self(isqrt(sum / count).min(i64::MAX as u128) as i64)
+    }
+}
+
+/// Integer square root, rounded down.
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    // Newton's method, starting from a power of two that is not below the root.
+    let mut x = 1u128 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (x + n / x) / 2;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+impl core::ops::Add for NtpDuration {
+    type Output = NtpDuration;
+
+    fn add(self, rhs: NtpDuration) -> NtpDuration {
+        NtpDuration(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl core::ops::Sub for NtpDuration {
+    type Output = NtpDuration;
+
+    fn sub(self, rhs: NtpDuration) -> NtpDuration {
+        NtpDuration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl core::ops::Neg for NtpDuration {
+    type Output = NtpDuration;
+
+    fn neg(self) -> NtpDuration {
+        NtpDuration(self.0.saturating_neg())
+    }
+}
+
+impl core::ops::Mul<i64> for NtpDuration {
+    type Output = NtpDuration;
+
+    fn mul(self, rhs: i64) -> NtpDuration {
+        NtpDuration(self.0.saturating_mul(rhs))
+    }
+}
+
+impl core::ops::Div<i64> for NtpDuration {
+    type Output = NtpDuration;
+
+    fn div(self, rhs: i64) -> NtpDuration {
+        NtpDuration(self.0.saturating_div(rhs))
+    }
+}
+
+impl core::ops::AddAssign for NtpDuration {
+    fn add_assign(&mut self, rhs: NtpDuration) {
+        *self = *self + rhs;
+    }
+}
+
+impl core::ops::SubAssign for NtpDuration {
+    fn sub_assign(&mut self, rhs: NtpDuration) {
+        *self = *self - rhs;
+    }
+}
+
+impl From<core::time::Duration> for NtpDuration {
+    fn from(duration: core::time::Duration) -> Self {
+        let seconds = duration.as_secs().min(i32::MAX as u64) as i64;
+        let fraction = ((duration.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        NtpDuration((seconds << 32) | fraction as i64)
+    }
+}
+
+impl core::fmt::Display for NtpDuration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:+.6}s", self.to_seconds_f64())
+    }
+}
+
+/// Four character ASCII code carried in the reference identifier of a Kiss-o'-Death packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KissCode(pub [u8; 4]);
+
+impl KissCode {
+    /// Access was denied by the server.
+    pub const DENY: KissCode = KissCode(*b"DENY");
+    /// Access was denied due to the server's local policy.
+    pub const RSTR: KissCode = KissCode(*b"RSTR");
+    /// The client must reduce its polling rate.
+    pub const RATE: KissCode = KissCode(*b"RATE");
+
+    pub fn from_reference_identifier(reference_identifier: u32) -> Self {
+        KissCode(reference_identifier.to_be_bytes())
+    }
+
+    pub fn to_reference_identifier(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// Returns true if the client must stop sending packets to the server that sent this code.
+    pub fn is_access_denied(&self) -> bool {
+        *self == Self::DENY || *self == Self::RSTR
+    }
+}
+
+impl core::fmt::Display for KissCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for &byte in &self.0 {
+            if byte.is_ascii_graphic() {
+                write!(f, "{}", byte as char)?;
+            } else {
+                write!(f, "\\x{byte:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "chrono")]
 impl From<Timestamp> for chrono::NaiveDateTime {
     fn from(timestamp: Timestamp) -> Self {
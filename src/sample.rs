@@ -0,0 +1,266 @@
+use crate::{
+    clock::NtpClock,
+    error::SntpProtocolError,
+    protocol::{KissCode, LeapIndicator, Mode, NtpDuration, SntpMessage, Timestamp},
+};
+
+/// Maximum stratum of a server that is synchronized.
+const MAX_STRATUM: u8 = 15;
+
+/// Frequency tolerance of the local clock (15 PPM), used to grow dispersion over time.
+pub(crate) fn frequency_tolerance(elapsed: NtpDuration) -> NtpDuration {
+    NtpDuration(elapsed.0 / 1_000_000 * 15)
+}
+
+/// Result of a single request/reply exchange with a server.
+///
+/// The four timestamps of the on-wire protocol are:
+///
+/// - T1: local time the request was sent
+/// - T2: server time the request was received
+/// - T3: server time the reply was sent
+/// - T4: local time the reply was received
+#[derive(Debug, Clone, Copy)]
+pub struct SntpSample {
+    /// Offset of the server's clock relative to the local clock, `((T2 - T1) + (T3 - T4)) / 2`.
+    /// A positive offset means the local clock is behind the server.
+    pub offset: NtpDuration,
+    /// Round-trip delay, `(T4 - T1) - (T3 - T2)`.
+    pub delay: NtpDuration,
+    /// Maximum error of this sample due to the precision of both clocks and frequency
+    /// tolerance during the exchange.
+    pub dispersion: NtpDuration,
+    /// Local time at which the reply was received (T4).
+    pub time: Timestamp,
+    pub leap_indicator: LeapIndicator,
+    pub stratum: u8,
+    pub poll: i8,
+    pub precision: i8,
+    pub root_delay: NtpDuration,
+    pub root_dispersion: NtpDuration,
+    pub reference_identifier: u32,
+    pub reference_timestamp: Timestamp,
+    /// Server time at which the reply was sent (T3).
+    pub transmit_timestamp: Timestamp,
+}
+
+impl SntpSample {
+    /// Computes a sample from the four timestamps of an exchange and the reply that carried them.
+    pub(crate) fn from_exchange(
+        reply: &SntpMessage,
+        t1: Timestamp,
+        t2: Timestamp,
+        t3: Timestamp,
+        t4: Timestamp,
+        local_precision: i8,
+    ) -> Self {
+        let offset = NtpDuration(((t2 - t1).0 >> 1) + ((t3 - t4).0 >> 1));
+        let delay = (t4 - t1) - (t3 - t2);
+        Self::from_packet(reply, offset, delay, t4 - t1, t4, local_precision)
+    }
+
+    /// Computes a sample from a broadcast packet received at local time `t4`, given the one-way
+    /// delay from the server.
+    pub(crate) fn from_broadcast(
+        packet: &SntpMessage,
+        t4: Timestamp,
+        one_way_delay: NtpDuration,
+        local_precision: i8,
+    ) -> Self {
+        let offset = (packet.transmit_timestamp - t4) + one_way_delay;
+        let delay = one_way_delay * 2;
+        Self::from_packet(
+            packet,
+            offset,
+            delay,
+            NtpDuration::ZERO,
+            t4,
+            local_precision,
+        )
+    }
+
+    /// Fills in a sample from the server's packet. `elapsed` is the local time that passed while
+    /// the measurement was taken.
+    fn from_packet(
+        packet: &SntpMessage,
+        offset: NtpDuration,
+        delay: NtpDuration,
+        elapsed: NtpDuration,
+        time: Timestamp,
+        local_precision: i8,
+    ) -> Self {
+        let delay = delay.max(NtpDuration::from_log2(local_precision));
+        let precision = packet.precision as i8;
+        let dispersion = NtpDuration::from_log2(precision)
+            + NtpDuration::from_log2(local_precision)
+            + frequency_tolerance(elapsed);
+
+        SntpSample {
+            offset,
+            delay,
+            dispersion,
+            time,
+            leap_indicator: packet.leap_indicator,
+            stratum: packet.stratum,
+            poll: packet.poll as i8,
+            precision,
+            root_delay: NtpDuration::from_ntp_short(packet.root_delay),
+            root_dispersion: NtpDuration::from_ntp_short(packet.root_dispersion),
+            reference_identifier: packet.reference_identifier,
+            reference_timestamp: packet.reference_timestamp,
+            transmit_timestamp: packet.transmit_timestamp,
+        }
+    }
+
+    /// Root synchronization distance, which is the maximum error of [`SntpSample::offset`]
+    /// relative to the primary reference source.
+    pub fn root_distance(&self) -> NtpDuration {
+        (self.root_delay + self.delay) / 2 + self.root_dispersion + self.dispersion
+    }
+}
+
+/// Timestamps of a completed client/server exchange, which the next request in interleaved mode
+/// refers back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Exchange {
+    /// Local time the request was sent (T1).
+    sent: Timestamp,
+    /// Server time the request was received (T2), which identifies the exchange to the server.
+    server_received: Timestamp,
+    /// Local time the reply was received (T4).
+    received: Timestamp,
+}
+
+/// A valid reply to a [`ClientRequest`].
+pub(crate) struct Reply {
+    pub(crate) sample: SntpSample,
+    /// The exchange this reply completed.
+    pub(crate) exchange: Exchange,
+    /// Whether the server answered in interleaved mode, in which case the sample is of the
+    /// previous exchange.
+    pub(crate) interleaved: bool,
+}
+
+/// A client mode request that has been written to a buffer and is waiting for its reply.
+pub(crate) struct ClientRequest {
+    transmit_timestamp: Timestamp,
+    /// When the request actually left, which is the transmit timestamp unless the transport
+    /// reported a more precise one.
+    sent_at: Timestamp,
+    local_precision: i8,
+    /// The previous exchange with the server if the request asks for an interleaved reply.
+    previous: Option<Exchange>,
+}
+
+impl ClientRequest {
+    /// Writes a new request to `buffer`, timestamping it with `clock`.
+    ///
+    /// If `previous` is given, the request asks the server to reply in interleaved mode with the
+    /// precise time it sent its reply to that exchange. Servers that do not support interleaved
+    /// mode reply normally.
+    pub(crate) fn new<C>(
+        clock: &C,
+        buffer: &mut [u8],
+        previous: Option<Exchange>,
+    ) -> Result<Self, SntpProtocolError>
+    where
+        C: NtpClock + ?Sized,
+    {
+        let mut msg = SntpMessage::new_v4();
+        if let Some(previous) = previous {
+            msg.originate_timestamp = previous.server_received;
+            msg.receive_timestamp = previous.received;
+        }
+        msg.transmit_timestamp = clock.now();
+        msg.write_to_buffer(buffer)?;
+
+        Ok(ClientRequest {
+            transmit_timestamp: msg.transmit_timestamp,
+            sent_at: msg.transmit_timestamp,
+            local_precision: clock.precision(),
+            previous,
+        })
+    }
+
+    /// Records the time the request was sent as reported by the transport, if it reported one.
+    pub(crate) fn sent(&mut self, sent_at: Option<Timestamp>) {
+        if let Some(sent_at) = sent_at {
+            self.sent_at = sent_at;
+        }
+    }
+
+    /// Validates a reply to this request, received at local time `t4`, and computes a sample
+    /// from it.
+    pub(crate) fn process_reply(
+        &self,
+        packet: &[u8],
+        t4: Timestamp,
+    ) -> Result<Reply, SntpProtocolError> {
+        let mut reply = SntpMessage::new_v4();
+        reply.read_from_packet(packet)?;
+
+        if reply.mode != Mode::Server {
+            return Err(SntpProtocolError::UnexpectedSntpMode(reply.mode));
+        }
+
+        // A server copies the transmit timestamp of the request into the originate timestamp of
+        // its reply, or in interleaved mode the receive timestamp of the request. Anything else
+        // is either a stale or a bogus packet.
+        let interleaved = match self.previous {
+            _ if reply.originate_timestamp == self.transmit_timestamp => None,
+            Some(previous) if reply.originate_timestamp == previous.received => Some(previous),
+            _ => return Err(SntpProtocolError::OriginateTimestampMismatch),
+        };
+
+        check_synchronized(&reply)?;
+
+        // In interleaved mode the transmit timestamp is the precise time the server sent its
+        // reply to the previous exchange, which completes that exchange instead of this one.
+        let sample = match interleaved {
+            Some(previous) => SntpSample::from_exchange(
+                &reply,
+                previous.sent,
+                previous.server_received,
+                reply.transmit_timestamp,
+                previous.received,
+                self.local_precision,
+            ),
+            None => SntpSample::from_exchange(
+                &reply,
+                self.sent_at,
+                reply.receive_timestamp,
+                reply.transmit_timestamp,
+                t4,
+                self.local_precision,
+            ),
+        };
+
+        Ok(Reply {
+            sample,
+            exchange: Exchange {
+                sent: self.sent_at,
+                server_received: reply.receive_timestamp,
+                received: t4,
+            },
+            interleaved: interleaved.is_some(),
+        })
+    }
+}
+
+/// Checks that a packet from a server is not a Kiss-o'-Death and that the server is synchronized.
+pub(crate) fn check_synchronized(packet: &SntpMessage) -> Result<(), SntpProtocolError> {
+    if packet.stratum == 0 {
+        return Err(SntpProtocolError::KissOfDeath(
+            KissCode::from_reference_identifier(packet.reference_identifier),
+        ));
+    }
+
+    if packet.leap_indicator == LeapIndicator::AlarmCondition
+        || packet.stratum > MAX_STRATUM
+        || packet.transmit_timestamp.0 == 0
+    {
+        return Err(SntpProtocolError::ServerUnsynchronized);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Clock selection across multiple servers.
+//!
+//! This implements the intersection algorithm from RFC 5905 (a variant of Marzullo's algorithm).
+//! Each server provides a correctness interval `[offset - root distance, offset + root distance]`
+//! which contains the true time if the server is a truechimer. The algorithm finds the smallest
+//! interval that is contained in the correctness intervals of a majority of servers and discards
+//! the servers whose offset falls outside of it as falsetickers.
+
+use crate::{error::Error, protocol::NtpDuration, sample::SntpSample};
+
+/// A single server's offset and the maximum error of that offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub offset: NtpDuration,
+    pub root_distance: NtpDuration,
+}
+
+impl Candidate {
+    // A negative root distance is treated as zero, so that the interval is never inverted.
+    fn low(&self) -> NtpDuration {
+        self.offset
+            .saturating_sub(self.root_distance.max(NtpDuration::ZERO))
+    }
+
+    fn high(&self) -> NtpDuration {
+        self.offset
+            .saturating_add(self.root_distance.max(NtpDuration::ZERO))
+    }
+}
+
+impl From<&SntpSample> for Candidate {
+    fn from(sample: &SntpSample) -> Self {
+        Candidate {
+            offset: sample.offset,
+            root_distance: sample.root_distance(),
+        }
+    }
+}
+
+/// Combined result of the servers that survived selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    /// Offset of the survivors, weighted by the inverse of their root distance.
+    pub offset: NtpDuration,
+    /// Maximum error of [`Selection::offset`]. The true offset lies within the intersection
+    /// interval, so this is the distance to the furthest end of that interval.
+    pub error_bound: NtpDuration,
+    /// Root mean square of the differences between each survivor's offset and
+    /// [`Selection::offset`].
+    pub jitter: NtpDuration,
+    /// Lower end of the intersection interval.
+    pub low: NtpDuration,
+    /// Upper end of the intersection interval.
+    pub high: NtpDuration,
+    /// Number of candidates whose offset is inside of the intersection interval.
+    pub survivors: usize,
+    /// Number of candidates that were discarded.
+    pub falsetickers: usize,
+}
+
+impl Selection {
+    /// Returns true if `candidate` survived selection.
+    pub fn is_survivor(&self, candidate: &Candidate) -> bool {
+        self.low <= candidate.offset && candidate.offset <= self.high
+    }
+}
+
+/// Samples from querying several servers, and the result of selecting between them.
+///
+/// Servers that could not be queried are left out of the selection.
+#[derive(Debug)]
+pub struct MultiQuery<S, R, const N: usize> {
+    /// Results in the same order as the transports that were queried.
+    pub samples: [Result<SntpSample, Error<S, R>>; N],
+    pub selection: Option<Selection>,
+}
+
+impl<S, R, const N: usize> MultiQuery<S, R, N> {
+    pub(crate) fn new(samples: [Result<SntpSample, Error<S, R>>; N]) -> Self {
+        let selection = select(
+            samples
+                .iter()
+                .filter_map(|sample| sample.as_ref().ok())
+                .map(Candidate::from),
+        );
+        MultiQuery { samples, selection }
+    }
+
+    /// Returns the samples of the servers that survived selection.
+    pub fn survivors(&self) -> impl Iterator<Item = &SntpSample> {
+        self.samples
+            .iter()
+            .filter_map(|sample| sample.as_ref().ok())
+            .filter(|sample| {
+                self.selection
+                    .is_some_and(|selection| selection.is_survivor(&Candidate::from(*sample)))
+            })
+    }
+}
+
+/// Runs the intersection algorithm over `candidates` and combines the survivors.
+///
+/// The iterator is traversed several times, so it must be cheap to clone. Returns `None` if there
+/// are no candidates or if no majority of candidates agree on an interval.
+pub fn select<I>(candidates: I) -> Option<Selection>
+where
+    I: IntoIterator<Item = Candidate>,
+    I::IntoIter: Clone,
+{
+    let candidates = candidates.into_iter();
+    let n = candidates.clone().count();
+    if n == 0 {
+        return None;
+    }
+
+    // Endpoints are visited as if they were sorted with lower endpoints before midpoints before
+    // upper endpoints when they are equal. Rather than sorting (which would require an allocation
+    // or a fixed capacity) each scan computes its counts directly in O(n^2).
+    let (mut low, mut high) = (NtpDuration::ZERO, NtpDuration::ZERO);
+    let mut found_interval = false;
+    let mut allow = 0;
+    while 2 * allow < n {
+        // Scanning upwards, the lowest lower endpoint contained in n - allow intervals.
+        let lowest = candidates
+            .clone()
+            .map(|c| c.low())
+            .filter(|&v| {
+                let entered = candidates.clone().filter(|c| c.low() <= v).count();
+                let exited = candidates.clone().filter(|c| c.high() < v).count();
+                entered - exited >= n - allow
+            })
+            .min();
+
+        // Scanning downwards, the highest upper endpoint contained in n - allow intervals.
+        let highest = candidates
+            .clone()
+            .map(|c| c.high())
+            .filter(|&v| {
+                let entered = candidates.clone().filter(|c| c.high() >= v).count();
+                let exited = candidates.clone().filter(|c| c.low() > v).count();
+                entered - exited >= n - allow
+            })
+            .max();
+
+        if let (Some(lowest), Some(highest)) = (lowest, highest) {
+            // Midpoints passed by either scan before it stopped are outside of the interval. If
+            // there are more of them than allowed falsetickers, try again allowing more.
+            let found = candidates
+                .clone()
+                .filter(|c| c.offset < lowest || c.offset > highest)
+                .count();
+
+            if found <= allow && lowest <= highest {
+                (low, high) = (lowest, highest);
+                found_interval = true;
+                break;
+            }
+        }
+
+        allow += 1;
+    }
+
+    if !found_interval {
+        return None;
+    }
+
+    let survivors = candidates
+        .clone()
+        .filter(|c| low <= c.offset && c.offset <= high);
+
+    let mut weights = 0.0;
+    let mut weighted = 0.0;
+    let mut count = 0;
+    for c in survivors.clone() {
+        let weight = 1.0 / c.root_distance.max(NtpDuration(1)).to_seconds_f64();
+        weights += weight;
+        weighted += c.offset.to_seconds_f64() * weight;
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+
+    let offset = NtpDuration::from_seconds_f64(weighted / weights).clamp(low, high);
+    let jitter = NtpDuration::rms(survivors.map(|c| c.offset.saturating_sub(offset)));
+
+    Some(Selection {
+        offset,
+        error_bound: offset.saturating_sub(low).max(high.saturating_sub(offset)),
+        jitter,
+        low,
+        high,
+        survivors: count,
+        falsetickers: n - count,
+    })
+}
@@ -0,0 +1,548 @@
+//! Server mode.
+//!
+//! An [`SntpServer`] answers client requests with the time of a local reference clock. It does not
+//! perform any I/O itself: requests are passed to [`SntpServer::handle_request`] along with the
+//! time they were received, and the reply it writes is sent back to the client. For a transport
+//! implementing [`NtpAddressedTransport`](crate::NtpAddressedTransport), such as
+//! `std::net::UdpSocket`, [`sntp_serve_one`](crate::sntp_serve_one) and its
+//! [async counterpart](crate::nonblocking::sntp_serve_one) do this for one request:
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")]
+//! # fn example() -> std::io::Result<()> {
+//! use barentp::server::{
+//!     AccessAction, AccessRule, RateLimitConfig, RateLimitSlot, ServerConfig, SntpServer,
+//! };
+//! use barentp::SystemClock;
+//!
+//! let access_list = [
+//!     AccessRule::new([0, 0, 0, 0].into(), 0, AccessAction::Deny),
+//!     AccessRule::new([192, 168, 0, 0].into(), 16, AccessAction::Allow),
+//! ];
+//! let mut rate_limit = [RateLimitSlot::EMPTY; 1024];
+//!
+//! let socket = std::net::UdpSocket::bind("0.0.0.0:123")?;
+//! let mut server = SntpServer::new(ServerConfig::default())
+//!     .with_access_list(&access_list)
+//!     .with_rate_limit(RateLimitConfig::default(), &mut rate_limit);
+//! loop {
+//!     let _ = barentp::sntp_serve_one(&mut server, &socket, &SystemClock);
+//! }
+//! # }
+//! ```
+//!
+//! # Abuse protection
+//!
+//! Clients can be refused by address prefix with an access list of [`AccessRule`]s, and each
+//! client can be limited to an average request rate with [`SntpServer::with_rate_limit`]. The rate
+//! limiter remembers recent clients in a fixed-size table provided by the caller, so it never
+//! allocates. IPv6 clients are rate limited by their /64 prefix, since a single host can usually
+//! use any address in its /64.
+//!
+//! # Interleaved mode
+//!
+//! The transmit timestamp of a reply is taken before the reply is actually sent. With
+//! [`SntpServer::with_interleaved`] the server remembers when it really sent recent replies, as
+//! reported to [`SntpServer::sent`], and clients that ask for it receive that time in the reply to
+//! their next request. [`sntp_serve_one`](crate::sntp_serve_one) reports the send time from the
+//! transport, or the time right after sending if the transport does not timestamp packets.
+
+use core::net::IpAddr;
+
+use crate::{
+    clock::NtpClock,
+    digest::Md5,
+    protocol::{KissCode, LeapIndicator, Mode, NtpDuration, SntpMessage, Timestamp},
+};
+
+/// Reference identifier of a server that is synchronized to the server at `upstream`.
+///
+/// As described in RFC 5905, this is the IPv4 address of the upstream server, or the first four
+/// bytes of the MD5 hash of its IPv6 address. IPv4-mapped IPv6 addresses are treated as IPv4
+/// addresses.
+pub fn upstream_reference_identifier(upstream: IpAddr) -> [u8; 4] {
+    match upstream.to_canonical() {
+        IpAddr::V4(addr) => addr.octets(),
+        IpAddr::V6(addr) => {
+            let mut md5 = Md5::new();
+            md5.update(&addr.octets());
+            let digest = md5.finalize();
+            [digest[0], digest[1], digest[2], digest[3]]
+        }
+    }
+}
+
+/// What an [`SntpServer`] tells its clients about its own synchronization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Leap second warning, or [`LeapIndicator::AlarmCondition`] if the server is not
+    /// synchronized. Defaults to no warning.
+    pub leap_indicator: LeapIndicator,
+    /// Stratum of the server, which is one more than the stratum of its reference. A server with a
+    /// local reference clock such as a GPS receiver is stratum 1, which is the default.
+    pub stratum: u8,
+    /// Reference identifier. For a stratum 1 server this is a four character code for the kind of
+    /// reference, e.g. `*b"GPS\0"`, otherwise it identifies the server this server is
+    /// synchronized to, see [`upstream_reference_identifier`]. Defaults to `*b"LOCL"`.
+    pub reference_identifier: [u8; 4],
+    /// Round-trip delay to the primary reference. Defaults to zero.
+    pub root_delay: NtpDuration,
+    /// Maximum error relative to the primary reference. Defaults to zero.
+    pub root_dispersion: NtpDuration,
+    /// Local time the clock was last set or corrected. Defaults to the time of each reply, for a
+    /// reference clock that is always synchronized.
+    pub reference_timestamp: Option<Timestamp>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            leap_indicator: LeapIndicator::NoWarning,
+            stratum: 1,
+            reference_identifier: *b"LOCL",
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            reference_timestamp: None,
+        }
+    }
+}
+
+/// What to do with requests from the clients matching an [`AccessRule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessAction {
+    /// Answer requests normally.
+    Allow,
+    /// Drop requests without a reply.
+    Ignore,
+    /// Answer with a `DENY` Kiss-o'-Death, telling the client to stop querying this server.
+    Deny,
+    /// Answer with a `RSTR` Kiss-o'-Death, telling the client that access is restricted.
+    Restrict,
+}
+
+/// Access control for the clients in an address prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRule {
+    prefix: IpAddr,
+    prefix_len: u8,
+    action: AccessAction,
+}
+
+impl AccessRule {
+    /// Applies `action` to the clients whose address starts with the first `prefix_len` bits of
+    /// `prefix`. A `prefix_len` of 0 matches every client of the same address family, and lengths
+    /// longer than the address are treated as the full address.
+    pub const fn new(prefix: IpAddr, prefix_len: u8, action: AccessAction) -> Self {
+        AccessRule {
+            prefix,
+            prefix_len,
+            action,
+        }
+    }
+
+    pub fn action(&self) -> AccessAction {
+        self.action
+    }
+
+    /// Returns true if `address` is in the prefix of this rule.
+    pub fn matches(&self, address: IpAddr) -> bool {
+        match (self.prefix, address.to_canonical()) {
+            (IpAddr::V4(prefix), IpAddr::V4(address)) => {
+                let mask = prefix_mask(self.prefix_len, 32) as u32;
+                u32::from(prefix) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(prefix), IpAddr::V6(address)) => {
+                let mask = prefix_mask(self.prefix_len, 128);
+                u128::from(prefix) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Mask with the highest `prefix_len` of the low `bits` bits set.
+fn prefix_mask(prefix_len: u8, bits: u32) -> u128 {
+    let prefix_len = u32::from(prefix_len).min(bits);
+    let all = u128::MAX >> (128 - bits);
+    all & !(all.checked_shr(prefix_len).unwrap_or(0))
+}
+
+/// What to do with a request from a client that is over its rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// Answer with a `RATE` Kiss-o'-Death, telling the client to poll less often. The reply is no
+    /// larger than the request, so it cannot be used to amplify traffic.
+    Kiss,
+    /// Drop the request without a reply.
+    Drop,
+}
+
+/// Configuration of the per-client rate limit of an [`SntpServer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Average interval allowed between requests from one client, as a power of two in seconds.
+    /// Defaults to 3 (8 seconds).
+    pub interval: i8,
+    /// Number of requests a client may send at once before it is limited. Defaults to 8.
+    pub burst: u8,
+    /// Defaults to [`RateLimitAction::Kiss`].
+    pub action: RateLimitAction,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            interval: 3,
+            burst: 8,
+            action: RateLimitAction::Kiss,
+        }
+    }
+}
+
+/// Entry in the table of recent clients of the rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitSlot {
+    client: Option<IpAddr>,
+    /// Local time at which the client will have used up none of its burst.
+    ready_at: Timestamp,
+}
+
+impl RateLimitSlot {
+    pub const EMPTY: RateLimitSlot = RateLimitSlot {
+        client: None,
+        ready_at: Timestamp::new(0, 0),
+    };
+}
+
+/// Number of neighbouring slots a client can be stored in.
+const RATE_LIMIT_WAYS: usize = 4;
+
+/// Fixed-memory rate limiter using the generic cell rate algorithm for each client.
+#[derive(Debug)]
+struct RateLimiter<'a> {
+    config: RateLimitConfig,
+    slots: &'a mut [RateLimitSlot],
+}
+
+impl RateLimiter<'_> {
+    /// Records a request from `client` at local time `now`, returning true if it is over its
+    /// limit.
+    fn is_limited(&mut self, client: IpAddr, now: Timestamp) -> bool {
+        if self.slots.is_empty() {
+            return false;
+        }
+
+        let client = rate_limit_key(client);
+        let start = address_hash(client) as usize % self.slots.len();
+        let mut index = start;
+        for way in 0..RATE_LIMIT_WAYS.min(self.slots.len()) {
+            let candidate = (start + way) % self.slots.len();
+            if self.slots[candidate].client == Some(client) {
+                index = candidate;
+                break;
+            }
+            // Otherwise replace whichever client has been idle for the longest.
+            let idle = |slot: &RateLimitSlot| match slot.client {
+                None => NtpDuration::MAX,
+                Some(_) => now - slot.ready_at,
+            };
+            if idle(&self.slots[candidate]) > idle(&self.slots[index]) {
+                index = candidate;
+            }
+        }
+
+        let slot = &mut self.slots[index];
+        if slot.client != Some(client) {
+            *slot = RateLimitSlot {
+                client: Some(client),
+                ready_at: now,
+            };
+        }
+
+        let interval = NtpDuration::from_log2(self.config.interval);
+        let tolerance = interval * i64::from(self.config.burst.saturating_sub(1));
+        let ready_at = if slot.ready_at - now > NtpDuration::ZERO {
+            slot.ready_at
+        } else {
+            now
+        };
+        if ready_at - now > tolerance {
+            return true;
+        }
+        slot.ready_at = ready_at + interval;
+        false
+    }
+}
+
+/// Entry in the table of recent replies kept for interleaved mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterleavedSlot {
+    /// Client the reply was sent to.
+    client: IpAddr,
+    /// Local time the request was received, which the client sends back to refer to this reply.
+    receive: Timestamp,
+    /// Local time the reply was sent.
+    transmit: Timestamp,
+}
+
+impl InterleavedSlot {
+    pub const EMPTY: InterleavedSlot = InterleavedSlot {
+        client: IpAddr::V4(core::net::Ipv4Addr::UNSPECIFIED),
+        receive: Timestamp::new(0, 0),
+        transmit: Timestamp::new(0, 0),
+    };
+}
+
+/// Fixed-memory table of when recent replies were sent, indexed by the client and the receive
+/// timestamp of the request they answered, so that a client cannot learn when replies to other
+/// clients were sent.
+#[derive(Debug)]
+struct InterleavedTable<'a> {
+    slots: &'a mut [InterleavedSlot],
+    /// Slot of the last reply, until its send time is reported.
+    last: Option<usize>,
+}
+
+impl InterleavedTable<'_> {
+    fn index(&self, client: IpAddr, receive: Timestamp) -> usize {
+        let hash = address_hash(client) ^ fnv1a(&receive.0.to_be_bytes());
+        hash as usize % self.slots.len()
+    }
+
+    /// Returns when the reply to the request from `client` received at local time `receive` was
+    /// sent, if it is still remembered.
+    fn transmit(&self, client: IpAddr, receive: Timestamp) -> Option<Timestamp> {
+        if self.slots.is_empty() || receive.0 == 0 {
+            return None;
+        }
+        let slot = &self.slots[self.index(client, receive)];
+        (slot.client == client && slot.receive == receive).then_some(slot.transmit)
+    }
+
+    fn insert(&mut self, client: IpAddr, receive: Timestamp, transmit: Timestamp) {
+        if self.slots.is_empty() {
+            return;
+        }
+        let index = self.index(client, receive);
+        self.slots[index] = InterleavedSlot {
+            client,
+            receive,
+            transmit,
+        };
+        self.last = Some(index);
+    }
+
+    fn sent(&mut self, transmit: Timestamp) {
+        if let Some(index) = self.last.take() {
+            self.slots[index].transmit = transmit;
+        }
+    }
+}
+
+/// Address that clients are rate limited by.
+fn rate_limit_key(client: IpAddr) -> IpAddr {
+    match client.to_canonical() {
+        IpAddr::V6(address) => IpAddr::V6((u128::from(address) & !(u64::MAX as u128)).into()),
+        address => address,
+    }
+}
+
+fn address_hash(address: IpAddr) -> u64 {
+    match address {
+        IpAddr::V4(address) => fnv1a(&address.octets()),
+        IpAddr::V6(address) => fnv1a(&address.octets()),
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Address of a client, which access control and rate limiting are based on.
+pub trait ClientAddress {
+    fn ip_addr(&self) -> IpAddr;
+}
+
+impl ClientAddress for IpAddr {
+    fn ip_addr(&self) -> IpAddr {
+        *self
+    }
+}
+
+impl ClientAddress for core::net::SocketAddr {
+    fn ip_addr(&self) -> IpAddr {
+        self.ip()
+    }
+}
+
+/// Answers client requests.
+#[derive(Debug)]
+pub struct SntpServer<'a> {
+    config: ServerConfig,
+    access_list: &'a [AccessRule],
+    rate_limiter: Option<RateLimiter<'a>>,
+    interleaved: Option<InterleavedTable<'a>>,
+}
+
+impl<'a> SntpServer<'a> {
+    /// Creates a server that answers every client.
+    pub fn new(config: ServerConfig) -> Self {
+        SntpServer {
+            config,
+            access_list: &[],
+            rate_limiter: None,
+            interleaved: None,
+        }
+    }
+
+    /// Restricts which clients are answered. The rule with the longest prefix that matches a
+    /// client applies to it, and clients that no rule matches are allowed.
+    pub fn with_access_list(mut self, access_list: &'a [AccessRule]) -> Self {
+        self.access_list = access_list;
+        self
+    }
+
+    /// Limits the rate at which each client is answered, remembering up to `slots.len()` recent
+    /// clients. Slots should be initialized to [`RateLimitSlot::EMPTY`].
+    pub fn with_rate_limit(
+        mut self,
+        config: RateLimitConfig,
+        slots: &'a mut [RateLimitSlot],
+    ) -> Self {
+        self.rate_limiter = Some(RateLimiter { config, slots });
+        self
+    }
+
+    /// Supports interleaved mode for clients that ask for it, remembering when up to
+    /// `slots.len()` recent replies were sent. Slots should be initialized to
+    /// [`InterleavedSlot::EMPTY`].
+    pub fn with_interleaved(mut self, slots: &'a mut [InterleavedSlot]) -> Self {
+        self.interleaved = Some(InterleavedTable { slots, last: None });
+        self
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Replaces the configuration, e.g. after the server's own clock was synchronized.
+    pub fn set_config(&mut self, config: ServerConfig) {
+        self.config = config;
+    }
+
+    /// Returns the action of the longest prefix in the access list that matches `client`.
+    pub fn access(&self, client: IpAddr) -> AccessAction {
+        self.access_list
+            .iter()
+            .filter(|rule| rule.matches(client))
+            .max_by_key(|rule| rule.prefix_len)
+            .map_or(AccessAction::Allow, |rule| rule.action)
+    }
+
+    /// Builds the reply to a request from `client` that was received at local time `received`,
+    /// stamping its transmit timestamp with `clock`.
+    ///
+    /// Returns the length of the reply written to `reply`, or `None` if the request should be
+    /// dropped without a reply because it is not a valid client request, the client is ignored or
+    /// over its rate limit, or `reply` is too small.
+    pub fn handle_request<C>(
+        &mut self,
+        request: &[u8],
+        client: IpAddr,
+        received: Timestamp,
+        clock: &C,
+        reply: &mut [u8],
+    ) -> Option<usize>
+    where
+        C: NtpClock + ?Sized,
+    {
+        let mut msg = SntpMessage::new_v4();
+        msg.read_from_packet(request).ok()?;
+        if msg.mode != Mode::Client || msg.transmit_timestamp.0 == 0 {
+            return None;
+        }
+
+        // Like ntpd, the access list is applied before the rate limit, so that denied clients
+        // are told to stop rather than to back off, and do not take up slots of the limiter.
+        match self.access(client) {
+            AccessAction::Ignore => return None,
+            AccessAction::Deny => return kiss_of_death(&msg, KissCode::DENY, msg.poll, reply),
+            AccessAction::Restrict => return kiss_of_death(&msg, KissCode::RSTR, msg.poll, reply),
+            AccessAction::Allow => {}
+        }
+
+        if let Some(limiter) = &mut self.rate_limiter {
+            if limiter.is_limited(client, received) {
+                return match limiter.config.action {
+                    RateLimitAction::Kiss => {
+                        let poll = msg.poll.max(limiter.config.interval.max(0) as u8);
+                        kiss_of_death(&msg, KissCode::RATE, poll, reply)
+                    }
+                    RateLimitAction::Drop => None,
+                };
+            }
+        }
+
+        let config = &self.config;
+        let now = clock.now();
+        let mut response = SntpMessage {
+            leap_indicator: config.leap_indicator,
+            version: msg.version,
+            mode: Mode::Server,
+            stratum: config.stratum,
+            poll: msg.poll,
+            precision: clock.precision() as u8,
+            root_delay: config.root_delay.to_ntp_short(),
+            root_dispersion: config.root_dispersion.to_ntp_short(),
+            reference_identifier: u32::from_be_bytes(config.reference_identifier),
+            reference_timestamp: config.reference_timestamp.unwrap_or(received),
+            originate_timestamp: msg.transmit_timestamp,
+            receive_timestamp: received,
+            transmit_timestamp: now,
+        };
+
+        if let Some(table) = &mut self.interleaved {
+            // A client asks for an interleaved reply by sending back the receive timestamp of
+            // its previous request, and recognizes the reply by its own receive timestamp.
+            if let Some(transmit) = table.transmit(client, msg.originate_timestamp) {
+                if msg.receive_timestamp.0 != 0 {
+                    response.originate_timestamp = msg.receive_timestamp;
+                    response.transmit_timestamp = transmit;
+                }
+            }
+            table.insert(client, received, now);
+        }
+
+        response.write_to_buffer(reply).ok()?;
+        Some(SntpMessage::BUFFER_SIZE)
+    }
+
+    /// Records the local time the last reply built by [`SntpServer::handle_request`] was sent,
+    /// for interleaved mode.
+    pub fn sent(&mut self, sent_at: Timestamp) {
+        if let Some(table) = &mut self.interleaved {
+            table.sent(sent_at);
+        }
+    }
+}
+
+/// Writes a Kiss-o'-Death reply to `request`, which carries no time information.
+pub(crate) fn kiss_of_death(
+    request: &SntpMessage,
+    code: KissCode,
+    poll: u8,
+    reply: &mut [u8],
+) -> Option<usize> {
+    let mut response = SntpMessage::new_v4();
+    response.leap_indicator = LeapIndicator::AlarmCondition;
+    response.version = request.version;
+    response.mode = Mode::Server;
+    response.stratum = 0;
+    response.poll = poll;
+    response.reference_identifier = code.to_reference_identifier();
+    response.originate_timestamp = request.transmit_timestamp;
+    response.write_to_buffer(reply).ok()?;
+    Some(SntpMessage::BUFFER_SIZE)
+}
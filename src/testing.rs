@@ -0,0 +1,321 @@
+//! Simulated network for testing code that uses this crate offline.
+//!
+//! A [`SimulatedNetwork`] is an [`NtpTransport`] and [`NtpTransportAsync`] connected to a
+//! simulated [`SntpServer`]. Time only passes as packets travel through the network, or when a
+//! test advances it with [`SimulatedNetwork::advance`], so tests run instantly and always give the
+//! same results. The local clock is [`SimulatedNetwork::clock`], and the server's clock is ahead
+//! of it by a configurable offset:
+//!
+//! ```
+//! use barentp::testing::{NetworkConfig, SimulatedNetwork};
+//! use barentp::NtpDuration;
+//!
+//! let network = SimulatedNetwork::new(NetworkConfig {
+//!     offset: NtpDuration::from_millis(250),
+//!     request_delay: NtpDuration::from_millis(30),
+//!     reply_delay: NtpDuration::from_millis(10),
+//!     ..NetworkConfig::default()
+//! });
+//! let sample = barentp::sntp_query(&network, &network.clock()).unwrap();
+//!
+//! // Asymmetric delays shift the measured offset by half of their difference.
+//! let error = sample.offset - NtpDuration::from_millis(260);
+//! assert!(error.abs() < NtpDuration::from_micros(1));
+//! ```
+//!
+//! Packets can be lost, duplicated and reordered at random, using a seeded random number
+//! generator so that failures can be reproduced. The server can also be made to answer with
+//! kiss-o'-death packets, either always or for the next request only.
+
+use crate::{
+    clock::NtpClock,
+    nonblocking::NtpTransportAsync,
+    protocol::{KissCode, NtpDuration, SntpMessage, Timestamp},
+    server::{kiss_of_death, ServerConfig, SntpServer},
+    NtpTransport,
+};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Address of the local host as the simulated server sees it.
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+/// How a [`SimulatedNetwork`] and its server behave.
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkConfig {
+    /// How far the server's clock is ahead of the local clock. Defaults to zero.
+    pub offset: NtpDuration,
+    /// Time for a request to reach the server. Defaults to 10ms.
+    pub request_delay: NtpDuration,
+    /// Time for a reply to reach the local host. Defaults to 10ms.
+    pub reply_delay: NtpDuration,
+    /// Time between the server receiving a request and sending the reply. Defaults to zero.
+    pub processing_delay: NtpDuration,
+    /// Probability that a packet is lost, in each direction. Defaults to zero.
+    pub loss: f64,
+    /// Probability that a reply is delivered twice. Defaults to zero.
+    pub duplication: f64,
+    /// Probability that a reply is held back by `reorder_delay`, so that it arrives after replies
+    /// to later requests. Defaults to zero.
+    pub reordering: f64,
+    /// Extra delay of reordered replies. Defaults to 1.5 seconds, so that with the default
+    /// `recv_timeout` they arrive while waiting for the reply to the next request.
+    pub reorder_delay: NtpDuration,
+    /// How long receiving waits for a reply before it fails with [`TimedOut`]. Defaults to 1
+    /// second.
+    pub recv_timeout: NtpDuration,
+    /// Answer every request with a kiss-o'-death packet with this code. Defaults to `None`.
+    pub kiss_of_death: Option<KissCode>,
+    /// Seed of the random number generator that decides which packets are lost, duplicated or
+    /// reordered.
+    pub seed: u64,
+    /// Configuration of the simulated server.
+    pub server: ServerConfig,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            offset: NtpDuration::ZERO,
+            request_delay: NtpDuration::from_millis(10),
+            reply_delay: NtpDuration::from_millis(10),
+            processing_delay: NtpDuration::ZERO,
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            reorder_delay: NtpDuration::from_millis(1500),
+            recv_timeout: NtpDuration::from_seconds(1),
+            kiss_of_death: None,
+            seed: 0,
+            server: ServerConfig::default(),
+        }
+    }
+}
+
+/// Error returned when no reply arrives within [`NetworkConfig::recv_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl core::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "timed out waiting for a reply")
+    }
+}
+
+impl core::error::Error for TimedOut {}
+
+/// An in-memory transport connected to a simulated server, see the [module docs](self).
+#[derive(Debug)]
+pub struct SimulatedNetwork {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    config: NetworkConfig,
+    now: Timestamp,
+    server: SntpServer<'static>,
+    /// Replies on their way to the local host, ordered by arrival time.
+    in_flight: VecDeque<(Timestamp, Vec<u8>)>,
+    injected_kiss: Option<KissCode>,
+    requests: usize,
+    rng: u64,
+}
+
+impl SimulatedNetwork {
+    /// Local time at which every simulation starts, in early 2019.
+    pub const START: Timestamp = Timestamp::new(0xE000_0000, 0);
+
+    pub fn new(config: NetworkConfig) -> Self {
+        SimulatedNetwork {
+            state: Mutex::new(State {
+                config,
+                now: Self::START,
+                server: SntpServer::new(config.server),
+                in_flight: VecDeque::new(),
+                injected_kiss: None,
+                requests: 0,
+                rng: config.seed,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> NetworkConfig {
+        self.state().config
+    }
+
+    /// Changes how the network behaves from now on. Packets already in flight are not affected.
+    pub fn set_config(&self, config: NetworkConfig) {
+        let mut state = self.state();
+        state.server.set_config(config.server);
+        state.config = config;
+    }
+
+    /// The local clock.
+    pub fn clock(&self) -> SimulatedClock<'_> {
+        SimulatedClock(self)
+    }
+
+    /// The current local time.
+    pub fn now(&self) -> Timestamp {
+        self.state().now
+    }
+
+    /// Lets time pass, e.g. between polls.
+    pub fn advance(&self, duration: NtpDuration) {
+        let mut state = self.state();
+        state.now = state.now + duration;
+    }
+
+    /// Answers the next request that reaches the server with a kiss-o'-death packet.
+    pub fn inject_kiss_of_death(&self, code: KissCode) {
+        self.state().injected_kiss = Some(code);
+    }
+
+    /// Number of requests that have reached the server.
+    pub fn requests(&self) -> usize {
+        self.state().requests
+    }
+
+    /// Number of replies that have not been received yet, including lost ones that are late.
+    pub fn replies_in_flight(&self) -> usize {
+        self.state().in_flight.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl State {
+    fn send(&mut self, request: &[u8]) {
+        let config = self.config;
+        if self.roll(config.loss) {
+            return;
+        }
+        self.requests += 1;
+
+        let arrival = self.now + config.request_delay;
+        let received = arrival + config.offset;
+        let sent = received + config.processing_delay;
+        let mut reply = [0; SntpMessage::MAX_PACKET_SIZE];
+        let len = match self.injected_kiss.take().or(config.kiss_of_death) {
+            Some(code) => {
+                let mut msg = SntpMessage::new_v4();
+                if msg.read_from_packet(request).is_err() {
+                    return;
+                }
+                kiss_of_death(&msg, code, msg.poll, &mut reply)
+            }
+            None => {
+                let server_clock = FixedClock(sent);
+                self.server
+                    .handle_request(request, CLIENT, received, &server_clock, &mut reply)
+            }
+        };
+        let Some(len) = len else {
+            return;
+        };
+        if self.roll(config.loss) {
+            return;
+        }
+
+        let mut delivered = arrival + config.processing_delay + config.reply_delay;
+        if self.roll(config.reordering) {
+            delivered = delivered + config.reorder_delay;
+        }
+        self.deliver(delivered, &reply[..len]);
+        if self.roll(config.duplication) {
+            self.deliver(delivered, &reply[..len]);
+        }
+    }
+
+    fn deliver(&mut self, arrival: Timestamp, packet: &[u8]) {
+        let index = self
+            .in_flight
+            .partition_point(|(other, _)| *other - arrival <= NtpDuration::ZERO);
+        self.in_flight.insert(index, (arrival, packet.to_vec()));
+    }
+
+    fn recv(&mut self, buffer: &mut [u8]) -> Result<usize, TimedOut> {
+        let timeout = self.now + self.config.recv_timeout;
+        match self.in_flight.front() {
+            Some((arrival, _)) if *arrival - timeout <= NtpDuration::ZERO => {}
+            _ => {
+                self.now = timeout;
+                return Err(TimedOut);
+            }
+        }
+
+        let (arrival, packet) = self.in_flight.pop_front().unwrap();
+        if arrival - self.now > NtpDuration::ZERO {
+            self.now = arrival;
+        }
+        let len = packet.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&packet[..len]);
+        Ok(len)
+    }
+
+    /// Returns true with the given probability, using the SplitMix64 generator.
+    fn roll(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+impl NtpTransport for SimulatedNetwork {
+    type SendError = Infallible;
+    type RecvError = TimedOut;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.state().send(buffer);
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        self.state().recv(buffer)
+    }
+}
+
+impl NtpTransportAsync for SimulatedNetwork {
+    type SendError = Infallible;
+    type RecvError = TimedOut;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        NtpTransport::send(self, buffer)
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        NtpTransport::recv(self, buffer)
+    }
+}
+
+/// The local clock of a [`SimulatedNetwork`].
+#[derive(Debug, Clone, Copy)]
+pub struct SimulatedClock<'a>(&'a SimulatedNetwork);
+
+impl NtpClock for SimulatedClock<'_> {
+    fn now(&self) -> Timestamp {
+        self.0.now()
+    }
+}
+
+/// The server's clock while it handles a request, which is when it sends the reply.
+struct FixedClock(Timestamp);
+
+impl NtpClock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.0
+    }
+}
@@ -0,0 +1,152 @@
+use barentp::protocol::{Mode, SntpMessage};
+use barentp::{NtpAddressedTransport, NtpClock, NtpDuration, SystemClock, Timestamp};
+use std::collections::VecDeque;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+
+mod common;
+use common::{block_on, FixedClock, START};
+
+fn reply_to(request: &SntpMessage, now: Timestamp) -> [u8; SntpMessage::BUFFER_SIZE] {
+    let mut reply = SntpMessage::new_v4();
+    reply.mode = Mode::Server;
+    reply.stratum = 1;
+    reply.originate_timestamp = request.transmit_timestamp;
+    reply.receive_timestamp = now;
+    reply.transmit_timestamp = now;
+    let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+    reply.write_to_buffer(&mut buffer).unwrap();
+    buffer
+}
+
+/// Spawns a server on the loopback interface that is `offset_ms` ahead of the system clock.
+fn spawn_server(offset_ms: i64, count: usize) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+        for _ in 0..count {
+            let (_, peer) = socket.recv_from(&mut buffer).unwrap();
+            let mut request = SntpMessage::new_v4();
+            request.read_from_buffer(&buffer).unwrap();
+            let now = SystemClock.now() + NtpDuration::from_millis(offset_ms);
+            socket.send_to(&reply_to(&request, now), peer).unwrap();
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_query_servers_through_one_socket() {
+    let addresses = [
+        spawn_server(200, 1),
+        spawn_server(201, 1),
+        spawn_server(-3000, 1),
+    ];
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+
+    let query = barentp::sntp_query_servers_to(&socket, &addresses, &SystemClock);
+    let selection = query.selection.expect("no selection");
+    assert_eq!(query.survivors().count(), 2);
+    let falseticker = query.samples[2].as_ref().expect("query failed");
+    assert!(!selection.is_survivor(&falseticker.into()));
+    assert!(
+        (selection.offset - NtpDuration::from_millis(200)).abs() < NtpDuration::from_millis(10)
+    );
+}
+
+/// Transport that delivers a stray packet from another address and a stale reply to an earlier
+/// request before every real reply.
+struct StrayTransport {
+    request: Mutex<SntpMessage>,
+    inbox: Mutex<VecDeque<(u32, [u8; SntpMessage::BUFFER_SIZE])>>,
+}
+
+impl NtpAddressedTransport for StrayTransport {
+    type Address = u32;
+    type SendError = std::convert::Infallible;
+    type RecvError = &'static str;
+
+    fn send_to(&self, buffer: &[u8], address: &u32) -> Result<(), Self::SendError> {
+        let mut request = self.request.lock().unwrap();
+        request.read_from_buffer(buffer).unwrap();
+        let now = START;
+
+        // A reply from another server, which uses the same originate timestamp.
+        let stray = reply_to(&request, now + NtpDuration::from_seconds(60));
+        let mut inbox = self.inbox.lock().unwrap();
+        inbox.push_back((address + 1, stray));
+        let mut earlier = SntpMessage::new_v4();
+        earlier.transmit_timestamp = request.transmit_timestamp - NtpDuration::from_seconds(64);
+        inbox.push_back((*address, reply_to(&earlier, now)));
+        inbox.push_back((*address, reply_to(&request, now)));
+        Ok(())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, u32), Self::RecvError> {
+        let (source, packet) = self.inbox.lock().unwrap().pop_front().ok_or("timed out")?;
+        buffer[..packet.len()].copy_from_slice(&packet);
+        Ok((packet.len(), source))
+    }
+}
+
+#[test]
+fn test_replies_from_other_sources_are_discarded() {
+    let transport = StrayTransport {
+        request: Mutex::new(SntpMessage::new_v4()),
+        inbox: Mutex::new(VecDeque::new()),
+    };
+    let sample = barentp::sntp_query_to(&transport, &7, &FixedClock(START)).expect("query failed");
+    assert_eq!(sample.offset, NtpDuration::ZERO);
+    assert!(transport.inbox.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_ipv4_mapped_sources() {
+    let v4: SocketAddr = "192.0.2.1:123".parse().unwrap();
+    let mapped: SocketAddr = "[::ffff:192.0.2.1]:123".parse().unwrap();
+    assert!(UdpSocket::is_same_address(&mapped, &v4));
+    assert!(UdpSocket::is_same_address(&v4, &mapped));
+    assert!(!UdpSocket::is_same_address(
+        &"[::ffff:192.0.2.1]:124".parse().unwrap(),
+        &v4
+    ));
+    assert!(!UdpSocket::is_same_address(
+        &"[::ffff:192.0.2.2]:123".parse().unwrap(),
+        &v4
+    ));
+}
+
+#[test]
+fn test_query_to_from_dual_stack_socket() {
+    // Replies to an IPv4 server come from its IPv4-mapped address on a dual-stack socket.
+    let Ok(socket) = UdpSocket::bind("[::]:0") else {
+        return;
+    };
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let address = spawn_server(50, 1);
+    let sample = barentp::sntp_query_to(&socket, &address, &SystemClock).expect("query failed");
+    assert!((sample.offset - NtpDuration::from_millis(50)).abs() < NtpDuration::from_millis(10));
+}
+
+#[test]
+fn test_async_query_to() {
+    let address = spawn_server(50, 1);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+
+    let sample = block_on(barentp::nonblocking::sntp_query_to(
+        &socket,
+        &address,
+        &SystemClock,
+    ))
+    .expect("query failed");
+    assert!((sample.offset - NtpDuration::from_millis(50)).abs() < NtpDuration::from_millis(10));
+}
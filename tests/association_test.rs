@@ -0,0 +1,140 @@
+use barentp::association::{Association, AssociationConfig};
+use barentp::protocol::{KissCode, Mode, SntpMessage};
+use barentp::{NtpClock, NtpDuration};
+use std::sync::Mutex;
+
+mod common;
+use common::{block_on, ManualClock, START};
+
+/// Server that is 5ms ahead of the clock and can be told to send a Kiss-o'-Death.
+struct Server<'a> {
+    clock: &'a ManualClock,
+    kiss: Mutex<Option<KissCode>>,
+    request: Mutex<SntpMessage>,
+}
+
+impl<'a> Server<'a> {
+    fn new(clock: &'a ManualClock) -> Self {
+        Self {
+            clock,
+            kiss: Mutex::new(None),
+            request: Mutex::new(SntpMessage::new_v4()),
+        }
+    }
+
+    fn reply(&self, buffer: &mut [u8]) -> usize {
+        let request = self.request.lock().unwrap();
+        let now = self.clock.now() + NtpDuration::from_millis(5);
+        let mut reply = SntpMessage::new_v4();
+        reply.mode = Mode::Server;
+        reply.stratum = 1;
+        reply.originate_timestamp = request.transmit_timestamp;
+        reply.receive_timestamp = now;
+        reply.transmit_timestamp = now;
+        if let Some(kiss) = *self.kiss.lock().unwrap() {
+            reply.stratum = 0;
+            reply.reference_identifier = kiss.to_reference_identifier();
+        }
+        reply.write_to_buffer(buffer).unwrap();
+        SntpMessage::BUFFER_SIZE
+    }
+}
+
+impl barentp::NtpTransport for Server<'_> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.request
+            .lock()
+            .unwrap()
+            .read_from_buffer(buffer)
+            .unwrap();
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.reply(buffer))
+    }
+}
+
+impl barentp::nonblocking::NtpTransportAsync for Server<'_> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.request
+            .lock()
+            .unwrap()
+            .read_from_buffer(buffer)
+            .unwrap();
+        Ok(())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.reply(buffer))
+    }
+}
+
+fn new_clock() -> ManualClock {
+    ManualClock::new(START)
+}
+
+#[test]
+fn test_association_adapts_poll_interval() {
+    let clock = new_clock();
+    let server = Server::new(&clock);
+    let mut association = Association::new(AssociationConfig::default(), clock.precision());
+    assert!(association.is_poll_due(clock.now()));
+    assert!(association.estimate().is_none());
+
+    for _ in 0..32 {
+        let wait = association.time_until_poll(clock.now()).unwrap();
+        clock.advance(NtpDuration::from(wait));
+        assert!(association.is_poll_due(clock.now()));
+        barentp::sntp_poll(&mut association, &server, &clock).expect("poll failed");
+        assert!(!association.is_poll_due(clock.now()));
+    }
+
+    let estimate = association.estimate().expect("no estimate");
+    assert!((estimate.offset - NtpDuration::from_millis(5)).abs() < NtpDuration::from_micros(1));
+    assert_eq!(association.reachability(), 0xFF);
+    assert_eq!(association.poll(), AssociationConfig::default().max_poll);
+    assert!(association.candidate(clock.now()).is_some());
+}
+
+#[test]
+fn test_association_rate_kiss_of_death() {
+    let clock = new_clock();
+    let server = Server::new(&clock);
+    let mut association = Association::new(AssociationConfig::default(), clock.precision());
+    barentp::sntp_poll(&mut association, &server, &clock).expect("poll failed");
+    let poll = association.poll();
+
+    *server.kiss.lock().unwrap() = Some(KissCode::RATE);
+    barentp::sntp_poll(&mut association, &server, &clock).expect_err("expected a kiss-o'-death");
+    assert_eq!(association.poll(), poll + 1);
+    assert_eq!(
+        association.time_until_poll(clock.now()),
+        Some(core::time::Duration::from_secs(1 << (poll + 1)))
+    );
+    assert!(association.estimate().is_some());
+}
+
+#[test]
+fn test_association_deny_kiss_of_death() {
+    let clock = new_clock();
+    let server = Server::new(&clock);
+    *server.kiss.lock().unwrap() = Some(KissCode::DENY);
+
+    let mut association = Association::new(AssociationConfig::default(), clock.precision());
+    let result = block_on(barentp::nonblocking::sntp_poll(
+        &mut association,
+        &server,
+        &clock,
+    ));
+    assert!(result.is_err());
+    assert_eq!(association.denied(), Some(KissCode::DENY));
+    assert!(association.time_until_poll(clock.now()).is_none());
+    assert!(!association.is_poll_due(clock.now()));
+}
@@ -0,0 +1,70 @@
+use barentp::auth::{DigestAlgorithm, SymmetricKey};
+use barentp::error::SntpProtocolError;
+use barentp::protocol::SntpMessage;
+
+const SECRET: &[u8] = b"secret";
+
+/// A client request with every other field zeroed.
+fn request() -> [u8; 128] {
+    let mut buffer = [0; 128];
+    buffer[0] = 0x23;
+    buffer
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn test_sign_known_answers() {
+    let cases = [
+        (DigestAlgorithm::Md5, "e090dd0f2a35e38783a54445cb1e6933"),
+        (
+            DigestAlgorithm::Sha1,
+            "ddf086dcbfd187aefb251326ca916acfcdc50225",
+        ),
+    ];
+    for (algorithm, expected) in cases {
+        let key = SymmetricKey::new(0x0102_0304, algorithm, SECRET);
+        let mut buffer = request();
+        let len = key.sign(&mut buffer, SntpMessage::BUFFER_SIZE).unwrap();
+        assert_eq!(len, SntpMessage::BUFFER_SIZE + key.mac_len());
+        assert_eq!(&buffer[48..52], &[1, 2, 3, 4]);
+        assert_eq!(hex(&buffer[52..len]), expected, "{algorithm:?}");
+        key.verify(&buffer[..len]).expect("verification failed");
+    }
+}
+
+#[test]
+fn test_verify_rejects_bad_macs() {
+    let key = SymmetricKey::new(1, DigestAlgorithm::Sha1, SECRET);
+    let mut buffer = request();
+    let len = key.sign(&mut buffer, SntpMessage::BUFFER_SIZE).unwrap();
+
+    let failed = |result| matches!(result, Err(SntpProtocolError::AuthenticationFailed));
+    assert!(failed(key.verify(&buffer[..SntpMessage::BUFFER_SIZE])));
+    assert!(failed(
+        SymmetricKey::new(1, DigestAlgorithm::Sha1, b"other").verify(&buffer[..len])
+    ));
+    assert!(failed(
+        SymmetricKey::new(2, DigestAlgorithm::Sha1, SECRET).verify(&buffer[..len])
+    ));
+    assert!(failed(
+        SymmetricKey::new(1, DigestAlgorithm::Md5, SECRET).verify(&buffer[..len])
+    ));
+
+    let mut tampered = buffer;
+    tampered[40] ^= 1;
+    assert!(failed(key.verify(&tampered[..len])));
+
+    assert!(matches!(
+        key.sign(&mut buffer[..50], SntpMessage::BUFFER_SIZE),
+        Err(SntpProtocolError::SntpBufferTooSmall { .. })
+    ));
+}
+
+#[test]
+fn test_debug_hides_secret() {
+    let key = SymmetricKey::new(1, DigestAlgorithm::Md5, b"hunter2");
+    assert!(!format!("{key:?}").contains("hunter2"));
+}
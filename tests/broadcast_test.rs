@@ -0,0 +1,360 @@
+use barentp::auth::{DigestAlgorithm, SymmetricKey};
+use barentp::broadcast::{BroadcastClient, Broadcaster};
+use barentp::error::{Error, SntpProtocolError};
+use barentp::protocol::{LeapIndicator, Mode, SntpMessage};
+use barentp::server::ServerConfig;
+use barentp::{NtpClock, NtpDuration, NtpTransport, SystemClock, Timestamp};
+use std::net::UdpSocket;
+use std::sync::Mutex;
+
+mod common;
+use common::{assert_close, block_on, FixedClock, START};
+
+const FROZEN: FixedClock = FixedClock(START);
+
+/// The server is 5ms ahead of the local clock and 1ms away in each direction.
+fn server_time(local: Timestamp) -> Timestamp {
+    local + NtpDuration::from_millis(5)
+}
+
+/// Transport for the calibration exchange, which timestamps packets itself.
+struct CalibrationServer(Mutex<SntpMessage>);
+
+impl NtpTransport for CalibrationServer {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.0.lock().unwrap().read_from_buffer(buffer).unwrap();
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.recv_timestamped(buffer)?.0)
+    }
+
+    fn send_timestamped(&self, buffer: &[u8]) -> Result<Option<Timestamp>, Self::SendError> {
+        self.send(buffer)?;
+        Ok(Some(START))
+    }
+
+    fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        let request = self.0.lock().unwrap();
+        let now = server_time(START + NtpDuration::from_millis(1));
+        let mut reply = SntpMessage::new_v4();
+        reply.mode = Mode::Server;
+        reply.stratum = 2;
+        reply.originate_timestamp = request.transmit_timestamp;
+        reply.receive_timestamp = now;
+        reply.transmit_timestamp = now;
+        reply.write_to_buffer(buffer).unwrap();
+        Ok((
+            SntpMessage::BUFFER_SIZE,
+            Some(START + NtpDuration::from_millis(2)),
+        ))
+    }
+}
+
+fn broadcast_packet(transmit: Timestamp) -> [u8; SntpMessage::BUFFER_SIZE] {
+    let mut msg = SntpMessage::new_v4();
+    msg.mode = Mode::Broadcast;
+    msg.stratum = 2;
+    msg.poll = 6;
+    msg.transmit_timestamp = transmit;
+    let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+    msg.write_to_buffer(&mut buffer).unwrap();
+    buffer
+}
+
+fn calibration_sample() -> barentp::SntpSample {
+    let transport = CalibrationServer(Mutex::new(SntpMessage::new_v4()));
+    barentp::sntp_query(&transport, &FROZEN).expect("calibration failed")
+}
+
+fn calibrated_client() -> BroadcastClient<'static> {
+    let mut client = BroadcastClient::new(-20);
+    client.calibrate(&calibration_sample());
+    client
+}
+
+#[test]
+fn test_broadcast_offset_after_calibration() {
+    let mut client = BroadcastClient::new(-20);
+    let sent = START + NtpDuration::from_seconds(10);
+    let uncalibrated = client
+        .handle_packet(
+            &broadcast_packet(server_time(sent)),
+            sent + NtpDuration::from_millis(1),
+        )
+        .expect("invalid packet");
+    assert!(uncalibrated.is_none());
+
+    let mut client = calibrated_client();
+    assert_close(client.one_way_delay().unwrap(), NtpDuration::from_millis(1));
+
+    for i in 1..4 {
+        let sent = START + NtpDuration::from_seconds(64 * i);
+        let sample = client
+            .handle_packet(
+                &broadcast_packet(server_time(sent)),
+                sent + NtpDuration::from_millis(1),
+            )
+            .expect("invalid packet")
+            .expect("client is not calibrated");
+        assert_close(sample.offset, NtpDuration::from_millis(5));
+        assert_close(sample.delay, NtpDuration::from_millis(2));
+        assert_eq!(sample.stratum, 2);
+    }
+}
+
+#[test]
+fn test_broadcast_rejects_invalid_packets() {
+    let mut client = calibrated_client();
+    let packet = broadcast_packet(START + NtpDuration::from_seconds(64));
+    client
+        .handle_packet(&packet, START)
+        .expect("invalid packet");
+
+    // Replayed and older packets.
+    assert!(matches!(
+        client.handle_packet(&packet, START),
+        Err(SntpProtocolError::StalePacket)
+    ));
+    assert!(matches!(
+        client.handle_packet(&broadcast_packet(START), START),
+        Err(SntpProtocolError::StalePacket)
+    ));
+
+    let mut msg = SntpMessage::new_v4();
+    msg.read_from_buffer(&broadcast_packet(START + NtpDuration::from_seconds(128)))
+        .unwrap();
+    let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+
+    msg.mode = Mode::Server;
+    msg.write_to_buffer(&mut buffer).unwrap();
+    assert!(matches!(
+        client.handle_packet(&buffer, START),
+        Err(SntpProtocolError::UnexpectedSntpMode(Mode::Server))
+    ));
+
+    msg.mode = Mode::Broadcast;
+    msg.leap_indicator = LeapIndicator::AlarmCondition;
+    msg.write_to_buffer(&mut buffer).unwrap();
+    assert!(matches!(
+        client.handle_packet(&buffer, START),
+        Err(SntpProtocolError::ServerUnsynchronized)
+    ));
+}
+
+#[test]
+fn test_broadcast_recovers_after_server_steps_back() {
+    let mut client = calibrated_client();
+    let sent = START + NtpDuration::from_seconds(64);
+    client
+        .handle_packet(&broadcast_packet(sent), sent)
+        .expect("invalid packet");
+
+    // The server's clock was stepped back by more than the broadcast interval.
+    let stepped = |sent: Timestamp| sent - NtpDuration::from_seconds(100);
+    let sent = sent + NtpDuration::from_seconds(64);
+    assert!(matches!(
+        client.handle_packet(&broadcast_packet(stepped(sent)), sent),
+        Err(SntpProtocolError::StalePacket)
+    ));
+
+    let one_way_delay = client.one_way_delay();
+    client.set_one_way_delay(one_way_delay);
+    let sample = client
+        .handle_packet(&broadcast_packet(stepped(sent)), sent)
+        .expect("invalid packet")
+        .expect("client is not calibrated");
+    assert_close(
+        sample.offset,
+        NtpDuration::from_seconds(-100) + NtpDuration::from_millis(1),
+    );
+
+    // Recalibrating does the same.
+    let mut client = calibrated_client();
+    client
+        .handle_packet(&broadcast_packet(sent), sent)
+        .expect("invalid packet");
+    client.calibrate(&calibration_sample());
+    client
+        .handle_packet(&broadcast_packet(stepped(sent)), sent)
+        .expect("invalid packet");
+}
+
+#[test]
+fn test_recv_broadcast_over_udp() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let mut client = BroadcastClient::new(SystemClock.precision());
+    client.set_one_way_delay(Some(NtpDuration::ZERO));
+
+    // A packet from another broadcaster is ignored.
+    let far_future = SystemClock.now() + NtpDuration::from_seconds(3600);
+    other
+        .send_to(
+            &broadcast_packet(far_future),
+            listener.local_addr().unwrap(),
+        )
+        .unwrap();
+    server
+        .send_to(
+            &broadcast_packet(SystemClock.now()),
+            listener.local_addr().unwrap(),
+        )
+        .unwrap();
+    let sample = barentp::sntp_recv_broadcast(&mut client, &listener, &server_addr, &SystemClock)
+        .expect("receive failed")
+        .expect("client is not calibrated");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+
+    server
+        .send_to(
+            &broadcast_packet(SystemClock.now()),
+            listener.local_addr().unwrap(),
+        )
+        .unwrap();
+    let sample = block_on(barentp::nonblocking::sntp_recv_broadcast(
+        &mut client,
+        &listener,
+        &server_addr,
+        &SystemClock,
+    ))
+    .expect("receive failed")
+    .expect("client is not calibrated");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+
+    // Replaying the same packet is rejected.
+    let packet = broadcast_packet(SystemClock.now());
+    for _ in 0..2 {
+        server
+            .send_to(&packet, listener.local_addr().unwrap())
+            .unwrap();
+    }
+    barentp::sntp_recv_broadcast(&mut client, &listener, &server_addr, &SystemClock)
+        .expect("receive failed");
+    let replayed = barentp::sntp_recv_broadcast(&mut client, &listener, &server_addr, &SystemClock);
+    assert!(matches!(
+        replayed,
+        Err(Error::SntpProtocol(SntpProtocolError::StalePacket))
+    ));
+}
+
+#[test]
+fn test_broadcaster_schedule() {
+    let mut broadcaster = Broadcaster::new(ServerConfig::default(), 6);
+    assert_eq!(
+        broadcaster.time_until_next(START),
+        std::time::Duration::ZERO
+    );
+
+    let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = broadcaster.build_packet(&FROZEN, &mut buffer).unwrap();
+    assert_eq!(len, SntpMessage::BUFFER_SIZE);
+    let mut msg = SntpMessage::new_v4();
+    msg.read_from_buffer(&buffer[..len]).unwrap();
+    assert_eq!(msg.mode, Mode::Broadcast);
+    assert_eq!(msg.poll, 6);
+    assert_eq!(msg.stratum, 1);
+    assert_eq!(msg.transmit_timestamp, START);
+
+    assert_eq!(
+        broadcaster.time_until_next(START),
+        std::time::Duration::from_secs(64)
+    );
+    assert_eq!(
+        broadcaster.time_until_next(START + NtpDuration::from_seconds(60)),
+        std::time::Duration::from_secs(4)
+    );
+    assert_eq!(
+        broadcaster.time_until_next(START + NtpDuration::from_seconds(100)),
+        std::time::Duration::ZERO
+    );
+}
+
+#[test]
+fn test_authenticated_broadcast() {
+    for algorithm in [DigestAlgorithm::Md5, DigestAlgorithm::Sha1] {
+        let key = SymmetricKey::new(7, algorithm, b"broadcast secret");
+        let mut broadcaster = Broadcaster::new(ServerConfig::default(), 4).with_key(key);
+        let mut client = BroadcastClient::new(-20).with_key(key);
+        client.set_one_way_delay(Some(NtpDuration::ZERO));
+
+        let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+        let len = broadcaster.build_packet(&SystemClock, &mut buffer).unwrap();
+        assert_eq!(len, SntpMessage::BUFFER_SIZE + key.mac_len());
+        let sample = client
+            .handle_packet(&buffer[..len], SystemClock.now())
+            .expect("authentication failed")
+            .expect("client is not calibrated");
+        assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+
+        // Unauthenticated, tampered and wrongly keyed packets are rejected.
+        let mut client = BroadcastClient::new(-20).with_key(key);
+        let unauthenticated = broadcast_packet(SystemClock.now());
+        let mut tampered = buffer;
+        tampered[47] ^= 1;
+        let wrong_key = SymmetricKey::new(7, algorithm, b"wrong secret");
+        let mut forged = [0; SntpMessage::MAX_PACKET_SIZE];
+        let forged_len = Broadcaster::new(ServerConfig::default(), 4)
+            .with_key(wrong_key)
+            .build_packet(&SystemClock, &mut forged)
+            .unwrap();
+        for packet in [
+            &unauthenticated[..],
+            &tampered[..len],
+            &forged[..forged_len],
+        ] {
+            assert!(matches!(
+                client.handle_packet(packet, SystemClock.now()),
+                Err(SntpProtocolError::AuthenticationFailed)
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_broadcast_over_udp() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let destination = listener.local_addr().unwrap();
+
+    let key = SymmetricKey::new(1, DigestAlgorithm::Sha1, b"secret");
+    let mut broadcaster = Broadcaster::new(ServerConfig::default(), 4).with_key(key);
+    let mut client = BroadcastClient::new(SystemClock.precision()).with_key(key);
+    client.set_one_way_delay(Some(NtpDuration::ZERO));
+
+    barentp::sntp_broadcast(&mut broadcaster, &server, &destination, &SystemClock)
+        .expect("send failed");
+    let sample = barentp::sntp_recv_broadcast(&mut client, &listener, &server_addr, &SystemClock)
+        .expect("receive failed")
+        .expect("client is not calibrated");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+
+    block_on(barentp::nonblocking::sntp_broadcast(
+        &mut broadcaster,
+        &server,
+        &destination,
+        &SystemClock,
+    ))
+    .expect("send failed");
+    let sample = barentp::sntp_recv_broadcast(&mut client, &listener, &server_addr, &SystemClock)
+        .expect("receive failed")
+        .expect("client is not calibrated");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+}
@@ -0,0 +1,153 @@
+use barentp::server::{ServerConfig, SntpServer};
+use barentp::{NtpClock, NtpDuration, SystemClock, Timestamp};
+use std::net::UdpSocket;
+use std::process::{Command, Output};
+
+/// A server clock that is two seconds ahead.
+struct AheadClock;
+
+impl NtpClock for AheadClock {
+    fn now(&self) -> Timestamp {
+        SystemClock.now() + NtpDuration::from_seconds(2)
+    }
+}
+
+/// Serves `count` requests on a local port in a background thread.
+fn spawn_server(count: usize) -> (u16, std::thread::JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let handle = std::thread::spawn(move || {
+        let config = ServerConfig {
+            reference_identifier: *b"GPS\0",
+            ..ServerConfig::default()
+        };
+        let mut server = SntpServer::new(config);
+        for _ in 0..count {
+            barentp::sntp_serve_one(&mut server, &socket, &AheadClock).expect("serve failed");
+        }
+    });
+    (port, handle)
+}
+
+/// A local port that nothing answers on.
+fn unused_port() -> (u16, UdpSocket) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    (socket.local_addr().unwrap().port(), socket)
+}
+
+/// A local port that nothing listens on, so that queries are refused.
+fn closed_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Parses every number that follows `key` in the output.
+fn values_after(output: &str, key: &str) -> Vec<f64> {
+    output
+        .split(key)
+        .skip(1)
+        .map(|rest| {
+            let end = rest.find([' ', ',']).unwrap_or(rest.len());
+            rest[..end].parse().expect("invalid number")
+        })
+        .collect()
+}
+
+fn barentp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_barentp"))
+        .args(args)
+        .output()
+        .expect("failed to run barentp")
+}
+
+#[test]
+fn test_cli_human_output() {
+    let (port, server) = spawn_server(1);
+    let output = barentp(&["--port", &port.to_string(), "127.0.0.1"]);
+    server.join().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!("127.0.0.1 (127.0.0.1:{port}): offset +")),
+        "{stdout}"
+    );
+    let offsets = values_after(&stdout, "offset ");
+    assert!((offsets[0] - 2.0).abs() < 0.1, "{stdout}");
+    assert!(
+        stdout.contains("stratum 1, refid GPS, leap none"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_cli_json_output() {
+    let (port, server) = spawn_server(2);
+    let (silent_port, _silent) = unused_port();
+    let output = barentp(&[
+        "-j",
+        "-t",
+        "0.2",
+        "-p",
+        &port.to_string(),
+        "127.0.0.1",
+        "127.0.0.1",
+    ]);
+    server.join().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let offsets = values_after(&stdout, "\"offset\": ");
+    assert_eq!(offsets.len(), 2, "{stdout}");
+    assert!(offsets.iter().all(|offset| (offset - 2.0).abs() < 0.1));
+    assert!(stdout.contains("\"refid\": \"GPS\""), "{stdout}");
+
+    // Servers that do not reply are reported in the output and fail the run.
+    let output = barentp(&[
+        "--json",
+        "--timeout=0.2",
+        "--port",
+        &silent_port.to_string(),
+        "127.0.0.1",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"error\": "), "{stdout}");
+    assert!(!stdout.contains("\"offset\""), "{stdout}");
+}
+
+#[test]
+fn test_cli_exit_codes() {
+    let (port, _silent) = unused_port();
+    let output = barentp(&["-t", "0.2", "-p", &port.to_string(), "127.0.0.1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("barentp: 127.0.0.1: "));
+
+    assert_eq!(barentp(&["--bogus"]).status.code(), Some(2));
+    assert_eq!(barentp(&["--timeout", "soon"]).status.code(), Some(2));
+    assert_eq!(barentp(&["--port"]).status.code(), Some(2));
+    assert_eq!(barentp(&["--help"]).status.code(), Some(0));
+}
+
+#[test]
+fn test_cli_error_messages() {
+    let port = closed_port().to_string();
+    let output = barentp(&["-t", "0.5", "-p", &port, "127.0.0.1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "barentp: 127.0.0.1: transport recv error: Connection refused (os error 111)\n"
+    );
+
+    let output = barentp(&["-j", "-t", "0.5", "-p", &port, "127.0.0.1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[\n  {\"server\": \"127.0.0.1\", \
+         \"error\": \"transport recv error: Connection refused (os error 111)\"}\n]\n"
+    );
+}
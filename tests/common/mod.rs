@@ -0,0 +1,65 @@
+//! Helpers shared by the integration tests. Each test only uses some of them.
+#![allow(dead_code)]
+
+use barentp::{NtpClock, NtpDuration, Timestamp};
+use std::sync::Mutex;
+
+pub const START: Timestamp = Timestamp::new(0xE000_0000, 0);
+
+/// Clock that is always at the same time.
+pub struct FixedClock(pub Timestamp);
+
+impl NtpClock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.0
+    }
+}
+
+/// Clock that only moves when the test moves it.
+pub struct ManualClock(Mutex<Timestamp>);
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        ManualClock(Mutex::new(now))
+    }
+
+    pub fn advance(&self, duration: NtpDuration) {
+        let mut now = self.0.lock().unwrap();
+        *now = *now + duration;
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl NtpClock for ManualClock {
+    fn now(&self) -> Timestamp {
+        *self.0.lock().unwrap()
+    }
+}
+
+pub fn assert_close(actual: NtpDuration, expected: NtpDuration) {
+    assert!(
+        (actual - expected).abs() < NtpDuration::from_micros(1),
+        "expected {expected}, got {actual}"
+    );
+}
+
+struct NoopWaker;
+
+impl std::task::Wake for NoopWaker {
+    fn wake(self: std::sync::Arc<Self>) {}
+}
+
+/// Runs a future that never has to wait for anything to completion.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+    let mut cx = std::task::Context::from_waker(&waker);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
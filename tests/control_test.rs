@@ -0,0 +1,262 @@
+use barentp::control::{ControlMessage, ControlOpcode, ControlReassembler, PeerStatus, Variable};
+use barentp::error::{Error, SntpProtocolError};
+use barentp::protocol::{LeapIndicator, Mode};
+use std::net::UdpSocket;
+
+mod common;
+use common::block_on;
+
+/// System variables long enough to need several fragments.
+fn system_variables() -> String {
+    let mut variables = String::from(
+        "version=\"ntpd 4.2.8p15@1.3728-o (1), with, commas\", processor=\"x86_64\",\r\n\
+         leap=00, stratum=2, precision=-24, rootdelay=1.234, rootdisp=5.678,\r\n\
+         refid=192.0.2.1, reftime=0xe5a1b2c3.d4e5f607, offset=-0.123, sys_jitter=0.045",
+    );
+    for i in 0..40 {
+        variables.push_str(&format!(", filler{i}=\"{}\"", "x".repeat(i)));
+    }
+    variables
+}
+
+fn response<'a>(request: &ControlMessage<'_>, status: u16, data: &'a [u8]) -> ControlMessage<'a> {
+    ControlMessage {
+        response: true,
+        status,
+        association_id: request.association_id,
+        data,
+        ..*request
+    }
+}
+
+/// Answers control requests like `ntpd` would, sending fragments out of order and with a stray
+/// fragment from an earlier request mixed in.
+fn responder(socket: UdpSocket, requests: usize) {
+    let mut buf = [0; 1024];
+    for _ in 0..requests {
+        let (len, client) = socket.recv_from(&mut buf).unwrap();
+        let request = ControlMessage::read_from_packet(&buf[..len]).unwrap();
+        assert!(!request.response);
+
+        let mut fragments = Vec::new();
+        let variables = system_variables();
+        let associations = [
+            0x1234u16, 0x961a, // configured, reachable, system peer
+            0x1235, 0x9414, // configured, reachable, candidate
+            0x1236, 0x8011, // configured, unreachable
+        ]
+        .map(u16::to_be_bytes)
+        .concat();
+        match (request.opcode, request.association_id) {
+            (ControlOpcode::READSTAT, 0) => {
+                fragments.push(response(&request, 0x0615, &associations));
+            }
+            (ControlOpcode::READVAR, 0) => {
+                let chunks = variables.as_bytes().chunks(ControlMessage::MAX_DATA_SIZE);
+                let count = chunks.len();
+                for (i, chunk) in chunks.enumerate() {
+                    let mut fragment = response(&request, 0x0615, chunk);
+                    fragment.offset = (i * ControlMessage::MAX_DATA_SIZE) as u16;
+                    fragment.more = i + 1 < count;
+                    fragments.push(fragment);
+                }
+                fragments.reverse();
+                fragments.insert(1, fragments[1]);
+                let mut stale = fragments[0];
+                stale.sequence = request.sequence.wrapping_sub(1);
+                fragments.insert(0, stale);
+            }
+            _ => {
+                let mut error = response(&request, 4 << 8, &[]);
+                error.error = true;
+                fragments.push(error);
+            }
+        }
+
+        for fragment in fragments {
+            let mut packet = [0; 1024];
+            let len = fragment.write_to_buffer(&mut packet).unwrap();
+            socket.send_to(&packet[..len], client).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_control_message_codec() {
+    let request = ControlMessage::read_variables(0x1234, 7, b"stratum,offset");
+    let mut buffer = [0; 64];
+    let len = request.write_to_buffer(&mut buffer).unwrap();
+    assert_eq!(len, 12 + 16);
+    assert_eq!(
+        &buffer[..12],
+        &[0x16, 0x02, 0x12, 0x34, 0, 0, 0, 7, 0, 0, 0, 14]
+    );
+    assert_eq!(&buffer[12..28], b"stratum,offset\0\0");
+    assert_eq!(
+        ControlMessage::read_from_packet(&buffer[..len]).unwrap(),
+        request
+    );
+
+    let mut reply = response(&request, 0x0615, b"stratum=2");
+    reply.more = true;
+    reply.offset = 468;
+    let len = reply.write_to_buffer(&mut buffer).unwrap();
+    assert_eq!(buffer[1], 0x80 | 0x20 | 0x02);
+    assert_eq!(
+        ControlMessage::read_from_packet(&buffer[..len]).unwrap(),
+        reply
+    );
+
+    assert!(matches!(
+        ControlMessage::read_from_packet(&buffer[..20]),
+        Err(SntpProtocolError::TruncatedSntpPacket { .. })
+    ));
+    buffer[0] = 0x23;
+    assert!(matches!(
+        ControlMessage::read_from_packet(&buffer[..len]),
+        Err(SntpProtocolError::UnexpectedSntpMode(Mode::Client))
+    ));
+
+    // Data that does not fit in one fragment is rejected however large the buffer is.
+    let data = [b'x'; ControlMessage::MAX_DATA_SIZE + 1];
+    let mut buffer = [0; 1024];
+    assert!(matches!(
+        response(&request, 0, &data).write_to_buffer(&mut buffer),
+        Err(SntpProtocolError::ControlDataTooLarge {
+            size: 469,
+            max: 468
+        })
+    ));
+    assert!(matches!(
+        request.write_to_buffer(&mut buffer[..20]),
+        Err(SntpProtocolError::SntpBufferTooSmall {
+            size: 20,
+            expected: 28
+        })
+    ));
+}
+
+#[test]
+fn test_reassembly() {
+    let request = ControlMessage::read_variables(1, 0, b"");
+    let data = b"abcdefghij";
+    let fragment = |offset: usize, len: usize, more: bool| ControlMessage {
+        more,
+        offset: offset as u16,
+        ..response(&request, 0, &data[offset..offset + len])
+    };
+
+    let mut buffer = [0; 16];
+    let mut reassembler = ControlReassembler::new(&request, &mut buffer);
+    assert!(!reassembler.add_fragment(&fragment(8, 2, false)).unwrap());
+    assert!(!reassembler.add_fragment(&fragment(0, 4, true)).unwrap());
+    assert!(!reassembler.add_fragment(&fragment(0, 4, true)).unwrap());
+    assert!(matches!(
+        reassembler.add_fragment(&fragment(2, 4, true)),
+        Err(SntpProtocolError::InvalidControlFragment { offset: 2 })
+    ));
+    let mut other = fragment(4, 4, true);
+    other.sequence = 2;
+    assert!(matches!(
+        reassembler.add_fragment(&other),
+        Err(SntpProtocolError::StalePacket)
+    ));
+    assert!(reassembler.add_fragment(&fragment(4, 4, true)).unwrap());
+    assert_eq!(reassembler.finish().unwrap().data, data);
+
+    let mut small = [0; 4];
+    let mut reassembler = ControlReassembler::new(&request, &mut small);
+    assert!(matches!(
+        reassembler.add_fragment(&fragment(0, 10, false)),
+        Err(SntpProtocolError::SntpBufferTooSmall { .. })
+    ));
+    assert!(reassembler.finish().is_none());
+}
+
+#[test]
+fn test_variables() {
+    let variables = system_variables();
+    let response = barentp::control::ControlResponse {
+        status: 0x0615,
+        association_id: 0,
+        data: variables.as_bytes(),
+    };
+    let parsed: Vec<Variable<'_>> = response.variables().collect();
+    assert_eq!(parsed.len(), 11 + 40);
+    assert_eq!(
+        parsed[0],
+        Variable {
+            name: "version",
+            value: "ntpd 4.2.8p15@1.3728-o (1), with, commas"
+        }
+    );
+    assert_eq!(
+        parsed[2],
+        Variable {
+            name: "leap",
+            value: "00"
+        }
+    );
+    assert_eq!(parsed[9].value, "-0.123");
+    assert_eq!(parsed[11].value, "");
+
+    let status = response.system_status();
+    assert_eq!(status.leap_indicator(), LeapIndicator::NoWarning);
+    assert_eq!(status.clock_source(), 6);
+    assert_eq!(status.event_count(), 1);
+    assert_eq!(status.event_code(), 5);
+
+    let peer = PeerStatus(0x961a);
+    assert!(peer.is_configured() && peer.is_reachable() && !peer.is_authenticated());
+    assert_eq!(peer.selection(), 6);
+    assert_eq!(peer.event_count(), 1);
+    assert_eq!(peer.event_code(), 0xa);
+}
+
+#[test]
+fn test_control_queries_over_udp() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let responder = std::thread::spawn(move || responder(server, 4));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    client.connect(server_addr).unwrap();
+    let mut buffer = [0; 4096];
+
+    let response = barentp::sntp_control(&client, &ControlMessage::read_status(1, 0), &mut buffer)
+        .expect("query failed");
+    let associations: Vec<_> = response.associations().collect();
+    assert_eq!(associations.len(), 3);
+    assert_eq!(associations[0].association_id, 0x1234);
+    assert_eq!(associations[0].status.selection(), 6);
+    assert!(!associations[2].status.is_reachable());
+
+    let request = ControlMessage::read_variables(2, 0, b"");
+    let response = barentp::sntp_control(&client, &request, &mut buffer).expect("query failed");
+    assert_eq!(response.data, system_variables().as_bytes());
+    assert!(response.variables().any(|variable| variable
+        == Variable {
+            name: "stratum",
+            value: "2"
+        }));
+
+    let request = ControlMessage::read_variables(3, 0x9999, b"");
+    assert!(matches!(
+        barentp::sntp_control(&client, &request, &mut buffer),
+        Err(Error::SntpProtocol(SntpProtocolError::ControlError(4)))
+    ));
+
+    let request = ControlMessage::read_variables(4, 0, b"");
+    let response = block_on(barentp::nonblocking::sntp_control(
+        &client,
+        &request,
+        &mut buffer,
+    ))
+    .expect("query failed");
+    assert_eq!(response.variables().count(), 51);
+
+    responder.join().unwrap();
+}
@@ -0,0 +1,142 @@
+use barentp::discipline::{ClockDiscipline, DisciplineAction, DisciplineConfig, DisciplineState};
+use barentp::{ClockControl, NtpDuration, Timestamp};
+
+/// Simulated clock with a constant frequency error.
+struct DriftingClock {
+    /// Local time minus true time, in seconds.
+    error: f64,
+    /// Natural frequency error of the oscillator in seconds per second.
+    drift: f64,
+    /// Frequency correction applied by the discipline.
+    correction: f64,
+    steps: usize,
+}
+
+impl DriftingClock {
+    fn new(error: f64, drift: f64) -> Self {
+        Self {
+            error,
+            drift,
+            correction: 0.0,
+            steps: 0,
+        }
+    }
+
+    fn advance_one_second(&mut self) {
+        self.error += self.drift + self.correction;
+    }
+
+    /// Offset of a perfect server relative to this clock.
+    fn measured_offset(&self) -> NtpDuration {
+        NtpDuration::from_seconds_f64(-self.error)
+    }
+}
+
+impl ClockControl for DriftingClock {
+    type Error = std::convert::Infallible;
+
+    fn step(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        self.error += offset.to_seconds_f64();
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn slew(&mut self, offset: NtpDuration) -> Result<(), Self::Error> {
+        self.error += offset.to_seconds_f64();
+        Ok(())
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<(), Self::Error> {
+        self.correction = frequency;
+        Ok(())
+    }
+}
+
+/// Runs the discipline for `seconds`, feeding it an offset at every poll interval.
+fn run(discipline: &mut ClockDiscipline, clock: &mut DriftingClock, seconds: u32) {
+    let start = Timestamp::new(0xE000_0000, 0);
+    let mut next_poll = 0;
+    for second in 0..seconds {
+        if second >= next_poll {
+            let time = start + NtpDuration::from_seconds(second as i32);
+            discipline
+                .update(clock.measured_offset(), time, clock)
+                .unwrap();
+            next_poll = second + (1 << discipline.poll());
+        }
+        discipline.tick(clock).unwrap();
+        clock.advance_one_second();
+    }
+}
+
+#[test]
+fn test_discipline_corrects_frequency_error() {
+    let mut discipline = ClockDiscipline::new(DisciplineConfig::default(), -20);
+    let mut clock = DriftingClock::new(0.010, 50e-6);
+
+    run(&mut discipline, &mut clock, 3 * 86400);
+
+    assert_eq!(discipline.state(), DisciplineState::Sync);
+    assert_eq!(clock.steps, 0);
+    assert!(
+        (clock.correction + clock.drift).abs() < 0.1e-6,
+        "frequency = {}",
+        discipline.frequency()
+    );
+    assert!(clock.error.abs() < 0.001, "error = {}", clock.error);
+    assert!(discipline.poll() > DisciplineConfig::default().min_poll);
+}
+
+#[test]
+fn test_discipline_steps_large_offset() {
+    let mut discipline = ClockDiscipline::new(DisciplineConfig::default(), -20);
+    let mut clock = DriftingClock::new(-2.5, 0.0);
+
+    let time = Timestamp::new(0xE000_0000, 0);
+    let action = discipline
+        .update(clock.measured_offset(), time, &mut clock)
+        .unwrap();
+    assert_eq!(action, DisciplineAction::Step);
+    assert_eq!(discipline.state(), DisciplineState::Frequency);
+    assert!(clock.error.abs() < 1e-6);
+}
+
+#[test]
+fn test_discipline_ignores_spikes() {
+    let mut discipline = ClockDiscipline::with_frequency(DisciplineConfig::default(), -20, 0.0);
+    let mut clock = DriftingClock::new(0.0, 0.0);
+
+    let time = Timestamp::new(0xE000_0000, 0);
+    discipline
+        .update(NtpDuration::from_millis(1), time, &mut clock)
+        .unwrap();
+
+    // A single large offset is treated as a spike...
+    let time = time + NtpDuration::from_seconds(64);
+    let action = discipline
+        .update(NtpDuration::from_seconds(1), time, &mut clock)
+        .unwrap();
+    assert_eq!(action, DisciplineAction::Ignore);
+    assert_eq!(discipline.state(), DisciplineState::Spike);
+
+    // ...until it persists for longer than the stepout interval.
+    let time = time + NtpDuration::from_seconds(1000);
+    let action = discipline
+        .update(NtpDuration::from_seconds(1), time, &mut clock)
+        .unwrap();
+    assert_eq!(action, DisciplineAction::Step);
+    assert_eq!(clock.steps, 1);
+}
+
+#[test]
+fn test_discipline_panics() {
+    let mut discipline = ClockDiscipline::new(DisciplineConfig::default(), -20);
+    let mut clock = DriftingClock::new(0.0, 0.0);
+
+    let time = Timestamp::new(0xE000_0000, 0);
+    let action = discipline
+        .update(NtpDuration::from_seconds(5000), time, &mut clock)
+        .unwrap();
+    assert_eq!(action, DisciplineAction::Panic);
+    assert_eq!(clock.steps, 0);
+}
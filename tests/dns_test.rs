@@ -0,0 +1,168 @@
+use barentp::dns::{self, ServerPool, NTP_PORT};
+use barentp::error::Error;
+use barentp::server::{ServerConfig, SntpServer};
+use barentp::{NtpDuration, SystemClock};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Serves `count` requests on a local port in a background thread.
+fn spawn_server(count: usize) -> (SocketAddr, std::thread::JoinHandle<()>) {
+    spawn_server_on("127.0.0.1:0", count)
+}
+
+fn spawn_server_on(local: &str, count: usize) -> (SocketAddr, std::thread::JoinHandle<()>) {
+    let socket = UdpSocket::bind(local).unwrap();
+    let addr = socket.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut server = SntpServer::new(ServerConfig::default());
+        for _ in 0..count {
+            barentp::sntp_serve_one(&mut server, &socket, &SystemClock).expect("serve failed");
+        }
+    });
+    (addr, handle)
+}
+
+#[test]
+fn test_resolve() {
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    assert_eq!(dns::resolve("127.0.0.1").unwrap(), [addr("127.0.0.1:123")]);
+    assert_eq!(
+        dns::resolve("127.0.0.1:1123").unwrap(),
+        [addr("127.0.0.1:1123")]
+    );
+    assert_eq!(dns::resolve("::1").unwrap(), [addr("[::1]:123")]);
+    assert_eq!(dns::resolve("[::1]").unwrap(), [addr("[::1]:123")]);
+    assert_eq!(dns::resolve("[::1]:1123").unwrap(), [addr("[::1]:1123")]);
+    assert!(dns::resolve("localhost")
+        .unwrap()
+        .iter()
+        .all(|addr| addr.ip().is_loopback() && addr.port() == NTP_PORT));
+
+    for invalid in ["localhost:ntp", "[::1]:", "[::1]x"] {
+        assert_eq!(
+            dns::resolve(invalid).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput,
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn test_query_host() {
+    let (addr, server) = spawn_server(2);
+    let timeout = Duration::from_secs(5);
+    let reply = dns::sntp_query_host(&addr.to_string(), &SystemClock, timeout).unwrap();
+    assert_eq!(reply.addr, addr);
+    assert_eq!(reply.socket.peer_addr().unwrap(), addr);
+    assert!(reply.sample.offset.abs() < NtpDuration::from_millis(10));
+
+    // The socket can be used to poll the server again.
+    barentp::sntp_query(&reply.socket, &SystemClock).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_query_host_parallel() {
+    let (addr, server) = spawn_server(1);
+    let timeout = Duration::from_secs(5);
+    let reply = dns::sntp_query_host_parallel(&addr.to_string(), &SystemClock, timeout).unwrap();
+    assert_eq!(reply.addr, addr);
+    assert_eq!(reply.socket.read_timeout().unwrap(), Some(timeout));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_query_host_timeout() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let host = silent.local_addr().unwrap().to_string();
+    let timeout = Duration::from_millis(200);
+
+    let err = dns::sntp_query_host(&host, &SystemClock, timeout).unwrap_err();
+    assert!(matches!(err, Error::TransportRecv(_)));
+
+    let start = Instant::now();
+    let err = dns::sntp_query_host_parallel(&host, &SystemClock, timeout).unwrap_err();
+    assert!(matches!(err, Error::TransportRecv(ref err) if err.kind() == io::ErrorKind::TimedOut));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let err = dns::sntp_query_host("[::1]:x", &SystemClock, timeout).unwrap_err();
+    assert!(matches!(err, Error::Resolve(ref err) if err.kind() == io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn test_server_pool() {
+    let (addr, server) = spawn_server(1);
+    let mut pool = ServerPool::new(addr.to_string()).with_timeout(Duration::from_secs(1));
+    let socket = pool.next_server().unwrap();
+    assert_eq!(socket.peer_addr().unwrap(), addr);
+    assert_eq!(pool.in_use(), [addr]);
+    barentp::sntp_query(&socket, &SystemClock).unwrap();
+    server.join().unwrap();
+
+    // The only server of the pool is in use until it is released.
+    assert_eq!(
+        pool.next_server().unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+    pool.release(addr);
+    assert!(pool.in_use().is_empty());
+    assert_eq!(pool.next_server().unwrap().peer_addr().unwrap(), addr);
+}
+
+#[test]
+fn test_happy_eyeballs_prefers_ipv6() {
+    let (v4, server) = spawn_server(1);
+    let silent_v6 = UdpSocket::bind("[::1]:0").unwrap();
+    let v6 = silent_v6.local_addr().unwrap();
+
+    // The IPv6 address is tried first, and the IPv4 address once it has not replied in time.
+    let start = Instant::now();
+    let reply =
+        dns::sntp_query_happy_eyeballs(&[v4, v6], &SystemClock, Duration::from_secs(5)).unwrap();
+    let elapsed = start.elapsed();
+    server.join().unwrap();
+    assert_eq!(reply.addr, v4);
+    assert!(elapsed >= dns::ATTEMPT_DELAY, "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+    let (v6, server) = spawn_server_on("[::1]:0", 1);
+    let silent_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let v4 = silent_v4.local_addr().unwrap();
+    let start = Instant::now();
+    let reply =
+        dns::sntp_query_happy_eyeballs(&[v4, v6], &SystemClock, Duration::from_secs(5)).unwrap();
+    server.join().unwrap();
+    assert_eq!(reply.addr, v6);
+    assert_eq!(reply.socket.peer_addr().unwrap(), v6);
+    assert!(start.elapsed() < dns::ATTEMPT_DELAY);
+}
+
+#[test]
+fn test_happy_eyeballs_errors() {
+    let err =
+        dns::sntp_query_happy_eyeballs(&[], &SystemClock, Duration::from_secs(1)).unwrap_err();
+    assert!(matches!(err, Error::Resolve(_)));
+
+    let silent_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent_v6 = UdpSocket::bind("[::1]:0").unwrap();
+    let addrs = [
+        silent_v4.local_addr().unwrap(),
+        silent_v6.local_addr().unwrap(),
+    ];
+    let start = Instant::now();
+    let err = dns::sntp_query_happy_eyeballs(&addrs, &SystemClock, Duration::from_millis(500))
+        .unwrap_err();
+    assert!(matches!(err, Error::TransportRecv(ref err) if err.kind() == io::ErrorKind::TimedOut));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_query_host_zero_timeout() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let host = silent.local_addr().unwrap().to_string();
+    let err = dns::sntp_query_host_parallel(&host, &SystemClock, Duration::ZERO).unwrap_err();
+    assert!(matches!(err, Error::TransportRecv(ref err) if err.kind() == io::ErrorKind::TimedOut));
+    let err = dns::sntp_query_host_happy_eyeballs(&host, &SystemClock, Duration::ZERO).unwrap_err();
+    assert!(matches!(err, Error::TransportRecv(ref err) if err.kind() == io::ErrorKind::TimedOut));
+}
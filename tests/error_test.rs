@@ -0,0 +1,172 @@
+use barentp::error::{BoxError, Error, QueryError, SntpProtocolError, TransportError};
+use barentp::protocol::KissCode;
+use std::io;
+use std::net::SocketAddr;
+
+/// A transport error that is not an `io::Error`.
+#[derive(Debug)]
+struct TimedOut;
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out waiting for a reply")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+type IoError = Error<io::Error>;
+
+fn timed_out() -> IoError {
+    Error::TransportRecv(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "no reply from server",
+    ))
+}
+
+#[test]
+fn test_display_includes_cause() {
+    assert_eq!(
+        timed_out().to_string(),
+        "transport recv error: no reply from server"
+    );
+    let err: IoError = Error::TransportSend(io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        "connection refused",
+    ));
+    assert_eq!(err.to_string(), "transport send error: connection refused");
+    let err: IoError = SntpProtocolError::KissOfDeath(KissCode::RATE).into();
+    assert_eq!(
+        err.to_string(),
+        "SNTP protocol error: kiss-o'-death received: RATE"
+    );
+    let err: IoError = Error::Resolve(io::Error::new(io::ErrorKind::NotFound, "unknown host"));
+    assert_eq!(err.to_string(), "failed to resolve host: unknown host");
+}
+
+#[test]
+fn test_context() {
+    let server: SocketAddr = "192.0.2.1:123".parse().unwrap();
+    let err = timed_out().context(server, 3);
+    assert_eq!(err.server, server);
+    assert_eq!(err.attempt, 3);
+    assert!(matches!(err.error, Error::TransportRecv(_)));
+    assert_eq!(
+        err.to_string(),
+        "query of 192.0.2.1:123 failed on attempt 3: transport recv error: no reply from server"
+    );
+}
+
+/// The messages of an error and each of its sources, as error reporters print them.
+fn chain(err: &(dyn std::error::Error + 'static)) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut source = Some(err);
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+    messages
+}
+
+#[test]
+fn test_sources_do_not_repeat_the_message() {
+    let server: SocketAddr = "192.0.2.1:123".parse().unwrap();
+    let resolve = IoError::Resolve(io::Error::new(io::ErrorKind::NotFound, "unknown host"));
+    let errors: [BoxError; 6] = [
+        timed_out().into(),
+        timed_out().context(server, 1).into(),
+        timed_out().erase().context(server, 2).into(),
+        Error::<TimedOut>::TransportSend(TimedOut).into(),
+        IoError::from(SntpProtocolError::KissOfDeath(KissCode::RATE)).into(),
+        resolve.context("pool.ntp.org", 1).into(),
+    ];
+    for err in &errors {
+        let messages = chain(&**err);
+        for (i, message) in messages.iter().enumerate() {
+            for source in &messages[i + 1..] {
+                assert!(!message.contains(source.as_str()), "{messages:?}");
+            }
+        }
+    }
+    assert_eq!(
+        chain(&*errors[1]),
+        ["query of 192.0.2.1:123 failed on attempt 1: transport recv error: no reply from server"]
+    );
+}
+
+#[test]
+fn test_box_error() {
+    fn query(attempt: u32) -> Result<(), BoxError> {
+        match attempt {
+            1 => Err(timed_out())?,
+            _ => Err(timed_out().context("pool.ntp.org", attempt))?,
+        }
+    }
+
+    let err = query(1).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "transport recv error: no reply from server"
+    );
+    assert!(err.downcast_ref::<IoError>().is_some());
+
+    let err = query(2).unwrap_err();
+    assert!(err.to_string().starts_with("query of pool.ntp.org failed"));
+    let err = err
+        .downcast::<QueryError<&str, IoError>>()
+        .expect("keeps its type");
+    assert_eq!(err.attempt, 2);
+}
+
+#[test]
+fn test_into_io_error() {
+    fn query(err: IoError) -> io::Result<()> {
+        Err(err)?
+    }
+
+    let err = query(timed_out()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(err.to_string(), "no reply from server");
+
+    let err = query(SntpProtocolError::ServerUnsynchronized.into()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "server is not synchronized");
+
+    let resolve = Error::Resolve(io::Error::new(io::ErrorKind::NotFound, "unknown host"));
+    assert_eq!(query(resolve).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_erase() {
+    let errors: [Error<TransportError>; 3] = [
+        timed_out().erase(),
+        Error::<TimedOut>::TransportRecv(TimedOut).erase(),
+        Error::<TimedOut>::SntpProtocol(SntpProtocolError::StalePacket).erase(),
+    ];
+    assert_eq!(
+        errors[0].to_string(),
+        "transport recv error: no reply from server"
+    );
+    assert_eq!(
+        errors[1].to_string(),
+        "transport recv error: timed out waiting for a reply"
+    );
+    assert!(matches!(
+        errors[2],
+        Error::SntpProtocol(SntpProtocolError::StalePacket)
+    ));
+
+    // The boxed transport error keeps its type.
+    let Error::TransportRecv(ref err) = errors[1] else {
+        panic!("{:?}", errors[1]);
+    };
+    assert!(err.get_ref().is::<TimedOut>());
+
+    // Erased errors can be sent to other threads and converted into any error type, e.g. for
+    // error handling libraries.
+    let err: BoxError = std::thread::spawn(|| timed_out().erase())
+        .join()
+        .unwrap()
+        .into();
+    assert!(err.downcast_ref::<Error<TransportError>>().is_some());
+}
@@ -0,0 +1,77 @@
+use barentp::filter::{ClockFilter, FilterSample};
+use barentp::{NtpDuration, Timestamp};
+
+fn sample(seconds: u32, offset_millis: i64, delay_millis: i64) -> FilterSample {
+    FilterSample {
+        offset: NtpDuration::from_millis(offset_millis),
+        delay: NtpDuration::from_millis(delay_millis),
+        dispersion: NtpDuration::from_micros(10),
+        time: Timestamp::new(0xE000_0000 + seconds, 0),
+    }
+}
+
+#[test]
+fn test_filter_picks_minimum_delay() {
+    let mut filter = ClockFilter::new(-20);
+    filter
+        .add(sample(0, 5, 40))
+        .expect("first sample is always new");
+    let output = filter
+        .add(sample(64, 1, 10))
+        .expect("lower delay sample is new");
+    assert_eq!(output.offset, NtpDuration::from_millis(1));
+    assert_eq!(output.delay, NtpDuration::from_millis(10));
+
+    // A noisier sample doesn't replace the best one, and the best one isn't reported twice.
+    assert!(filter.add(sample(128, 30, 80)).is_none());
+    assert_eq!(filter.output().unwrap().offset, NtpDuration::from_millis(1));
+    assert_eq!(filter.samples().count(), 3);
+}
+
+#[test]
+fn test_filter_only_keeps_eight_samples() {
+    let mut filter = ClockFilter::new(-20);
+    filter.add(sample(0, 0, 1));
+    for i in 1..=ClockFilter::STAGES as u32 {
+        filter.add(sample(i * 64, 2, 20));
+    }
+
+    // The low delay sample was shifted out, so the filter moves on to the newer samples.
+    assert_eq!(filter.samples().count(), ClockFilter::STAGES);
+    let output = filter.output().unwrap();
+    assert_eq!(output.delay, NtpDuration::from_millis(20));
+}
+
+#[test]
+fn test_filter_jitter() {
+    let mut filter = ClockFilter::new(-20);
+    filter.add(sample(0, 0, 10));
+    filter.add(sample(64, 3, 20));
+    let output = filter
+        .add(sample(128, -4, 30))
+        .unwrap_or(filter.output().unwrap());
+
+    // sqrt((3^2 + 4^2) / 2) = 3.54ms
+    assert_eq!(output.offset, NtpDuration::ZERO);
+    assert_eq!(output.jitter.as_micros(), 3535);
+}
+
+#[test]
+fn test_filter_dispersion_ages() {
+    let mut filter = ClockFilter::new(-20);
+    let output = filter.add(sample(0, 0, 10)).unwrap();
+
+    // An empty filter is dominated by the dispersion of its empty stages.
+    assert!(output.dispersion > NtpDuration::from_seconds(7));
+
+    for i in 1..ClockFilter::STAGES as u32 {
+        filter.add(sample(i, 0, 10));
+    }
+    let fresh = filter.output().unwrap().dispersion;
+    assert!(fresh < NtpDuration::from_millis(1));
+
+    // 15 PPM over 1000 seconds.
+    let now = Timestamp::new(0xE000_0000 + 1007, 0);
+    let aged = filter.dispersion_at(now).unwrap();
+    assert!((14_999..=15_000).contains(&(aged - fresh).as_micros()));
+}
@@ -0,0 +1,225 @@
+use barentp::association::{Association, AssociationConfig};
+use barentp::server::{InterleavedSlot, ServerConfig, SntpServer};
+use barentp::{NtpClock, NtpDuration, NtpTransport, SystemClock, Timestamp};
+use std::net::{IpAddr, UdpSocket};
+use std::sync::Mutex;
+
+mod common;
+use common::{assert_close, FixedClock, ManualClock, START};
+
+/// The server's clock, which is 5ms ahead of the local clock.
+struct ServerClock<'a>(&'a ManualClock);
+
+impl NtpClock for ServerClock<'_> {
+    fn now(&self) -> Timestamp {
+        self.0.now() + NtpDuration::from_millis(5)
+    }
+}
+
+/// A server 1ms away in each direction that sends each reply 1ms after timestamping it.
+struct Link<'a> {
+    clock: &'a ManualClock,
+    server: Mutex<SntpServer<'a>>,
+    reply: Mutex<Vec<u8>>,
+}
+
+impl<'a> Link<'a> {
+    fn new(clock: &'a ManualClock, server: SntpServer<'a>) -> Self {
+        Link {
+            clock,
+            server: Mutex::new(server),
+            reply: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl NtpTransport for Link<'_> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        let server_clock = ServerClock(self.clock);
+        let mut server = self.server.lock().unwrap();
+        let mut reply = [0; 48];
+
+        self.clock.advance(NtpDuration::from_millis(1));
+        let len = server
+            .handle_request(
+                buffer,
+                IpAddr::from([192, 0, 2, 1]),
+                server_clock.now(),
+                &server_clock,
+                &mut reply,
+            )
+            .expect("request dropped");
+        self.clock.advance(NtpDuration::from_millis(1));
+        server.sent(server_clock.now());
+        self.clock.advance(NtpDuration::from_millis(1));
+
+        *self.reply.lock().unwrap() = reply[..len].to_vec();
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        let reply = self.reply.lock().unwrap();
+        buffer[..reply.len()].copy_from_slice(&reply);
+        Ok(reply.len())
+    }
+}
+
+fn interleaved_association() -> Association {
+    let config = AssociationConfig {
+        interleaved: true,
+        ..AssociationConfig::default()
+    };
+    Association::new(config, -20)
+}
+
+#[test]
+fn test_interleaved_client_and_server() {
+    let clock = ManualClock::new(START);
+    let mut slots = [InterleavedSlot::EMPTY; 16];
+    let link = Link::new(
+        &clock,
+        SntpServer::new(ServerConfig::default()).with_interleaved(&mut slots),
+    );
+    let mut association = interleaved_association();
+
+    // The first reply is in basic mode, and its transmit timestamp is 1ms early.
+    let sample = barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+    assert!(!association.is_interleaved());
+    assert_close(sample.offset, NtpDuration::from_micros(4500));
+    assert_close(sample.delay, NtpDuration::from_millis(3));
+
+    for _ in 0..3 {
+        clock.advance(NtpDuration::from_seconds(64));
+        let sample = barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+        assert!(association.is_interleaved());
+        assert_close(sample.offset, NtpDuration::from_millis(5));
+        assert_close(sample.delay, NtpDuration::from_millis(2));
+    }
+}
+
+#[test]
+fn test_interleaved_falls_back_to_basic_mode() {
+    let clock = ManualClock::new(START);
+    let link = Link::new(&clock, SntpServer::new(ServerConfig::default()));
+    let mut association = interleaved_association();
+    for _ in 0..3 {
+        let sample = barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+        assert!(!association.is_interleaved());
+        assert_close(sample.offset, NtpDuration::from_micros(4500));
+        clock.advance(NtpDuration::from_seconds(64));
+    }
+
+    // A server that has forgotten the previous reply answers in basic mode.
+    let mut slots = [InterleavedSlot::EMPTY; 1];
+    let link = Link::new(
+        &clock,
+        SntpServer::new(ServerConfig::default()).with_interleaved(&mut slots),
+    );
+    let mut association = interleaved_association();
+    barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+    barentp::sntp_query(&link, &clock).expect("query failed");
+    clock.advance(NtpDuration::from_seconds(64));
+    barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+    assert!(!association.is_interleaved());
+    clock.advance(NtpDuration::from_seconds(64));
+    barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+    assert!(association.is_interleaved());
+
+    // Clients that do not ask for interleaved mode never get it.
+    let mut association = Association::new(AssociationConfig::default(), -20);
+    for _ in 0..2 {
+        clock.advance(NtpDuration::from_seconds(64));
+        barentp::sntp_poll(&mut association, &link, &clock).expect("poll failed");
+        assert!(!association.is_interleaved());
+    }
+}
+
+#[test]
+fn test_interleaved_over_udp() {
+    let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server_socket.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut slots = [InterleavedSlot::EMPTY; 16];
+        let mut server = SntpServer::new(ServerConfig::default()).with_interleaved(&mut slots);
+        for _ in 0..3 {
+            barentp::sntp_serve_one(&mut server, &server_socket, &SystemClock)
+                .expect("serve failed");
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    client.connect(server_addr).unwrap();
+    let mut association = interleaved_association();
+    barentp::sntp_poll(&mut association, &client, &SystemClock).expect("poll failed");
+    assert!(!association.is_interleaved());
+    for _ in 0..2 {
+        let sample =
+            barentp::sntp_poll(&mut association, &client, &SystemClock).expect("poll failed");
+        assert!(association.is_interleaved());
+        assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn test_interleaved_replies_are_per_client() {
+    let mut slots = [InterleavedSlot::EMPTY; 8];
+    let mut server = SntpServer::new(ServerConfig::default()).with_interleaved(&mut slots);
+    let first = IpAddr::from([192, 0, 2, 1]);
+    let second = IpAddr::from([192, 0, 2, 2]);
+    let received = START + NtpDuration::from_seconds(1);
+    let sent = received + NtpDuration::from_millis(1);
+
+    let request = |originate: Timestamp, receive: Timestamp| {
+        let mut msg = barentp::protocol::SntpMessage::new_v4();
+        msg.mode = barentp::protocol::Mode::Client;
+        msg.originate_timestamp = originate;
+        msg.receive_timestamp = receive;
+        msg.transmit_timestamp = START;
+        let mut buffer = [0; 48];
+        msg.write_to_buffer(&mut buffer).unwrap();
+        buffer
+    };
+    let reply = |server: &mut SntpServer, client, received: Timestamp, request: &[u8]| {
+        let mut buffer = [0; 48];
+        let len = server
+            .handle_request(
+                request,
+                client,
+                received,
+                &FixedClock(received),
+                &mut buffer,
+            )
+            .expect("request dropped");
+        let mut msg = barentp::protocol::SntpMessage::new_v4();
+        msg.read_from_buffer(&buffer[..len]).unwrap();
+        msg
+    };
+
+    let msg = reply(
+        &mut server,
+        first,
+        received,
+        &request(Timestamp::new(0, 0), START),
+    );
+    assert_eq!(msg.receive_timestamp, received);
+    server.sent(sent);
+
+    // Another client that sends back the receive timestamp of the first client's request gets a
+    // basic reply instead of the time the first client's reply was sent.
+    let later = received + NtpDuration::from_seconds(1);
+    let msg = reply(&mut server, second, later, &request(received, START));
+    assert_eq!(msg.originate_timestamp, START);
+    assert_eq!(msg.transmit_timestamp, later);
+
+    let later = later + NtpDuration::from_seconds(1);
+    let msg = reply(&mut server, first, later, &request(received, START));
+    assert_eq!(msg.originate_timestamp, START);
+    assert_eq!(msg.transmit_timestamp, sent);
+}
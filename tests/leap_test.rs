@@ -0,0 +1,326 @@
+use barentp::error::LeapTableError;
+use barentp::leap::{
+    GpsTime, LeapEntry, LeapHandler, LeapKind, LeapMode, LeapSecond, LeapTable, UnixTaiTime,
+};
+use barentp::protocol::LeapIndicator;
+use barentp::{NtpDuration, Timestamp};
+
+mod common;
+use common::FixedClock;
+
+/// 1 January 2017, right after the leap second inserted at the end of 2016.
+const MIDNIGHT: Timestamp = Timestamp::new(3_692_217_600, 0);
+const HOUR: NtpDuration = NtpDuration::from_seconds(3600);
+const DAY: NtpDuration = NtpDuration::from_seconds(86_400);
+
+/// The IERS table as shipped with tzdata, last updated in 2025 and expiring on 28 June 2026.
+const LEAP_SECONDS_LIST: &str = include_str!("data/leap-seconds.list");
+const UPDATED: Timestamp = Timestamp::new(3_960_835_200, 0);
+const EXPIRES: Timestamp = Timestamp::new(3_991_593_600, 0);
+
+const INSERT: LeapSecond = LeapSecond {
+    at: MIDNIGHT,
+    kind: LeapKind::Insert,
+};
+
+fn handler(mode: LeapMode, leap: LeapSecond) -> LeapHandler {
+    let mut handler = LeapHandler::new(mode);
+    handler.schedule(Some(leap));
+    handler
+}
+
+#[test]
+fn test_step_at_midnight() {
+    let mut leap = handler(LeapMode::Step, INSERT);
+    let before = MIDNIGHT - NtpDuration::from_millis(1);
+    assert_eq!(leap.offset_at(before), NtpDuration::ZERO);
+    assert_eq!(leap.corrected(before), before);
+    assert_eq!(leap.offset_at(MIDNIGHT), NtpDuration::from_seconds(-1));
+    assert_eq!(
+        leap.now(&FixedClock(MIDNIGHT + HOUR)),
+        MIDNIGHT + HOUR - NtpDuration::from_seconds(1)
+    );
+    assert!(!leap.is_smearing(before));
+
+    assert_eq!(leap.take_completed(before), None);
+    assert_eq!(
+        leap.take_completed(MIDNIGHT),
+        Some(NtpDuration::from_seconds(-1))
+    );
+    assert_eq!(leap.pending(), None);
+    assert_eq!(leap.offset_at(MIDNIGHT + HOUR), NtpDuration::ZERO);
+}
+
+#[test]
+fn test_smear_over_24_hours() {
+    let mut leap = handler(LeapMode::SMEAR_24H, INSERT);
+    let noon_before = MIDNIGHT - DAY / 2;
+    let noon_after = MIDNIGHT + DAY / 2;
+
+    assert_eq!(leap.offset_at(noon_before - HOUR), NtpDuration::ZERO);
+    assert_eq!(leap.offset_at(noon_before), NtpDuration::ZERO);
+    assert!(!leap.is_smearing(noon_before));
+    assert_eq!(
+        leap.offset_at(MIDNIGHT - HOUR * 6),
+        NtpDuration::from_millis(-250)
+    );
+    assert_eq!(leap.offset_at(MIDNIGHT), NtpDuration::from_millis(-500));
+    assert!(leap.is_smearing(MIDNIGHT));
+    assert_eq!(leap.offset_at(noon_after), NtpDuration::from_seconds(-1));
+    assert!(!leap.is_smearing(noon_after));
+
+    // The smeared time never goes backwards across midnight.
+    let step = NtpDuration::from_millis(100);
+    let mut previous = leap.corrected(MIDNIGHT - step * 10);
+    for i in -9..10 {
+        let corrected = leap.corrected(MIDNIGHT + step * i);
+        assert!(corrected - previous > NtpDuration::ZERO);
+        previous = corrected;
+    }
+
+    assert_eq!(leap.take_completed(MIDNIGHT), None);
+    assert_eq!(
+        leap.take_completed(noon_after),
+        Some(NtpDuration::from_seconds(-1))
+    );
+    assert_eq!(leap.pending(), None);
+}
+
+#[test]
+fn test_deleted_leap_second() {
+    let leap = handler(
+        LeapMode::SMEAR_24H,
+        LeapSecond {
+            at: MIDNIGHT,
+            kind: LeapKind::Delete,
+        },
+    );
+    assert_eq!(leap.offset_at(MIDNIGHT), NtpDuration::from_millis(500));
+    assert_eq!(leap.offset_at(MIDNIGHT + DAY), NtpDuration::from_seconds(1));
+}
+
+#[test]
+fn test_leap_indicator_from_servers() {
+    let mid_december = MIDNIGHT - DAY * 15;
+    let mut leap = LeapHandler::new(LeapMode::SMEAR_24H);
+
+    leap.handle_leap_indicator(LeapIndicator::AlarmCondition, mid_december);
+    assert_eq!(leap.pending(), None);
+    leap.handle_leap_indicator(LeapIndicator::LastMinuteHas61Seconds, mid_december);
+    assert_eq!(leap.pending(), Some(INSERT));
+
+    // Servers that stop announcing the leap second cancel it until it starts.
+    leap.handle_leap_indicator(LeapIndicator::NoWarning, mid_december + HOUR);
+    assert_eq!(leap.pending(), None);
+    leap.handle_leap_indicator(LeapIndicator::LastMinuteHas61Seconds, mid_december);
+    leap.handle_leap_indicator(LeapIndicator::NoWarning, MIDNIGHT - HOUR);
+    assert_eq!(leap.pending(), Some(INSERT));
+
+    // A warning on the last day of the month is for the end of that day.
+    let mut leap = LeapHandler::new(LeapMode::Step);
+    leap.handle_leap_indicator(LeapIndicator::LastMinuteHas59Seconds, MIDNIGHT - HOUR);
+    assert_eq!(
+        leap.pending(),
+        Some(LeapSecond {
+            at: MIDNIGHT,
+            kind: LeapKind::Delete,
+        })
+    );
+}
+
+#[test]
+fn test_scheduled_leap_second_survives_servers_without_warning() {
+    let mut leap = handler(LeapMode::Step, INSERT);
+    leap.handle_leap_indicator(LeapIndicator::NoWarning, MIDNIGHT - DAY * 40);
+    assert_eq!(leap.pending(), Some(INSERT));
+}
+
+#[test]
+fn test_announce_leap_second() {
+    let leap = handler(LeapMode::Step, INSERT);
+    assert_eq!(
+        leap.leap_indicator(MIDNIGHT - DAY * 40),
+        LeapIndicator::NoWarning
+    );
+    assert_eq!(
+        leap.leap_indicator(MIDNIGHT - DAY * 10),
+        LeapIndicator::LastMinuteHas61Seconds
+    );
+    assert_eq!(leap.leap_indicator(MIDNIGHT), LeapIndicator::NoWarning);
+
+    // Smeared leap seconds are hidden from clients.
+    let leap = handler(LeapMode::SMEAR_24H, INSERT);
+    assert_eq!(
+        leap.leap_indicator(MIDNIGHT - DAY * 10),
+        LeapIndicator::NoWarning
+    );
+}
+
+#[test]
+fn test_parse_leap_table() {
+    let mut entries = [LeapEntry::EMPTY; 32];
+    let table = LeapTable::parse(LEAP_SECONDS_LIST, UPDATED, &mut entries).unwrap();
+    assert_eq!(table.updated(), UPDATED);
+    assert_eq!(table.expires(), EXPIRES);
+    assert_eq!(table.entries().len(), 28);
+    assert_eq!(
+        table.entries()[0],
+        LeapEntry {
+            at: Timestamp::new(2_272_060_800, 0),
+            tai_offset: 10,
+        }
+    );
+
+    assert_eq!(table.tai_offset(Timestamp::new(2_272_060_799, 0)), None);
+    assert_eq!(table.tai_offset(Timestamp::new(2_272_060_800, 0)), Some(10));
+    assert_eq!(table.tai_offset(MIDNIGHT - HOUR), Some(36));
+    assert_eq!(table.tai_offset(MIDNIGHT), Some(37));
+    assert_eq!(table.tai_offset(UPDATED), Some(37));
+
+    assert_eq!(table.next_leap(MIDNIGHT - DAY * 100), Some(INSERT));
+    assert_eq!(table.next_leap(MIDNIGHT), None);
+    assert!(!table.is_expired(EXPIRES - HOUR));
+    assert!(table.is_expired(EXPIRES));
+
+    // Entries are compared across the NTP era boundary in 2036.
+    let after_era = Timestamp::from_utc(2_200_000_000, 0);
+    assert_eq!(table.tai_offset(after_era), Some(37));
+}
+
+#[test]
+fn test_leap_table_schedules_handler() {
+    let mut entries = [LeapEntry::EMPTY; 32];
+    let table = LeapTable::parse(LEAP_SECONDS_LIST, UPDATED, &mut entries).unwrap();
+    let mut leap = LeapHandler::new(LeapMode::Step);
+    leap.schedule(table.next_leap(MIDNIGHT - DAY / 2));
+    assert_eq!(leap.pending(), Some(INSERT));
+    assert_eq!(
+        leap.corrected(MIDNIGHT),
+        MIDNIGHT - NtpDuration::from_seconds(1)
+    );
+}
+
+#[test]
+fn test_leap_table_errors() {
+    let mut entries = [LeapEntry::EMPTY; 32];
+    assert_eq!(
+        LeapTable::parse(LEAP_SECONDS_LIST, EXPIRES, &mut entries).unwrap_err(),
+        LeapTableError::Expired
+    );
+
+    let tampered = LEAP_SECONDS_LIST.replace("37      # 1 Jan 2017", "38      # 1 Jan 2017");
+    assert_ne!(tampered, LEAP_SECONDS_LIST);
+    assert_eq!(
+        LeapTable::parse(&tampered, UPDATED, &mut entries).unwrap_err(),
+        LeapTableError::HashMismatch
+    );
+
+    let mut small = [LeapEntry::EMPTY; 16];
+    assert_eq!(
+        LeapTable::parse(LEAP_SECONDS_LIST, UPDATED, &mut small).unwrap_err(),
+        LeapTableError::TooManyEntries { capacity: 16 }
+    );
+
+    let unhashed: String = LEAP_SECONDS_LIST
+        .lines()
+        .filter(|line| !line.starts_with("#h"))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    assert_eq!(
+        LeapTable::parse(&unhashed, UPDATED, &mut entries).unwrap_err(),
+        LeapTableError::MissingField("hash")
+    );
+
+    let garbled = format!("{LEAP_SECONDS_LIST}\n3692217600 37 38\n");
+    assert!(matches!(
+        LeapTable::parse(&garbled, UPDATED, &mut entries).unwrap_err(),
+        LeapTableError::InvalidLine { .. }
+    ));
+}
+
+#[test]
+fn test_tai_and_gps_conversions() {
+    let mut entries = [LeapEntry::EMPTY; 32];
+    let table = LeapTable::parse(LEAP_SECONDS_LIST, UPDATED, &mut entries).unwrap();
+    let unix_midnight = 1_483_228_800;
+
+    assert_eq!(
+        table.utc_to_unix_tai(MIDNIGHT),
+        Some(UnixTaiTime {
+            seconds: unix_midnight + 37,
+            fraction: 0,
+        })
+    );
+    assert_eq!(
+        table.utc_to_tai(MIDNIGHT),
+        Some(MIDNIGHT + NtpDuration::from_seconds(37))
+    );
+    // GPS week 1930 started on 1 January 2017, when GPS time was 18 seconds ahead of UTC.
+    assert_eq!(
+        table.utc_to_gps(MIDNIGHT),
+        Some(GpsTime {
+            week: 1930,
+            seconds: 18,
+            fraction: 0,
+        })
+    );
+
+    let gps_epoch = Timestamp::from_utc(315_964_800, 0);
+    assert_eq!(
+        table.utc_to_gps(gps_epoch),
+        Some(GpsTime {
+            week: 0,
+            seconds: 0,
+            fraction: 0,
+        })
+    );
+    assert_eq!(
+        table.utc_to_gps(gps_epoch - NtpDuration::from_seconds(1)),
+        None
+    );
+    assert_eq!(table.utc_to_tai(Timestamp::new(2_272_060_799, 0)), None);
+
+    // The inserted leap second converts to the following midnight.
+    let leap_second = UnixTaiTime {
+        seconds: unix_midnight + 36,
+        fraction: 0x8000_0000,
+    };
+    assert_eq!(
+        table.unix_tai_to_utc(leap_second),
+        Some(Timestamp::new(MIDNIGHT.seconds(), 0x8000_0000))
+    );
+    assert_eq!(
+        table.gps_to_utc(GpsTime {
+            week: 1930,
+            seconds: GpsTime::SECONDS_PER_WEEK as u32,
+            fraction: 0,
+        }),
+        None
+    );
+}
+
+#[test]
+fn test_conversions_round_trip() {
+    let mut entries = [LeapEntry::EMPTY; 32];
+    let table = LeapTable::parse(LEAP_SECONDS_LIST, UPDATED, &mut entries).unwrap();
+    let times = [
+        Timestamp::new(2_287_785_599, 0xFFFF_FFFF),
+        Timestamp::new(3_029_443_200, 1),
+        MIDNIGHT - NtpDuration::from_seconds(1),
+        Timestamp::new(MIDNIGHT.seconds(), 0x1234_5678),
+        UPDATED,
+        Timestamp::from_utc(2_500_000_000, 999_999_999),
+    ];
+    for t in times {
+        let tai = table.utc_to_tai(t).unwrap();
+        assert_eq!(tai.seconds_fraction(), t.seconds_fraction());
+        assert_eq!(table.tai_to_utc(tai), Some(t));
+        assert_eq!(
+            table.unix_tai_to_utc(table.utc_to_unix_tai(t).unwrap()),
+            Some(t)
+        );
+        if let Some(gps) = table.utc_to_gps(t) {
+            assert_eq!(table.gps_to_utc(gps), Some(t));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+#![cfg(target_os = "linux")]
+
+use barentp::discipline::{ClockDiscipline, DisciplineAction, DisciplineConfig};
+use barentp::linux::LinuxClock;
+use barentp::{ClockControl, NtpClock, NtpDuration, Timestamp};
+
+#[test]
+fn test_linux_clock_now() {
+    let clock = LinuxClock::realtime().dry_run();
+    let system = Timestamp::from(std::time::SystemTime::now());
+    assert!((clock.now() - system).abs() < NtpDuration::from_seconds(1));
+    assert!((-30..=0).contains(&clock.precision()));
+}
+
+#[test]
+fn test_linux_clock_dry_run() {
+    let before = LinuxClock::realtime().dry_run().now();
+    let mut clock = LinuxClock::realtime().dry_run();
+    clock.step(NtpDuration::from_seconds(-3600)).unwrap();
+    clock.slew(NtpDuration::from_millis(20)).unwrap();
+    clock.slew(NtpDuration::from_millis(-5)).unwrap();
+    clock.set_frequency(1e-3).unwrap();
+
+    let adjustments = clock.adjustments();
+    assert_eq!(adjustments.stepped, NtpDuration::from_seconds(-3600));
+    assert!((adjustments.slewed - NtpDuration::from_millis(15)).abs() < NtpDuration::from_nanos(1));
+    assert_eq!(adjustments.frequency, Some(500e-6));
+
+    // The clock was not actually stepped back by an hour.
+    assert!(clock.now() - before >= NtpDuration::ZERO);
+}
+
+#[test]
+fn test_linux_clock_discipline_dry_run() {
+    let mut clock = LinuxClock::realtime().dry_run();
+    let mut discipline =
+        ClockDiscipline::with_frequency(DisciplineConfig::default(), clock.precision(), 10e-6);
+    let now = clock.now();
+    let action = discipline
+        .update(NtpDuration::from_millis(2), now, &mut clock)
+        .unwrap();
+    assert_eq!(action, DisciplineAction::Slew);
+    assert_eq!(clock.adjustments().stepped, NtpDuration::ZERO);
+}
+
+#[test]
+fn test_linux_clock_kernel_state() {
+    let clock = LinuxClock::realtime();
+    // Reading the kernel state does not require any privileges.
+    match clock.kernel_frequency() {
+        Ok(frequency) => assert!(frequency.abs() <= 500e-6 + 1e-9),
+        Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied),
+    }
+}
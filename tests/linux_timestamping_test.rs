@@ -0,0 +1,69 @@
+#![cfg(target_os = "linux")]
+
+use barentp::linux::{TimestampingMode, TimestampingSocket};
+use barentp::protocol::{Mode, SntpMessage};
+use barentp::{NtpClock, NtpDuration, SystemClock};
+use std::net::UdpSocket;
+
+/// Spawns a server on the loopback interface that answers `count` requests using the system clock.
+fn spawn_server(count: usize) -> std::net::SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+        for _ in 0..count {
+            let (_, peer) = socket.recv_from(&mut buffer).unwrap();
+            let received = SystemClock.now();
+            let mut request = SntpMessage::new_v4();
+            request.read_from_buffer(&buffer).unwrap();
+
+            let mut reply = SntpMessage::new_v4();
+            reply.mode = Mode::Server;
+            reply.stratum = 1;
+            reply.originate_timestamp = request.transmit_timestamp;
+            reply.receive_timestamp = received;
+            reply.transmit_timestamp = SystemClock.now();
+            reply.write_to_buffer(&mut buffer).unwrap();
+            socket.send_to(&buffer, peer).unwrap();
+        }
+    });
+    addr
+}
+
+fn connect(addr: std::net::SocketAddr) -> TimestampingSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(addr).unwrap();
+    socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    TimestampingSocket::new(socket, TimestampingMode::Software).unwrap()
+}
+
+#[test]
+fn test_kernel_receive_timestamp() {
+    let socket = connect(spawn_server(1));
+    let request = [0x23; SntpMessage::BUFFER_SIZE];
+    socket.socket().send(&request).unwrap();
+
+    // The packet waits in the socket while we sleep, but its timestamp is from when it arrived.
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+    let (len, timestamp) = socket.recv_timestamped(&mut buffer).unwrap();
+    let now = SystemClock.now();
+
+    assert_eq!(len, SntpMessage::BUFFER_SIZE);
+    let timestamp = timestamp.expect("no kernel timestamp");
+    assert!(now - timestamp >= NtpDuration::from_millis(50));
+    assert!(now - timestamp < NtpDuration::from_seconds(5));
+}
+
+#[test]
+fn test_query_with_kernel_timestamps() {
+    let socket = connect(spawn_server(4));
+    for _ in 0..4 {
+        let sample = barentp::sntp_query(&socket, &SystemClock).expect("query failed");
+        assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+        assert!(sample.delay < NtpDuration::from_millis(10));
+        assert!(sample.delay >= NtpDuration::ZERO);
+    }
+}
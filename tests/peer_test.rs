@@ -0,0 +1,308 @@
+use barentp::auth::{DigestAlgorithm, SymmetricKey};
+use barentp::error::SntpProtocolError;
+use barentp::peer::{PeerMode, SymmetricPeer};
+use barentp::protocol::{Mode, SntpMessage};
+use barentp::server::ServerConfig;
+use barentp::{NtpClock, NtpDuration, SystemClock, Timestamp};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+mod common;
+use common::{assert_close, block_on, FixedClock, START};
+
+/// Sends a packet from `from` at local time `sent`, which `to` receives at its local time
+/// `received`.
+fn exchange(
+    from: &mut SymmetricPeer<'_>,
+    sent: Timestamp,
+    to: &mut SymmetricPeer<'_>,
+    received: Timestamp,
+) -> Result<Option<barentp::SntpSample>, SntpProtocolError> {
+    let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = from.build_packet(&FixedClock(sent), &mut buffer).unwrap();
+    to.handle_packet(&buffer[..len], received)
+}
+
+/// Like [`exchange`], but the packet leaves 1ms after it was stamped and arrives 1ms later.
+fn exchange_late(
+    from: &mut SymmetricPeer<'_>,
+    sent: Timestamp,
+    to: &mut SymmetricPeer<'_>,
+    received: Timestamp,
+) -> Result<Option<barentp::SntpSample>, SntpProtocolError> {
+    let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = from.build_packet(&FixedClock(sent), &mut buffer).unwrap();
+    from.sent(Some(sent + NtpDuration::from_millis(1)));
+    to.handle_packet(&buffer[..len], received + NtpDuration::from_millis(2))
+}
+
+#[test]
+fn test_symmetric_exchange() {
+    let mut active = SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, -20);
+    let config = ServerConfig {
+        stratum: 2,
+        ..ServerConfig::default()
+    };
+    let mut passive = SymmetricPeer::new(PeerMode::Passive, config, 4, -20);
+    assert_eq!(active.time_until_poll(START), Some(Duration::ZERO));
+    assert_eq!(passive.time_until_poll(START), None);
+
+    // The passive peer's clock is 5ms ahead and packets take 1ms each way.
+    let passive_time = |local: Timestamp| local + NtpDuration::from_millis(5);
+    let first = exchange(
+        &mut active,
+        START,
+        &mut passive,
+        passive_time(START + NtpDuration::from_millis(1)),
+    )
+    .expect("invalid packet");
+    assert!(first.is_none());
+    assert_eq!(
+        passive.time_until_poll(passive_time(START)),
+        Some(Duration::ZERO)
+    );
+
+    let at = START + NtpDuration::from_millis(10);
+    let sample = exchange(
+        &mut passive,
+        passive_time(at),
+        &mut active,
+        at + NtpDuration::from_millis(1),
+    )
+    .expect("invalid packet")
+    .expect("exchange not complete");
+    assert_close(sample.offset, NtpDuration::from_millis(5));
+    assert_close(sample.delay, NtpDuration::from_millis(2));
+    assert_eq!(sample.stratum, 2);
+
+    let at = START + NtpDuration::from_millis(20);
+    let sample = exchange(
+        &mut active,
+        at,
+        &mut passive,
+        passive_time(at + NtpDuration::from_millis(1)),
+    )
+    .expect("invalid packet")
+    .expect("exchange not complete");
+    assert_close(sample.offset, NtpDuration::from_millis(-5));
+    assert_close(sample.delay, NtpDuration::from_millis(2));
+    assert_eq!(sample.stratum, 1);
+
+    assert_eq!(
+        active.time_until_poll(at + NtpDuration::from_seconds(10)),
+        Some(Duration::from_secs(6))
+    );
+}
+
+#[test]
+fn test_symmetric_rejects_invalid_packets() {
+    let mut active = SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, -20);
+    let mut passive = SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, -20);
+
+    let mut buffer = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = active
+        .build_packet(&FixedClock(START), &mut buffer)
+        .unwrap();
+    passive
+        .handle_packet(&buffer[..len], START)
+        .expect("invalid packet");
+    assert!(matches!(
+        passive.handle_packet(&buffer[..len], START),
+        Err(SntpProtocolError::StalePacket)
+    ));
+
+    // Passive peers do not answer each other.
+    let mut other = SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, -20);
+    let len = other
+        .build_packet(
+            &FixedClock(START + NtpDuration::from_seconds(1)),
+            &mut buffer,
+        )
+        .unwrap();
+    assert!(matches!(
+        passive.handle_packet(&buffer[..len], START),
+        Err(SntpProtocolError::UnexpectedSntpMode(
+            Mode::SymmetricPassive
+        ))
+    ));
+
+    // A reply to a packet that was never sent.
+    let mut msg = SntpMessage::new_v4();
+    msg.mode = Mode::SymmetricPassive;
+    msg.stratum = 2;
+    msg.originate_timestamp = START + NtpDuration::from_seconds(100);
+    msg.transmit_timestamp = START + NtpDuration::from_seconds(101);
+    msg.write_to_buffer(&mut buffer).unwrap();
+    assert!(matches!(
+        active.handle_packet(&buffer[..SntpMessage::BUFFER_SIZE], START),
+        Err(SntpProtocolError::OriginateTimestampMismatch)
+    ));
+
+    // An unsynchronized peer is still answered, so that it can synchronize to this side.
+    let unsynchronized = ServerConfig {
+        stratum: 16,
+        ..ServerConfig::default()
+    };
+    let mut active = SymmetricPeer::new(PeerMode::Active, unsynchronized, 4, -20);
+    let mut passive = SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, -20);
+    let at = START + NtpDuration::from_seconds(1);
+    exchange(&mut active, START, &mut passive, START).expect("invalid packet");
+    exchange(&mut passive, at, &mut active, at)
+        .expect("invalid packet")
+        .expect("exchange not complete");
+    let at = START + NtpDuration::from_seconds(2);
+    assert!(matches!(
+        exchange(&mut active, at, &mut passive, at),
+        Err(SntpProtocolError::ServerUnsynchronized)
+    ));
+    let at = START + NtpDuration::from_seconds(3);
+    exchange(&mut passive, at, &mut active, at)
+        .expect("invalid packet")
+        .expect("exchange not complete");
+}
+
+#[test]
+fn test_interleaved_peers() {
+    let peer = |mode| SymmetricPeer::new(mode, ServerConfig::default(), 4, -20).with_interleaved();
+    let mut active = peer(PeerMode::Active);
+    let mut passive = peer(PeerMode::Passive);
+
+    // The passive peer's clock is 5ms ahead.
+    let passive_time = |local: Timestamp| local + NtpDuration::from_millis(5);
+    let at = |seconds| START + NtpDuration::from_seconds(seconds);
+    let first = exchange_late(&mut active, at(0), &mut passive, passive_time(at(0)));
+    assert!(first.expect("invalid packet").is_none());
+
+    // The first exchange is in basic mode, so the transmit timestamp is 1ms early.
+    let sample = exchange_late(&mut passive, passive_time(at(1)), &mut active, at(1))
+        .expect("invalid packet")
+        .expect("exchange not complete");
+    assert!(!active.is_interleaved());
+    assert_close(sample.offset, NtpDuration::from_micros(4500));
+    assert_close(sample.delay, NtpDuration::from_millis(3));
+
+    // The first interleaved packet has no exchange to complete yet.
+    let sample = exchange_late(&mut active, at(2), &mut passive, passive_time(at(2)));
+    assert!(sample.expect("invalid packet").is_none());
+    assert!(passive.is_interleaved());
+
+    for i in 1..3 {
+        let sent = at(2 * i + 1);
+        let sample = exchange_late(&mut passive, passive_time(sent), &mut active, sent)
+            .expect("invalid packet")
+            .expect("exchange not complete");
+        assert!(active.is_interleaved());
+        assert_close(sample.offset, NtpDuration::from_millis(5));
+        assert_close(sample.delay, NtpDuration::from_millis(2));
+
+        let sent = at(2 * i + 2);
+        let sample = exchange_late(&mut active, sent, &mut passive, passive_time(sent))
+            .expect("invalid packet")
+            .expect("exchange not complete");
+        assert!(passive.is_interleaved());
+        assert_close(sample.offset, NtpDuration::from_millis(-5));
+        assert_close(sample.delay, NtpDuration::from_millis(2));
+    }
+}
+
+#[test]
+fn test_interleaved_falls_back_to_basic_mode() {
+    let mut active =
+        SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, -20).with_interleaved();
+    let mut passive = SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, -20);
+    let at = |seconds| START + NtpDuration::from_seconds(seconds);
+    exchange_late(&mut active, at(0), &mut passive, at(0)).expect("invalid packet");
+    exchange_late(&mut passive, at(1), &mut active, at(1))
+        .expect("invalid packet")
+        .expect("exchange not complete");
+
+    // A peer that does not support interleaved mode cannot use the interleaved packet, and its
+    // answer does not complete the exchange either.
+    assert!(matches!(
+        exchange_late(&mut active, at(2), &mut passive, at(2)),
+        Err(SntpProtocolError::OriginateTimestampMismatch)
+    ));
+    assert!(matches!(
+        exchange_late(&mut passive, at(3), &mut active, at(3)),
+        Err(SntpProtocolError::OriginateTimestampMismatch)
+    ));
+
+    for i in 2..4 {
+        let sample = exchange_late(&mut active, at(2 * i), &mut passive, at(2 * i))
+            .expect("invalid packet")
+            .expect("exchange not complete");
+        assert_close(sample.offset, NtpDuration::from_micros(-500));
+        let sample = exchange_late(&mut passive, at(2 * i + 1), &mut active, at(2 * i + 1))
+            .expect("invalid packet")
+            .expect("exchange not complete");
+        assert!(!active.is_interleaved());
+        assert_close(sample.offset, NtpDuration::from_micros(-500));
+    }
+}
+
+#[test]
+fn test_authenticated_peers() {
+    let key = SymmetricKey::new(3, DigestAlgorithm::Sha1, b"peer secret");
+    let mut active =
+        SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, -20).with_key(key);
+    let mut passive =
+        SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, -20).with_key(key);
+    let mut intruder = SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, -20);
+
+    assert!(matches!(
+        exchange(&mut intruder, START, &mut passive, START),
+        Err(SntpProtocolError::AuthenticationFailed)
+    ));
+    assert_eq!(passive.time_until_poll(START), None);
+
+    exchange(&mut active, START, &mut passive, START).expect("invalid packet");
+    let at = START + NtpDuration::from_seconds(1);
+    exchange(&mut passive, at, &mut active, at)
+        .expect("authentication failed")
+        .expect("exchange not complete");
+}
+
+#[test]
+fn test_peers_over_udp() {
+    let socket_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let socket_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for socket in [&socket_a, &socket_b] {
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+    }
+    let addr_a = socket_a.local_addr().unwrap();
+    let addr_b = socket_b.local_addr().unwrap();
+
+    let precision = SystemClock.precision();
+    let mut a = SymmetricPeer::new(PeerMode::Active, ServerConfig::default(), 4, precision);
+    let mut b = SymmetricPeer::new(PeerMode::Passive, ServerConfig::default(), 4, precision);
+
+    barentp::sntp_peer_send(&mut a, &socket_a, &addr_b, &SystemClock).expect("send failed");
+    let first =
+        barentp::sntp_peer_recv(&mut b, &socket_b, &addr_a, &SystemClock).expect("receive failed");
+    assert!(first.is_none());
+
+    barentp::sntp_peer_send(&mut b, &socket_b, &addr_a, &SystemClock).expect("send failed");
+    let sample = barentp::sntp_peer_recv(&mut a, &socket_a, &addr_b, &SystemClock)
+        .expect("receive failed")
+        .expect("exchange not complete");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+
+    block_on(barentp::nonblocking::sntp_peer_send(
+        &mut a,
+        &socket_a,
+        &addr_b,
+        &SystemClock,
+    ))
+    .expect("send failed");
+    let sample = block_on(barentp::nonblocking::sntp_peer_recv(
+        &mut b,
+        &socket_b,
+        &addr_a,
+        &SystemClock,
+    ))
+    .expect("receive failed")
+    .expect("exchange not complete");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+}
@@ -0,0 +1,122 @@
+use barentp::error::{Error, SntpProtocolError};
+use barentp::protocol::SntpMessage;
+use std::cell::RefCell;
+
+/// Transport that replies with a fixed packet.
+struct ReplyTransport {
+    reply: Vec<u8>,
+    sent: RefCell<Vec<u8>>,
+}
+
+impl ReplyTransport {
+    fn new(reply: Vec<u8>) -> Self {
+        Self {
+            reply,
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl barentp::NtpTransport for ReplyTransport {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.sent.borrow_mut().extend_from_slice(buffer);
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        buffer[..self.reply.len()].copy_from_slice(&self.reply);
+        Ok(self.reply.len())
+    }
+}
+
+fn server_reply() -> Vec<u8> {
+    let mut reply = vec![0; SntpMessage::BUFFER_SIZE];
+    reply[0] = 0x24; // LI = 0, VN = 4, Mode = 4
+    reply[1] = 2;
+    reply[40..48].copy_from_slice(&[0xE0, 0, 0, 0, 0x80, 0, 0, 0]);
+    reply
+}
+
+#[test]
+fn test_request_is_exactly_one_header() {
+    let transport = ReplyTransport::new(server_reply());
+    barentp::sntp_get_transmit_timestamp(&transport).expect("failed to get timestamp");
+    assert_eq!(transport.sent.borrow().len(), SntpMessage::BUFFER_SIZE);
+}
+
+#[test]
+fn test_truncated_reply_is_rejected() {
+    let mut reply = server_reply();
+    reply.truncate(10);
+    let transport = ReplyTransport::new(reply);
+
+    let result = barentp::sntp_get_transmit_timestamp(&transport);
+    assert!(matches!(
+        result,
+        Err(Error::SntpProtocol(
+            SntpProtocolError::TruncatedSntpPacket {
+                size: 10,
+                expected: 48
+            }
+        ))
+    ));
+}
+
+#[test]
+fn test_reply_with_mac() {
+    let mut reply = server_reply();
+    reply.extend_from_slice(&7u32.to_be_bytes());
+    reply.extend_from_slice(&[0xAB; 20]);
+
+    let mut msg = SntpMessage::new_v4();
+    let extensions = msg.read_from_packet(&reply).expect("failed to read packet");
+    assert_eq!(extensions.fields().count(), 0);
+    let mac = extensions.mac().expect("missing MAC");
+    assert_eq!(mac.key_identifier, 7);
+    assert_eq!(mac.digest, &[0xAB; 20]);
+    assert!(!mac.is_crypto_nak());
+
+    let transport = ReplyTransport::new(reply);
+    let timestamp =
+        barentp::sntp_get_transmit_timestamp(&transport).expect("failed to get timestamp");
+    assert_eq!(timestamp.seconds(), 0xE0000000);
+}
+
+#[test]
+fn test_reply_with_extension_fields() {
+    let mut reply = server_reply();
+    reply.extend_from_slice(&[0x01, 0x04, 0x00, 0x10]);
+    reply.extend_from_slice(&[0x11; 12]);
+    reply.extend_from_slice(&[0x02, 0x04, 0x00, 0x1C]);
+    reply.extend_from_slice(&[0x22; 24]);
+    reply.extend_from_slice(&[0, 0, 0, 0]);
+
+    let mut msg = SntpMessage::new_v4();
+    let extensions = msg.read_from_packet(&reply).expect("failed to read packet");
+    let fields = extensions.fields().collect::<Vec<_>>();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0].field_type, 0x0104);
+    assert_eq!(fields[0].value, &[0x11; 12]);
+    assert_eq!(fields[1].field_type, 0x0204);
+    assert_eq!(fields[1].value, &[0x22; 24]);
+    assert!(extensions
+        .mac()
+        .expect("missing crypto-NAK")
+        .is_crypto_nak());
+}
+
+#[test]
+fn test_reply_with_invalid_extension_field() {
+    let mut reply = server_reply();
+    reply.extend_from_slice(&[0x01, 0x04, 0x00, 0x11]);
+    reply.extend_from_slice(&[0x11; 13]);
+
+    let mut msg = SntpMessage::new_v4();
+    assert!(matches!(
+        msg.read_from_packet(&reply),
+        Err(SntpProtocolError::InvalidSntpExtensionField { offset: 48 })
+    ));
+}
@@ -0,0 +1,269 @@
+use barentp::protocol::SntpMessage;
+use barentp::select::{select, Candidate};
+use barentp::{NtpClock, NtpDuration, Timestamp};
+use std::sync::Mutex;
+
+mod common;
+use common::{assert_close, block_on, ManualClock, START};
+
+/// Transport that answers requests like a server whose clock is `offset` ahead of `clock`.
+struct OffsetServer<'a> {
+    clock: &'a ManualClock,
+    offset: NtpDuration,
+    request: Mutex<[u8; SntpMessage::BUFFER_SIZE]>,
+}
+
+impl<'a> OffsetServer<'a> {
+    fn new(clock: &'a ManualClock, offset_millis: i64) -> Self {
+        Self {
+            clock,
+            offset: NtpDuration::from_millis(offset_millis),
+            request: Mutex::new([0; SntpMessage::BUFFER_SIZE]),
+        }
+    }
+
+    fn reply(&self, buffer: &mut [u8]) -> usize {
+        let mut request = SntpMessage::new_v4();
+        request
+            .read_from_buffer(&*self.request.lock().unwrap())
+            .unwrap();
+
+        // 10ms each way, 1ms inside of the server.
+        let step = NtpDuration::from_millis(10);
+        let now = self.clock.now();
+        let mut reply = SntpMessage::new_v4();
+        reply.mode = barentp::protocol::Mode::Server;
+        reply.stratum = 2;
+        reply.precision = -20i8 as u8;
+        reply.originate_timestamp = request.transmit_timestamp;
+        reply.receive_timestamp = now + step + self.offset;
+        reply.transmit_timestamp = now + step + NtpDuration::from_millis(1) + self.offset;
+        self.clock.set(now + step * 2 + NtpDuration::from_millis(1));
+        reply.write_to_buffer(buffer).unwrap();
+        SntpMessage::BUFFER_SIZE
+    }
+}
+
+impl barentp::NtpTransport for OffsetServer<'_> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        *self.request.lock().unwrap() = buffer.try_into().unwrap();
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.reply(buffer))
+    }
+}
+
+impl barentp::nonblocking::NtpTransportAsync for OffsetServer<'_> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        *self.request.lock().unwrap() = buffer.try_into().unwrap();
+        Ok(())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.reply(buffer))
+    }
+}
+
+fn candidate(offset_millis: i64, distance_millis: i64) -> Candidate {
+    Candidate {
+        offset: NtpDuration::from_millis(offset_millis),
+        root_distance: NtpDuration::from_millis(distance_millis),
+    }
+}
+
+#[test]
+fn test_select_discards_falseticker() {
+    let candidates = [
+        candidate(100, 20),
+        candidate(110, 20),
+        candidate(95, 20),
+        candidate(5000, 20),
+    ];
+
+    let selection = select(candidates).expect("no selection");
+    assert_eq!(selection.survivors, 3);
+    assert_eq!(selection.falsetickers, 1);
+    assert!(!selection.is_survivor(&candidates[3]));
+    assert_close(selection.low, NtpDuration::from_millis(90));
+    assert_close(selection.high, NtpDuration::from_millis(115));
+
+    let offset = selection.offset.as_millis();
+    assert!((95..=110).contains(&offset), "offset = {offset}ms");
+    assert!(selection.error_bound <= NtpDuration::from_millis(25));
+}
+
+#[test]
+fn test_select_without_majority() {
+    let candidates = [candidate(0, 1), candidate(1000, 1)];
+    assert!(select(candidates).is_none());
+    assert!(select([]).is_none());
+}
+
+#[test]
+fn test_select_negative_root_distance() {
+    // Negative root distances are treated as zero rather than inverting the intervals.
+    let candidates = [
+        candidate(0, -1000),
+        candidate(5000, 1000),
+        candidate(0, -10_000),
+        candidate(0, -10_000),
+    ];
+    let selection = select(candidates).expect("no selection");
+    assert_eq!(selection.survivors, 3);
+    assert_eq!(selection.falsetickers, 1);
+    assert_eq!(selection.offset, NtpDuration::ZERO);
+    assert_eq!(
+        (selection.low, selection.high),
+        (NtpDuration::ZERO, NtpDuration::ZERO)
+    );
+}
+
+#[test]
+fn test_query_servers() {
+    let clock = ManualClock::new(START);
+    let servers = [
+        OffsetServer::new(&clock, 250),
+        OffsetServer::new(&clock, 251),
+        OffsetServer::new(&clock, -3000),
+        OffsetServer::new(&clock, 249),
+        OffsetServer::new(&clock, 250),
+    ];
+
+    let query = barentp::sntp_query_servers(&servers, &clock);
+    for sample in &query.samples {
+        let sample = sample.as_ref().expect("query failed");
+        assert_close(sample.delay, NtpDuration::from_millis(20));
+        assert_eq!(sample.stratum, 2);
+    }
+
+    let selection = query.selection.expect("no selection");
+    assert_eq!(selection.survivors, 4);
+    assert_eq!(query.survivors().count(), 4);
+    assert!((249..=251).contains(&selection.offset.as_millis()));
+}
+
+#[test]
+fn test_query_servers_async() {
+    let clock = ManualClock::new(START);
+    let servers = [
+        OffsetServer::new(&clock, -40),
+        OffsetServer::new(&clock, -41),
+        OffsetServer::new(&clock, -39),
+    ];
+
+    let query = block_on(barentp::nonblocking::sntp_query_servers(&servers, &clock));
+    let selection = query.selection.expect("no selection");
+    assert_eq!(selection.survivors, 3);
+    assert!((-41..=-39).contains(&selection.offset.as_millis()));
+}
+
+#[test]
+fn test_query_discards_mismatched_originate() {
+    let clock = ManualClock::new(START);
+    let server = OffsetServer::new(&clock, 40);
+
+    /// Receives a stale reply to an earlier request before the reply from `server`.
+    struct Replay<'a> {
+        server: OffsetServer<'a>,
+        stale: Mutex<bool>,
+    }
+
+    impl barentp::NtpTransport for Replay<'_> {
+        type SendError = std::convert::Infallible;
+        type RecvError = std::convert::Infallible;
+
+        fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+            barentp::NtpTransport::send(&self.server, buffer)
+        }
+
+        fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+            if std::mem::take(&mut *self.stale.lock().unwrap()) {
+                let mut reply = SntpMessage::new_v4();
+                reply.mode = barentp::protocol::Mode::Server;
+                reply.stratum = 1;
+                reply.transmit_timestamp = START;
+                reply.write_to_buffer(buffer).unwrap();
+                return Ok(SntpMessage::BUFFER_SIZE);
+            }
+            Ok(self.server.reply(buffer))
+        }
+    }
+
+    let replay = Replay {
+        server,
+        stale: Mutex::new(true),
+    };
+    let sample = barentp::sntp_query(&replay, &clock).unwrap();
+    assert!(!*replay.stale.lock().unwrap());
+    assert!((39..=41).contains(&sample.offset.as_millis()));
+}
+
+/// Transport that answers the last request with a reply crafted by `craft`, like a malicious
+/// server.
+struct CraftedServer<F> {
+    craft: F,
+    request: Mutex<[u8; SntpMessage::BUFFER_SIZE]>,
+}
+
+impl<F: Fn(&mut SntpMessage)> CraftedServer<F> {
+    fn new(craft: F) -> Self {
+        Self {
+            craft,
+            request: Mutex::new([0; SntpMessage::BUFFER_SIZE]),
+        }
+    }
+}
+
+impl<F: Fn(&mut SntpMessage)> barentp::NtpTransport for CraftedServer<F> {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        *self.request.lock().unwrap() = buffer.try_into().unwrap();
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        let mut request = SntpMessage::new_v4();
+        request
+            .read_from_buffer(&*self.request.lock().unwrap())
+            .unwrap();
+        let mut reply = SntpMessage::new_v4();
+        reply.mode = barentp::protocol::Mode::Server;
+        reply.stratum = 1;
+        reply.originate_timestamp = request.transmit_timestamp;
+        reply.receive_timestamp = request.transmit_timestamp;
+        reply.transmit_timestamp = request.transmit_timestamp;
+        (self.craft)(&mut reply);
+        reply.write_to_buffer(buffer).unwrap();
+        Ok(SntpMessage::BUFFER_SIZE)
+    }
+}
+
+#[test]
+fn test_query_saturates_bogus_values() {
+    let clock = ManualClock::new(START);
+
+    // A precision of 2^127 seconds.
+    let server = CraftedServer::new(|reply| reply.precision = 0x7f);
+    let sample = barentp::sntp_query(&server, &clock).unwrap();
+    assert_eq!(sample.dispersion, NtpDuration::MAX);
+    assert_eq!(sample.root_distance(), NtpDuration::MAX);
+
+    // A reply sent half an era after the request was received.
+    let server = CraftedServer::new(|reply| {
+        reply.transmit_timestamp = Timestamp::new(0x6000_0000, 0);
+        reply.root_delay = u32::MAX;
+    });
+    let sample = barentp::sntp_query(&server, &clock).unwrap();
+    assert_eq!(sample.delay, NtpDuration::MAX);
+    assert!(sample.root_distance() >= NtpDuration::MAX / 2);
+}
@@ -0,0 +1,348 @@
+use barentp::error::{Error, SntpProtocolError};
+use barentp::protocol::KissCode;
+use barentp::protocol::{LeapIndicator, Mode, SntpMessage, Version};
+use barentp::server::{
+    upstream_reference_identifier, AccessAction, AccessRule, RateLimitAction, RateLimitConfig,
+    RateLimitSlot, ServerConfig, SntpServer,
+};
+use barentp::{NtpClock, NtpDuration, SystemClock, Timestamp};
+use std::net::{IpAddr, UdpSocket};
+
+mod common;
+use common::block_on;
+
+const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 20));
+
+struct FixedClock(Timestamp);
+
+impl NtpClock for FixedClock {
+    fn now(&self) -> Timestamp {
+        self.0
+    }
+
+    fn precision(&self) -> i8 {
+        -18
+    }
+}
+
+fn request(mode: Mode, version: Version) -> [u8; SntpMessage::BUFFER_SIZE] {
+    let mut msg = SntpMessage::new_v4();
+    msg.mode = mode;
+    msg.version = version;
+    msg.poll = 6;
+    msg.transmit_timestamp = Timestamp::new(0xE000_0000, 1234);
+    let mut buffer = [0; SntpMessage::BUFFER_SIZE];
+    msg.write_to_buffer(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn test_server_reply() {
+    let config = ServerConfig {
+        stratum: 2,
+        reference_identifier: [192, 168, 1, 1],
+        root_delay: NtpDuration::from_millis(20),
+        root_dispersion: NtpDuration::from_millis(5),
+        ..ServerConfig::default()
+    };
+    let mut server = SntpServer::new(config);
+    let received = Timestamp::new(0xE000_0001, 0);
+    let clock = FixedClock(Timestamp::new(0xE000_0001, 0x1000));
+
+    let mut reply = [0; SntpMessage::MAX_PACKET_SIZE];
+    let len = server
+        .handle_request(
+            &request(Mode::Client, Version::V3),
+            CLIENT,
+            received,
+            &clock,
+            &mut reply,
+        )
+        .expect("request was dropped");
+    assert_eq!(len, SntpMessage::BUFFER_SIZE);
+
+    let mut msg = SntpMessage::new_v4();
+    msg.read_from_buffer(&reply[..len]).unwrap();
+    assert_eq!(msg.mode, Mode::Server);
+    assert_eq!(msg.version, Version::V3);
+    assert_eq!(msg.leap_indicator, LeapIndicator::NoWarning);
+    assert_eq!(msg.stratum, 2);
+    assert_eq!(msg.poll, 6);
+    assert_eq!(msg.precision as i8, -18);
+    assert_eq!(msg.reference_identifier.to_be_bytes(), [192, 168, 1, 1]);
+    assert_eq!(
+        NtpDuration::from_ntp_short(msg.root_delay),
+        NtpDuration::from_ntp_short(NtpDuration::from_millis(20).to_ntp_short())
+    );
+    assert_eq!(msg.originate_timestamp, Timestamp::new(0xE000_0000, 1234));
+    assert_eq!(msg.receive_timestamp, received);
+    assert_eq!(msg.transmit_timestamp, clock.0);
+}
+
+#[test]
+fn test_server_drops_invalid_requests() {
+    let mut server = SntpServer::new(ServerConfig::default());
+    let clock = FixedClock(Timestamp::new(0xE000_0001, 0));
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+
+    let server_mode = request(Mode::Server, Version::V4);
+    assert!(server
+        .handle_request(&server_mode, CLIENT, clock.0, &clock, &mut reply)
+        .is_none());
+    let truncated = &request(Mode::Client, Version::V4)[..40];
+    assert!(server
+        .handle_request(truncated, CLIENT, clock.0, &clock, &mut reply)
+        .is_none());
+    assert!(server
+        .handle_request(&[0xFF; 48], CLIENT, clock.0, &clock, &mut reply)
+        .is_none());
+}
+
+#[test]
+fn test_serve_over_udp() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut server = SntpServer::new(ServerConfig::default());
+        for _ in 0..2 {
+            barentp::sntp_serve_one(&mut server, &socket, &SystemClock).expect("serve failed");
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+
+    // A packet that is not a client request is ignored.
+    client.send(&request(Mode::Server, Version::V4)).unwrap();
+    let sample = barentp::sntp_query(&client, &SystemClock).expect("query failed");
+    assert!(sample.offset.abs() < NtpDuration::from_millis(10));
+    assert_eq!(sample.stratum, 1);
+    assert_eq!(sample.reference_identifier.to_be_bytes(), *b"LOCL");
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_serve_async() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(socket.local_addr().unwrap()).unwrap();
+    client.send(&request(Mode::Client, Version::V4)).unwrap();
+
+    let mut server = SntpServer::new(ServerConfig::default());
+    let answered = block_on(barentp::nonblocking::sntp_serve_one(
+        &mut server,
+        &socket,
+        &SystemClock,
+    ))
+    .expect("serve failed");
+    assert_eq!(answered, Some(client.local_addr().unwrap()));
+
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    assert_eq!(client.recv(&mut reply).unwrap(), SntpMessage::BUFFER_SIZE);
+}
+
+/// Sends a request from `client` at `now` and returns the Kiss-o'-Death code of the reply, or
+/// `None` for a normal reply. Panics if the request was dropped.
+fn kiss_code(server: &mut SntpServer, client: IpAddr, now: Timestamp) -> Option<KissCode> {
+    let clock = FixedClock(now);
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    server
+        .handle_request(
+            &request(Mode::Client, Version::V4),
+            client,
+            now,
+            &clock,
+            &mut reply,
+        )
+        .expect("request was dropped");
+    let mut msg = SntpMessage::new_v4();
+    msg.read_from_buffer(&reply).unwrap();
+    (msg.stratum == 0).then(|| KissCode::from_reference_identifier(msg.reference_identifier))
+}
+
+#[test]
+fn test_server_access_list() {
+    let access_list = [
+        AccessRule::new([0, 0, 0, 0].into(), 0, AccessAction::Deny),
+        AccessRule::new([192, 168, 0, 0].into(), 16, AccessAction::Allow),
+        AccessRule::new([192, 168, 2, 0].into(), 24, AccessAction::Restrict),
+        AccessRule::new([192, 168, 3, 0].into(), 24, AccessAction::Ignore),
+        AccessRule::new("fd00::".parse().unwrap(), 8, AccessAction::Allow),
+    ];
+    let mut server = SntpServer::new(ServerConfig::default()).with_access_list(&access_list);
+    let now = Timestamp::new(0xE000_0000, 0);
+
+    assert_eq!(kiss_code(&mut server, CLIENT, now), None);
+    assert_eq!(
+        kiss_code(&mut server, [10, 0, 0, 1].into(), now),
+        Some(KissCode::DENY)
+    );
+    assert_eq!(
+        kiss_code(&mut server, [192, 168, 2, 7].into(), now),
+        Some(KissCode::RSTR)
+    );
+    assert_eq!(
+        kiss_code(&mut server, "fd12::1".parse().unwrap(), now),
+        None
+    );
+    // IPv4 clients of a dual-stack socket use the same rules.
+    assert_eq!(
+        kiss_code(&mut server, "::ffff:10.0.0.1".parse().unwrap(), now),
+        Some(KissCode::DENY)
+    );
+    // IPv6 clients that no rule matches are allowed.
+    assert_eq!(
+        kiss_code(&mut server, "2001:db8::1".parse().unwrap(), now),
+        None
+    );
+
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    let ignored = server.handle_request(
+        &request(Mode::Client, Version::V4),
+        [192, 168, 3, 1].into(),
+        now,
+        &FixedClock(now),
+        &mut reply,
+    );
+    assert!(ignored.is_none());
+}
+
+#[test]
+fn test_server_rate_limit() {
+    let mut slots = [RateLimitSlot::EMPTY; 16];
+    let config = RateLimitConfig {
+        interval: 2,
+        burst: 4,
+        action: RateLimitAction::Kiss,
+    };
+    let mut server = SntpServer::new(ServerConfig::default()).with_rate_limit(config, &mut slots);
+    let mut now = Timestamp::new(0xE000_0000, 0);
+
+    for _ in 0..4 {
+        assert_eq!(kiss_code(&mut server, CLIENT, now), None);
+    }
+    assert_eq!(kiss_code(&mut server, CLIENT, now), Some(KissCode::RATE));
+    // Other clients, including others in the same IPv4 /24, are not affected.
+    assert_eq!(kiss_code(&mut server, [192, 168, 1, 21].into(), now), None);
+
+    // One request is allowed for every interval that passes.
+    now = now + NtpDuration::from_seconds(4);
+    assert_eq!(kiss_code(&mut server, CLIENT, now), None);
+    assert_eq!(kiss_code(&mut server, CLIENT, now), Some(KissCode::RATE));
+
+    // Clients sending at the average rate are never limited.
+    for _ in 0..32 {
+        now = now + NtpDuration::from_seconds(4);
+        assert_eq!(kiss_code(&mut server, CLIENT, now), None);
+    }
+}
+
+#[test]
+fn test_server_denies_before_rate_limit() {
+    let access_list = [
+        AccessRule::new([10, 0, 0, 0].into(), 8, AccessAction::Deny),
+        AccessRule::new([10, 1, 0, 0].into(), 16, AccessAction::Restrict),
+    ];
+    let mut slots = [RateLimitSlot::EMPTY; 16];
+    let config = RateLimitConfig {
+        interval: 2,
+        burst: 1,
+        action: RateLimitAction::Kiss,
+    };
+    let mut server = SntpServer::new(ServerConfig::default())
+        .with_access_list(&access_list)
+        .with_rate_limit(config, &mut slots);
+    let now = Timestamp::new(0xE000_0000, 0);
+
+    // Denied clients are told to stop however often they ask, not to back off.
+    for _ in 0..4 {
+        assert_eq!(
+            kiss_code(&mut server, [10, 0, 0, 1].into(), now),
+            Some(KissCode::DENY)
+        );
+        assert_eq!(
+            kiss_code(&mut server, [10, 1, 0, 1].into(), now),
+            Some(KissCode::RSTR)
+        );
+    }
+    assert_eq!(kiss_code(&mut server, CLIENT, now), None);
+    assert_eq!(kiss_code(&mut server, CLIENT, now), Some(KissCode::RATE));
+}
+
+#[test]
+fn test_server_rate_limit_ipv6_prefix_and_drop() {
+    let mut slots = [RateLimitSlot::EMPTY; 4];
+    let config = RateLimitConfig {
+        interval: 4,
+        burst: 1,
+        action: RateLimitAction::Drop,
+    };
+    let mut server = SntpServer::new(ServerConfig::default()).with_rate_limit(config, &mut slots);
+    let now = Timestamp::new(0xE000_0000, 0);
+    let clock = FixedClock(now);
+    let mut reply = [0; SntpMessage::BUFFER_SIZE];
+    let mut handle = |client: &str| {
+        server
+            .handle_request(
+                &request(Mode::Client, Version::V4),
+                client.parse().unwrap(),
+                now,
+                &clock,
+                &mut reply,
+            )
+            .is_some()
+    };
+
+    assert!(handle("2001:db8:0:1::1"));
+    // Addresses in the same /64 share a limit.
+    assert!(!handle("2001:db8:0:1::2"));
+    assert!(handle("2001:db8:0:2::1"));
+}
+
+#[test]
+fn test_client_sees_rate_kiss_of_death() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut slots = [RateLimitSlot::EMPTY; 8];
+        let config = RateLimitConfig {
+            burst: 1,
+            ..RateLimitConfig::default()
+        };
+        let mut server =
+            SntpServer::new(ServerConfig::default()).with_rate_limit(config, &mut slots);
+        for _ in 0..2 {
+            barentp::sntp_serve_one(&mut server, &socket, &SystemClock).expect("serve failed");
+        }
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    barentp::sntp_query(&client, &SystemClock).expect("query failed");
+    let error = barentp::sntp_query(&client, &SystemClock).expect_err("expected a kiss-o'-death");
+    assert!(matches!(
+        error,
+        Error::SntpProtocol(SntpProtocolError::KissOfDeath(KissCode::RATE))
+    ));
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_upstream_reference_identifier() {
+    let v4: IpAddr = "192.0.2.7".parse().unwrap();
+    assert_eq!(upstream_reference_identifier(v4), [192, 0, 2, 7]);
+    let mapped: IpAddr = "::ffff:192.0.2.7".parse().unwrap();
+    assert_eq!(upstream_reference_identifier(mapped), [192, 0, 2, 7]);
+
+    // The first four bytes of the MD5 hash of the address.
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+    assert_eq!(upstream_reference_identifier(v6), [57, 171, 155, 55]);
+    let loopback: IpAddr = "::1".parse().unwrap();
+    assert_eq!(upstream_reference_identifier(loopback), [207, 64, 77, 200]);
+}
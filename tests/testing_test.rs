@@ -0,0 +1,151 @@
+use barentp::error::{Error, SntpProtocolError};
+use barentp::protocol::KissCode;
+use barentp::testing::{NetworkConfig, SimulatedNetwork, TimedOut};
+use barentp::{NtpDuration, SntpSample};
+
+mod common;
+use common::{assert_close, block_on};
+
+fn query(
+    network: &SimulatedNetwork,
+) -> Result<SntpSample, Error<std::convert::Infallible, TimedOut>> {
+    barentp::sntp_query(network, &network.clock())
+}
+
+#[test]
+fn test_offset_and_delay() {
+    let network = SimulatedNetwork::new(NetworkConfig {
+        offset: NtpDuration::from_millis(-40),
+        request_delay: NtpDuration::from_millis(5),
+        reply_delay: NtpDuration::from_millis(25),
+        processing_delay: NtpDuration::from_millis(2),
+        ..NetworkConfig::default()
+    });
+    let sample = query(&network).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(-50));
+    assert_close(sample.delay, NtpDuration::from_millis(30));
+    assert_close(
+        network.now() - SimulatedNetwork::START,
+        NtpDuration::from_millis(32),
+    );
+    assert_eq!(network.requests(), 1);
+
+    // Time only passes when the test lets it.
+    network.advance(NtpDuration::from_seconds(64));
+    let sample = block_on(barentp::nonblocking::sntp_query(&network, &network.clock())).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(-50));
+    assert_close(
+        network.now() - SimulatedNetwork::START,
+        NtpDuration::from_seconds(64) + NtpDuration::from_millis(64),
+    );
+}
+
+#[test]
+fn test_loss() {
+    let network = SimulatedNetwork::new(NetworkConfig {
+        loss: 1.0,
+        ..NetworkConfig::default()
+    });
+    assert!(matches!(
+        query(&network),
+        Err(Error::TransportRecv(TimedOut))
+    ));
+    assert_eq!(network.requests(), 0);
+    assert_eq!(
+        network.now() - SimulatedNetwork::START,
+        NtpDuration::from_seconds(1)
+    );
+
+    // Random loss is the same for the same seed.
+    let outcomes = |seed| {
+        let network = SimulatedNetwork::new(NetworkConfig {
+            loss: 0.3,
+            seed,
+            ..NetworkConfig::default()
+        });
+        (0..64).map(|_| query(&network).is_ok()).collect::<Vec<_>>()
+    };
+    let first = outcomes(7);
+    assert_eq!(first, outcomes(7));
+    assert_ne!(first, outcomes(8));
+    let received = first.iter().filter(|&&ok| ok).count();
+    assert!((20..60).contains(&received), "{received} replies");
+}
+
+#[test]
+fn test_duplication() {
+    let config = NetworkConfig {
+        offset: NtpDuration::from_millis(70),
+        duplication: 1.0,
+        ..NetworkConfig::default()
+    };
+    let network = SimulatedNetwork::new(config);
+    query(&network).unwrap();
+    assert_eq!(network.replies_in_flight(), 1);
+
+    // The duplicate arrives before the reply to the next request and is discarded.
+    network.advance(NtpDuration::from_seconds(64));
+    network.set_config(NetworkConfig {
+        duplication: 0.0,
+        ..config
+    });
+    let sample = query(&network).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(70));
+    assert_eq!(network.replies_in_flight(), 0);
+}
+
+#[test]
+fn test_reordering() {
+    let config = NetworkConfig {
+        offset: NtpDuration::from_millis(-30),
+        reordering: 1.0,
+        ..NetworkConfig::default()
+    };
+    let network = SimulatedNetwork::new(config);
+    assert!(matches!(
+        query(&network),
+        Err(Error::TransportRecv(TimedOut))
+    ));
+
+    // The reply to the second request overtakes the held back reply to the first one, which
+    // arrives later.
+    network.set_config(NetworkConfig {
+        reordering: 0.0,
+        ..config
+    });
+    query(&network).unwrap();
+    assert_eq!(network.replies_in_flight(), 1);
+
+    // The late reply arrives before the reply to the next request and is discarded.
+    network.advance(NtpDuration::from_millis(500));
+    let sample = query(&network).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(-30));
+    assert_close(sample.delay, NtpDuration::from_millis(20));
+    assert_eq!(network.replies_in_flight(), 0);
+}
+
+#[test]
+fn test_kiss_of_death() {
+    let network = SimulatedNetwork::new(NetworkConfig::default());
+    network.inject_kiss_of_death(KissCode::RATE);
+    assert!(matches!(
+        query(&network),
+        Err(Error::SntpProtocol(SntpProtocolError::KissOfDeath(
+            KissCode::RATE
+        )))
+    ));
+    query(&network).unwrap();
+
+    network.set_config(NetworkConfig {
+        kiss_of_death: Some(KissCode::DENY),
+        ..network.config()
+    });
+    for _ in 0..2 {
+        assert!(matches!(
+            query(&network),
+            Err(Error::SntpProtocol(SntpProtocolError::KissOfDeath(
+                KissCode::DENY
+            )))
+        ));
+    }
+}
@@ -5,28 +5,12 @@ pub fn compare_to_system_time() {
         std::env::var("NTP_TEST_SERVER").unwrap_or_else(|_| "pool.ntp.org".to_string());
     println!("Using NTP server: {}", ntp_server);
 
-    // Lookup the IP address of the NTP server using dns_lookup:
-    let ips = dns_lookup::lookup_host(&ntp_server).expect("failed to lookup host");
-    assert!(!ips.is_empty(), "no IP addresses found for {}", ntp_server);
-    let ip = ips[0];
-    println!("Using NTP server IP address: {}", ip);
-
-    let addr = std::net::SocketAddr::new(ip, 123);
+    // Look up the NTP server and connect a UDP socket to its first address:
+    let addrs = barentp::dns::resolve(&ntp_server).expect("failed to lookup host");
+    let addr = addrs[0];
     println!("Using NTP server socket address: {}", addr);
 
-    // Create a UDP socket, bind it to an available IP and port, then connect it to the NTP server:
-    let socket = std::net::UdpSocket::bind("0.0.0.0:0").expect("could not found to UDP socket");
-
-    socket
-        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
-        .expect("failed to set socket read timeout");
-
-    socket
-        .set_write_timeout(Some(std::time::Duration::from_secs(5)))
-        .expect("failed to set socket write timeout");
-
-    socket
-        .connect(addr)
+    let socket = barentp::dns::connect(addr, std::time::Duration::from_secs(5))
         .expect("failed to connect UDP socket to NTP server");
 
     // Get a timestamp from the NTP server:
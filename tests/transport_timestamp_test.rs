@@ -0,0 +1,148 @@
+use barentp::nonblocking::NtpTransportAsync;
+use barentp::protocol::{Mode, SntpMessage};
+use barentp::{NtpDuration, NtpTransport, Timestamp};
+use std::sync::Mutex;
+
+mod common;
+use common::{assert_close, block_on, FixedClock, START};
+
+/// Clock that never advances, so any delay or offset must come from the transport's timestamps.
+const FROZEN: FixedClock = FixedClock(START);
+
+/// Transport that timestamps its packets itself. The request leaves 1ms after the clock was read,
+/// takes 10ms in each direction and the server is 5ms ahead.
+struct TimestampingTransport {
+    timestamps: bool,
+    request: Mutex<SntpMessage>,
+}
+
+impl TimestampingTransport {
+    fn new(timestamps: bool) -> Self {
+        Self {
+            timestamps,
+            request: Mutex::new(SntpMessage::new_v4()),
+        }
+    }
+
+    fn sent(&self, buffer: &[u8]) -> Option<Timestamp> {
+        self.request
+            .lock()
+            .unwrap()
+            .read_from_buffer(buffer)
+            .unwrap();
+        self.timestamps.then(|| START + NtpDuration::from_millis(1))
+    }
+
+    fn received(&self, buffer: &mut [u8]) -> (usize, Option<Timestamp>) {
+        let request = self.request.lock().unwrap();
+        let server_time = START + NtpDuration::from_millis(16);
+        let mut reply = SntpMessage::new_v4();
+        reply.mode = Mode::Server;
+        reply.stratum = 1;
+        reply.originate_timestamp = request.transmit_timestamp;
+        reply.receive_timestamp = server_time;
+        reply.transmit_timestamp = server_time;
+        reply.write_to_buffer(buffer).unwrap();
+
+        let received = self
+            .timestamps
+            .then(|| START + NtpDuration::from_millis(21));
+        (SntpMessage::BUFFER_SIZE, received)
+    }
+}
+
+impl NtpTransport for TimestampingTransport {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.sent(buffer);
+        Ok(())
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.received(buffer).0)
+    }
+
+    fn send_timestamped(&self, buffer: &[u8]) -> Result<Option<Timestamp>, Self::SendError> {
+        Ok(self.sent(buffer))
+    }
+
+    fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        Ok(self.received(buffer))
+    }
+}
+
+impl NtpTransportAsync for TimestampingTransport {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.sent(buffer);
+        Ok(())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.received(buffer).0)
+    }
+
+    async fn send_timestamped(&self, buffer: &[u8]) -> Result<Option<Timestamp>, Self::SendError> {
+        Ok(self.sent(buffer))
+    }
+
+    async fn recv_timestamped(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, Option<Timestamp>), Self::RecvError> {
+        Ok(self.received(buffer))
+    }
+}
+
+/// Transport that only implements the required methods, to check the provided ones.
+struct PlainTransport(TimestampingTransport);
+
+impl NtpTransportAsync for PlainTransport {
+    type SendError = std::convert::Infallible;
+    type RecvError = std::convert::Infallible;
+
+    async fn send(&self, buffer: &[u8]) -> Result<(), Self::SendError> {
+        self.0.sent(buffer);
+        Ok(())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize, Self::RecvError> {
+        Ok(self.0.received(buffer).0)
+    }
+}
+
+#[test]
+fn test_query_prefers_transport_timestamps() {
+    let transport = TimestampingTransport::new(true);
+    let sample = barentp::sntp_query(&transport, &FROZEN).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(5));
+    assert_close(sample.delay, NtpDuration::from_millis(20));
+    assert_eq!(sample.time, START + NtpDuration::from_millis(21));
+}
+
+#[test]
+fn test_query_falls_back_to_clock() {
+    let transport = TimestampingTransport::new(false);
+    let sample = barentp::sntp_query(&transport, &FROZEN).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(16));
+    assert_eq!(sample.time, START);
+}
+
+#[test]
+fn test_query_async_prefers_transport_timestamps() {
+    let transport = TimestampingTransport::new(true);
+    let sample = block_on(barentp::nonblocking::sntp_query(&transport, &FROZEN)).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(5));
+    assert_close(sample.delay, NtpDuration::from_millis(20));
+
+    let transport = PlainTransport(TimestampingTransport::new(true));
+    let sample = block_on(barentp::nonblocking::sntp_query(&transport, &FROZEN)).unwrap();
+    assert_close(sample.offset, NtpDuration::from_millis(16));
+}